@@ -72,14 +72,22 @@ fn main() {
     // then in serialize and the intepetrer interpret that as do nothing.
     
     let mut expr = math::Expression::parse("cos(2.0i)", &math::Keyword::funcs(
-        &vec!["sin", "cos"]
+        &vec![("sin", 1), ("cos", 1)]
     ));
     
     println!("{}", expr.pretty_error());
     println!("{}", expr.tree.clone().unwrap());
     expr.optimize();
     println!("{}", expr.tree.clone().unwrap());
-    println!("{:#?}", expr.tree.clone().unwrap().serialize::<Variable, Function>());
+    println!("{}", expr.to_source());
+    let nodes = expr.tree.clone().unwrap().serialize::<Variable, Function>();
+    println!("{:#?}", nodes);
+    println!("{}", expr.to_dot());
+    println!("{}", math::nodes_to_dot(&nodes));
+    match math::check_nodes(&nodes) {
+        Ok(()) => println!("Serialized nodes are a valid tree."),
+        Err(error) => println!("Serialized nodes are invalid: {}", error),
+    }
 
 
     let mut intep = math::Intepreter::new();
@@ -98,7 +98,7 @@ impl Application {
             let mut w_shader = shader::Shader::create(&w_context).unwrap();
             w_shader.attach(shader::ShaderType::Vertex, include_str!("./shader/vert.glsl"));
             // Allwo fro runtime updating of sahders
-            w_shader.attach(shader::ShaderType::Fragment, include_str!("./shader/fractal.frag"));
+            w_shader.attach(shader::ShaderType::Fragment, &math::with_limits(include_str!("./shader/frag.glsl")));
             w_shader.link();
 
             
@@ -264,6 +264,7 @@ impl Application {
             let mut fractal_count = 1.0;
             let mut escape_radius = 2.0;
             let mut max_iters = 200;
+            let loop_limit = 256;
 
             let mut js = self.js.clone();
 
@@ -464,6 +465,24 @@ impl Application {
 
                 unsafe {
                     w_shader.uniform1i("nodes_count", global_nodes_count.as_ref().unwrap().get() as i32);
+                    GLOBAL_VIEW = (js.time_now() / 1000.0, (xzoom, yzoom), (xpos, ypos));
+                }
+                w_shader.uniform1i("loop_limit", loop_limit);
+                match unsafe { GLOBAL_NEWTON.as_ref() } {
+                    Some((newton, derivative)) => {
+                        let roots: Vec<f32> = newton.roots.iter()
+                            .flat_map(|root| vec![root.re as f32, root.im as f32])
+                            .collect();
+                        w_shader.uniform1i("render_mode", 1);
+                        w_shader.uniform1i("newton_derivative", *derivative as i32);
+                        w_shader.uniform1f("newton_relaxation", newton.settings.relaxation as f32);
+                        w_shader.uniform1f("newton_tolerance", newton.settings.tolerance as f32);
+                        if !roots.is_empty() {
+                            w_shader.uniform2fv("newton_roots", &roots);
+                        }
+                        w_shader.uniform1i("newton_roots_count", newton.roots.len() as i32);
+                    }
+                    None => w_shader.uniform1i("render_mode", 0),
                 }
 
                 if lock.vkey_down("KeyO") {
@@ -675,55 +694,126 @@ impl math::KeywordType for Function {
             "ceil" => 9,
             "floor" => 10,
             "rand" => 11,
+            "pow" => 12,
+            "logb" => 13,
+            "lerp" => 14,
+            "atan2" => 15,
+            "asin" => 16,
+            "acos" => 17,
+            "atan" => 18,
             _ => 0,
         }
     }
 }
 impl Function {
+    // The functions of the interpreter, and rand which only the shader can evaluate.
     fn keywords() -> Vec<math::Keyword> {
-        math::Keyword::funcs(&vec![
-            "sin",
-            "cos",
-            "tan",
-            "sqrt",
-            "log",
-            "exp",
-            "abs",
-            "arg",
-            "round",
-            "ceil",
-            "floor",
-            "rand",
-        ])
+        let mut functions = math::FUNCTIONS.to_vec();
+        functions.push(("rand", 1));
+        math::Keyword::funcs(&functions)
     }
 }
 
 
 static mut global_ubo: Option<std::sync::Arc<shader::UniformBufferObject>> = None;
 static mut global_nodes_count: Option<std::sync::Arc<std::cell::Cell<usize>>> = None;
+// Last parsed expression and the nodes in the UBO, so edits only update the nodes that changed.
+static mut GLOBAL_EXPRESSION: Option<math::Expression> = None;
+static mut GLOBAL_NODES: Option<math::Uploaded> = None;
+// Set by newton_rs with the index where the derivative starts in the UBO, None renders the expression itself.
+static mut GLOBAL_NEWTON: Option<(math::Newton, usize)> = None;
+// Time, zoom and view offset of the last frame, the shader's values of the variables that don't depend on the pixel.
+static mut GLOBAL_VIEW: (f64, (f64, f64), (f64, f64)) = (0.0, (1.0, 1.0), (0.0, 0.0));
 
 
+// Returns the parsed expression as versioned JSON, see Expression::to_json,
+// the diagnostics are included when the input is invalid.
 #[wasm_bindgen]
 pub fn eval_rs(input: JsValue) -> JsValue {
     let mut keywords = Variable::keywords();
     keywords.append(&mut Function::keywords());
-    let mut expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+    let input = input.as_string().unwrap();
+    let (parsed, change) = match unsafe { GLOBAL_EXPRESSION.take() } {
+        Some(previous) => previous.reparse(&math::Edit::between(&previous.input, &input), &keywords),
+        None => (math::Expression::parse(&input, &keywords), math::Change::Tree),
+    };
+    render(parsed, change)
+}
+// Renders an expression saved with Expression::to_json and returns it like eval_rs.
+// Returns an empty string if the JSON isn't a saved expression or was saved with another format version.
+#[wasm_bindgen]
+pub fn load_rs(json: JsValue) -> JsValue {
+    match math::Expression::from_json(&json.as_string().unwrap()) {
+        Ok(expression) => {
+            let result = render(expression, math::Change::Tree);
+            // The saved tree can be optimized already, so the next edit is parsed in full.
+            unsafe {
+                GLOBAL_EXPRESSION = None;
+            }
+            result
+        }
+        Err(error) => {
+            error!("{}", error);
+            JsValue::from_str("")
+        }
+    }
+}
+fn render(parsed: math::Expression, change: math::Change) -> JsValue {
+    unsafe {
+        GLOBAL_EXPRESSION = Some(parsed.clone());
+    }
+    let mut expr = parsed;
     expr.optimize();
+    // Keep rendering the last valid tree until the input parses again.
+    if !expr.is_valid() {
+        error!("{}", expr.pretty_error());
+        return JsValue::from_str(&expr.to_json());
+    }
     let tree = match &expr.tree {
         Some(tree) => tree,
-        None => {
-            match &expr.error {
-                Some(error) => error!("{}", expr.pretty_error()),
-                None => (),
+        None => return JsValue::from_str(&expr.to_json()),
+    };
+    if let Some(original) = unsafe { GLOBAL_EXPRESSION.as_ref() }.and_then(|original| original.tree.as_ref()) {
+        if let Err(error) = math::check_optimized(original, tree, 4) {
+            error!("Optimizing changed the value: {}", error);
+        }
+    }
+
+    // Simplifying can still change the structure, a coefficient that becomes 0 drops its term,
+    // so the update checks it against the old nodes.
+    if let (true, Some(uploaded)) = (change != math::Change::Tree, unsafe { GLOBAL_NODES.as_mut() }) {
+        if let Some(changed) = uploaded.update_values::<Variable, Function>(tree) {
+            info!("{:?}, nodes changed: {:?}", change, changed);
+            let ubo = unsafe { global_ubo.as_ref().unwrap() };
+            for index in changed {
+                ubo.set_element(index, &uploaded.nodes[index]);
             }
-            return JsValue::from_str("");
+            return JsValue::from_str(&expr.to_json());
         }
-    };
-    let mut nodes = expr.serialize::<Variable, Function>();
+    }
+
+    let nodes = expr.serialize::<Variable, Function>();
     info!("{:#?}", nodes);
+    if let Err(diagnostic) = expr.check_node_count(nodes.len()) {
+        error!("{}", diagnostic.pretty(&expr.input));
+        expr.diagnostics.push(diagnostic);
+        return JsValue::from_str(&expr.to_json());
+    }
+    if let Err(error) = math::check_nodes(&nodes) {
+        error!("Serialized nodes are not a valid tree: {}\n{}", error, math::nodes_to_dot(&nodes));
+    }
     
     unsafe {
-        global_ubo.as_ref().unwrap().set_data(&nodes);
+        let ubo = global_ubo.as_ref().unwrap();
+        // A tree of the same size often keeps most of its nodes at the same index.
+        match GLOBAL_NODES.as_ref().and_then(|uploaded| uploaded.changed(&nodes)) {
+            Some(changed) => {
+                for index in changed {
+                    ubo.set_element(index, &nodes[index]);
+                }
+            }
+            None => ubo.set_data(&nodes),
+        }
         match global_nodes_count {
             Some(_) => (),
             None => {
@@ -731,9 +821,163 @@ pub fn eval_rs(input: JsValue) -> JsValue {
             }
         }
         global_nodes_count.as_ref().unwrap().set(nodes.len());
+        GLOBAL_NODES = Some(math::Uploaded::new(nodes));
+        GLOBAL_NEWTON = None;
     }
 
-    JsValue::from_str(&tree.to_string())
+    JsValue::from_str(&expr.to_json())
+}
+// Renders Newton's method for the input as p(z), returns the derivative as versioned JSON,
+// with the diagnostics when the input is invalid or has no derivative.
+#[wasm_bindgen]
+pub fn newton_rs(input: JsValue, relaxation: f64, tolerance: f64) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let mut expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+
+    let interpreter = shader_interpreter();
+    let settings = math::NewtonSettings {
+        relaxation,
+        tolerance,
+        ..math::NewtonSettings::default()
+    };
+    let newton = math::Newton::new(&expr, interpreter, settings);
+    if !newton.is_valid() {
+        error!("{}", newton.derivative.pretty_error());
+        return JsValue::from_str(&newton.derivative.to_json());
+    }
+    info!("Roots: {:?}", newton.roots);
+
+    let (nodes, derivative) = newton.serialize::<Variable, Function>();
+    // The function and its derivative share the buffer, like the other errors it's reported on the input.
+    if let Err(diagnostic) = expr.check_node_count(nodes.len()) {
+        error!("{}", diagnostic.pretty(&expr.input));
+        expr.diagnostics.push(diagnostic);
+        return JsValue::from_str(&expr.to_json());
+    }
+    if let Err(error) = math::check_forest(&nodes, &[0, derivative]) {
+        error!("Serialized nodes are not valid trees: {}\n{}", error, math::nodes_to_dot(&nodes));
+    }
+    let json = newton.derivative.to_json();
+    unsafe {
+        global_ubo.as_ref().unwrap().set_data(&nodes);
+        global_nodes_count.as_ref().unwrap().set(nodes.len());
+        // The UBO no longer holds the nodes of the last expression, the next eval_rs uploads them again.
+        GLOBAL_EXPRESSION = None;
+        GLOBAL_NODES = None;
+        GLOBAL_NEWTON = Some((newton, derivative));
+    }
+    JsValue::from_str(&json)
+}
+// Where Newton's method ends up from the point, as JSON with the index of the root and the number of steps.
+// Empty if newton_rs didn't render anything yet.
+#[wasm_bindgen]
+pub fn newton_point_rs(re: f64, im: f64) -> JsValue {
+    match unsafe { GLOBAL_NEWTON.as_ref() } {
+        Some((newton, _)) => {
+            let point = newton.iterate(math::Complex::new(re, im));
+            JsValue::from_str(&serde_json::to_string(&point).unwrap_or_default())
+        }
+        None => JsValue::from_str(""),
+    }
+}
+// Values the shader gives the variables in the last frame, so roots are found for the function that is drawn.
+// The ones that change with the pixel or the point of the iteration are 0, except z which Newton's method sets.
+fn shader_interpreter() -> math::Intepreter {
+    let (time, (zoom_x, zoom_y), (offset_x, offset_y)) = unsafe { GLOBAL_VIEW };
+    let mut interpreter = math::Intepreter::new();
+    let values = [
+        ("time", time, 0.0),
+        ("zoom", zoom_x, zoom_y), ("zoomx", zoom_x, 0.0), ("zoomy", 0.0, zoom_y),
+        ("offset", offset_x, offset_y), ("offsetx", offset_x, 0.0), ("offsety", 0.0, offset_y),
+        ("zr", 0.0, 0.0), ("zi", 0.0, 0.0),
+        ("uv", 0.0, 0.0), ("uvx", 0.0, 0.0), ("uvy", 0.0, 0.0),
+        ("e", std::f64::consts::E, 0.0),
+        ("pi", std::f64::consts::PI, 0.0),
+        ("phi", (1.0 + 5.0_f64.sqrt()) / 2.0, 0.0),
+    ];
+    for &(name, re, im) in values.iter() {
+        interpreter.set_variable(name, math::Complex::new(re, im));
+    }
+    interpreter
+}
+// The input printed again with as few parentheses as it needs, empty if it's invalid.
+#[wasm_bindgen]
+pub fn source_rs(input: JsValue) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+    match expr.is_valid() {
+        true => JsValue::from_str(&expr.to_source()),
+        false => JsValue::from_str(""),
+    }
+}
+#[wasm_bindgen]
+pub fn latex_rs(input: JsValue) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+    match expr.is_valid() {
+        true => JsValue::from_str(&expr.to_latex()),
+        false => JsValue::from_str(""),
+    }
+}
+// Derivative of the input with respect to z, or the errors explaining why there is none.
+#[wasm_bindgen]
+pub fn derivative_rs(input: JsValue) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+    JsValue::from_str(&expr.derivative_source())
+}
+#[wasm_bindgen]
+pub fn mathml_rs(input: JsValue) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+    match expr.is_valid() {
+        true => JsValue::from_str(&expr.to_mathml()),
+        false => JsValue::from_str(""),
+    }
+}
+// Graphviz source of the tree eval_rs renders for the input, for finding out whether parsing or serializing went wrong.
+#[wasm_bindgen]
+pub fn dot_rs(input: JsValue) -> JsValue {
+    JsValue::from_str(&optimized(input).to_dot())
+}
+// Nodes eval_rs uploads for the input, as Graphviz source or as JSON.
+#[wasm_bindgen]
+pub fn nodes_dot_rs(input: JsValue) -> JsValue {
+    let nodes = optimized(input).serialize::<Variable, Function>();
+    JsValue::from_str(&math::nodes_to_dot(&nodes))
+}
+#[wasm_bindgen]
+pub fn nodes_json_rs(input: JsValue) -> JsValue {
+    let nodes = optimized(input).serialize::<Variable, Function>();
+    JsValue::from_str(&math::nodes_to_json(&nodes))
+}
+fn optimized(input: JsValue) -> math::Expression {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let mut expr = math::Expression::parse(&input.as_string().unwrap(), &keywords);
+    expr.optimize();
+    expr
+}
+// Classes of the tokens as JSON, see math::highlight.
+#[wasm_bindgen]
+pub fn highlight_rs(input: JsValue) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let highlights = math::highlight(&input.as_string().unwrap(), &keywords);
+    JsValue::from_str(&serde_json::to_string(&highlights).unwrap_or_default())
+}
+// Completions for the identifier in front of the cursor as JSON, the cursor is a byte offset into the input.
+#[wasm_bindgen]
+pub fn complete_rs(input: JsValue, cursor: usize) -> JsValue {
+    let mut keywords = Variable::keywords();
+    keywords.append(&mut Function::keywords());
+    let completions = math::complete(&input.as_string().unwrap(), cursor, &keywords);
+    JsValue::from_str(&serde_json::to_string(&completions).unwrap_or_default())
 }
 /*
 #[wasm_bindgen]
@@ -0,0 +1,175 @@
+use serde::{ Serialize, Deserialize };
+use super::definition::Definition;
+use super::diagnostic::Span;
+use super::keyword::Keyword;
+use super::parser::{ Expression, Token };
+
+
+
+// Built in functions with parameters that have a meaning, the others are named after their arity.
+const PARAMETERS: [(&str, &str); 4] = [
+    ("pow", "z, w"),
+    ("logb", "z, base"),
+    ("lerp", "a, b, t"),
+    ("atan2", "y, x"),
+];
+// Reserved words that are called like functions, with their arity, signature and help.
+const RESERVED: [(&str, usize, &str, &str); 4] = [
+    ("sum", 4, "sum(k, a, b, expr)", "Sum of expr for k from a to b."),
+    ("prod", 4, "prod(k, a, b, expr)", "Product of expr for k from a to b."),
+    ("iter", 3, "iter(n, w = z0, expr)", "Sets w to expr n times, starting at z0. iter(n, z0, expr) sets z."),
+    ("if", 3, "if(condition, a, b)",
+        "a where the condition holds, b elsewhere. <, >, <= and >= compare real parts, \
+        use |z| < 1 to compare moduli. == and != compare both parts."),
+];
+
+
+
+// Class of a token for syntax highlighting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenClass {
+    Number,
+    Imaginary,
+    Variable,
+    Function,
+    Operator,
+    Delimiter,
+    Invalid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Highlight {
+    pub span: Span,
+    pub class: TokenClass,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Completion {
+    pub name: String,
+    // Variable or Function.
+    pub class: TokenClass,
+    // Zero for variables.
+    pub arity: usize,
+    // Like "pow(z, w)" or "f(w)" for a user function.
+    pub signature: String,
+    // Explains a reserved word, empty for the others.
+    pub help: String,
+    // Part of the input the completion replaces.
+    pub span: Span,
+}
+
+
+
+impl Token {
+    pub fn class(&self) -> TokenClass {
+        match self {
+            Self::Real(..) => TokenClass::Number,
+            Self::Imaginary(..) => TokenClass::Imaginary,
+            Self::Variable(_) => TokenClass::Variable,
+            // Reserved words that take arguments in parentheses are highlighted like functions.
+            Self::Function(..) | Self::Sum | Self::Product | Self::Iterate | Self::If => TokenClass::Function,
+            Self::Invalid(_) => TokenClass::Invalid,
+
+            Self::Comma | Self::Semicolon => TokenClass::Delimiter,
+            Self::ParenthesisOpen | Self::ParenthesisClosed => TokenClass::Delimiter,
+            Self::AbsoluteOpen | Self::AbsoluteClosed | Self::NormOpen | Self::NormClosed => TokenClass::Delimiter,
+            Self::BracketOpen | Self::BracketClosed | Self::BraceOpen | Self::BraceClosed => TokenClass::Delimiter,
+            Self::FloorOpen | Self::FloorClosed | Self::CeilOpen | Self::CeilClosed => TokenClass::Delimiter,
+
+            _ => TokenClass::Operator,
+        }
+    }
+}
+
+
+
+// Classes of the tokens of the input, names the input defines or binds are known,
+// like they are when the input is parsed.
+pub fn highlight(input: &str, keywords: &[Keyword]) -> Vec<Highlight> {
+    let tokens = Token::parse(input, keywords);
+    let (defined, parameters) = Definition::scan(&tokens, input);
+    let bindings = Token::bindings(&tokens, input);
+    let known = Expression::known(keywords, &defined, &parameters, &bindings);
+
+    Token::parse(input, &known).iter()
+        .map(|lexeme| Highlight {
+            span: lexeme.span,
+            class: lexeme.token.class(),
+        })
+        .collect()
+}
+
+// Variables and functions that start with the identifier in front of the cursor, sorted by name.
+// The cursor is a byte offset, without an identifier in front of it every name matches.
+pub fn complete(input: &str, cursor: usize, keywords: &Vec<Keyword>) -> Vec<Completion> {
+    let mut cursor = cursor.min(input.len());
+    while !input.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    let span = Span::new(identifier_start(&input[..cursor]), cursor);
+    let prefix = Keyword::normalize(&input[span.start..span.end]);
+
+    // User functions have the names of their parameters.
+    let expression = Expression::parse(input, keywords);
+    let tokens = Token::parse(input, keywords);
+    let (defined, parameters) = Definition::scan(&tokens, input);
+    let bindings = Token::bindings(&tokens, input);
+    let known = Expression::known(keywords, &defined, &parameters, &bindings);
+
+    let mut completions: Vec<Completion> = known.iter().map(|keyword| {
+        let (class, arity, signature) = match keyword {
+            Keyword::Variable(name) => (TokenClass::Variable, 0, name.to_string()),
+            Keyword::Function(name, arity) => {
+                let parameters = match expression.definitions.iter().find(|definition| definition.name() == name) {
+                    Some(definition) => definition.parameters.join(", "),
+                    None => signature_parameters(name, *arity),
+                };
+                (TokenClass::Function, *arity, format!("{}({})", name, parameters))
+            }
+        };
+        Completion {
+            name: keyword.name().to_string(),
+            class,
+            arity,
+            signature,
+            help: String::new(),
+            span,
+        }
+    }).collect();
+    completions.extend(RESERVED.iter().map(|&(name, arity, signature, help)| Completion {
+        name: name.to_string(),
+        class: TokenClass::Function,
+        arity,
+        signature: signature.to_string(),
+        help: help.to_string(),
+        span,
+    }));
+
+    completions.retain(|completion| Keyword::normalize(&completion.name).starts_with(&prefix));
+    completions.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+    completions.dedup_by(|lhs, rhs| lhs.name == rhs.name);
+    completions
+}
+// Start of the identifier that ends at the end of the input, identifiers start with a letter.
+fn identifier_start(input: &str) -> usize {
+    let mut start = input.len();
+    for (position, c) in input.char_indices().rev() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '₀'..='₉' => start = position,
+            _ => break,
+        }
+    }
+    // "2si" completes "si", the number in front is a separate token.
+    match input[start..].find(|c: char| c.is_ascii_alphabetic()) {
+        Some(offset) => start + offset,
+        None => input.len(),
+    }
+}
+fn signature_parameters(name: &str, arity: usize) -> String {
+    match PARAMETERS.iter().find(|(function, _)| *function == name) {
+        Some((_, parameters)) => parameters.to_string(),
+        None if arity == 1 => "z".to_string(),
+        None => (1..=arity).map(|index| format!("z{}", index)).collect::<Vec<String>>().join(", "),
+    }
+}
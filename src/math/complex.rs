@@ -89,6 +89,24 @@ impl Complex {
             -self.im,
         )
     }
+    pub fn round(self) -> Self {
+        Self::new(
+            self.re.round(),
+            self.im.round(),
+        )
+    }
+    pub fn ceil(self) -> Self {
+        Self::new(
+            self.re.ceil(),
+            self.im.ceil(),
+        )
+    }
+    pub fn floor(self) -> Self {
+        Self::new(
+            self.re.floor(),
+            self.im.floor(),
+        )
+    }
 
     pub fn sqrt(self) -> Self {
         if self.is_real() {
@@ -144,6 +162,34 @@ impl Complex {
             a * b.sin(),
         )
     }
+
+    // Lanczos approximation with g = 7, the left half plane uses the reflection formula.
+    pub fn gamma(self) -> Self {
+        const G: f64 = 7.0;
+        const COEFFICIENTS: [f64; 9] = [
+            0.9999999999998099,
+            676.5203681218851,
+            -1259.1392167224028,
+            771.3234287776531,
+            -176.6150291621406,
+            12.507343278686905,
+            -0.13857109526572012,
+            9.984369578019572e-6,
+            1.5056327351493116e-7,
+        ];
+        if self.re < 0.5 {
+            let pi = Self::real(std::f64::consts::PI);
+            return pi / ((pi * self).sin() * (Self::real(1.0) - self).gamma());
+        }
+
+        let z = self - Self::real(1.0);
+        let mut x = Self::real(COEFFICIENTS[0]);
+        for (i, &coefficient) in COEFFICIENTS.iter().enumerate().skip(1) {
+            x = x + Self::real(coefficient) / (z + Self::real(i as f64));
+        }
+        let t = z + Self::real(G + 0.5);
+        Self::real((2.0 * std::f64::consts::PI).sqrt()) * t.pow(z + Self::real(0.5)) * (-t).exp() * x
+    }
 }
 
 // Trigonometry
@@ -223,6 +269,17 @@ impl Complex {
         )
     }
 
+    // Angle of the point (x, self), matches the real atan2 for real arguments.
+    pub fn atan2(self, x: Self) -> Self {
+        // -i * log((x + iy) / sqrt(x^2 + y^2))
+        let a = (x + Self::imaginary(1.0) * self) / (x * x + self * self).sqrt();
+        let b = a.log();
+        Self::new(
+            b.im,
+            -b.re,
+        )
+    }
+
     pub fn cot(self) -> Self {
         let re = 2.0 * self.re;
         let im = 2.0 * self.im;
@@ -381,7 +438,9 @@ impl std::fmt::Display for Complex {
             else if self.im == -1.0 { format!("-i") }
             else { format!("{:.4}i", self.im) }
         };
-        let operator = if self.im > 0.0 { 
+        let operator = if imaginary.is_empty() {
+            ""
+        } else if self.im > 0.0 { 
             "+"
         } else if self.im < 0.0 {
             imaginary = format!("{}", &imaginary[1..]);
@@ -413,3 +472,18 @@ impl Complex {
         format!("{:.4} * e^{:.4}°i", radius, theta)
     }
 }
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::Complex;
+
+    #[test]
+    fn display_without_imaginary_part() {
+        assert_eq!(format!("{}", Complex::new(3.0, 0.0)), "3.0000");
+        assert_eq!(format!("{}", Complex::new(3.0, 1e-310)), "3.0000");
+        assert_eq!(format!("{}", Complex::new(3.0, -1e-310)), "3.0000");
+        assert_eq!(format!("{}", Complex::new(1.0, -2.0)), "1.0000 - 2.0000i");
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use super::parser::{ Node, Syntax };
+
+
+
+// Tree with equal subtrees stored once, indexed like the serialized nodes.
+// Parents come before their children, so going from the back reaches every child before its parents.
+// Loops aren't shared, their body has to be evaluated again for every iteration,
+// so a loop and everything in it is stored in pre-order like a tree.
+pub struct Dag<'a> {
+    // Node at every index, for a shared index the first of the equal subtrees.
+    pub nodes: Vec<&'a Node>,
+    // Indices of the serialized children, 0 if there is none.
+    pub children: Vec<(usize, usize)>,
+    // Index of every node of the tree, equal subtrees have the same one.
+    indices: HashMap<*const Node, usize>,
+}
+
+// Distinct subtrees found while hash consing, a loop is a single one as large as its whole subtree.
+struct Class<'a> {
+    node: &'a Node,
+    left: Option<usize>,
+    right: Option<usize>,
+    size: usize,
+}
+#[derive(Default)]
+struct Classes<'a> {
+    classes: Vec<Class<'a>>,
+    keys: HashMap<(String, Option<usize>, Option<usize>), usize>,
+    members: Vec<(*const Node, usize)>,
+}
+impl<'a> Classes<'a> {
+    fn class(&mut self, node: &'a Node) -> usize {
+        let class = match node.binding() {
+            Some(_) => self.add(Class { node, left: None, right: None, size: tree_size(node) }),
+            None => {
+                let (left, right) = node.serialized_children();
+                let left = left.map(|left| self.class(left));
+                let right = right.map(|right| self.class(right));
+                let key = (syntax_key(&node.syntax), left, right);
+                match self.keys.get(&key) {
+                    Some(&class) => class,
+                    None => {
+                        let class = self.add(Class { node, left, right, size: 1 });
+                        self.keys.insert(key, class);
+                        class
+                    }
+                }
+            }
+        };
+        self.members.push((node as *const Node, class));
+        class
+    }
+    fn add(&mut self, class: Class<'a>) -> usize {
+        self.classes.push(class);
+        self.classes.len() - 1
+    }
+    // Reversed, the post-order is a topological order. Visiting the right child first
+    // keeps the pre-order for trees without equal subtrees.
+    fn post_order(&self, class: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+        if visited[class] {
+            return;
+        }
+        visited[class] = true;
+        let Class { right, left, .. } = self.classes[class];
+        for child in [right, left].iter().filter_map(|child| *child) {
+            self.post_order(child, visited, order);
+        }
+        order.push(class);
+    }
+}
+
+// The literal only changes how a number is printed.
+fn syntax_key(syntax: &Syntax) -> String {
+    match syntax {
+        Syntax::Real(value, _) => format!("Real {:x}", value.to_bits()),
+        Syntax::Imaginary(value, _) => format!("Imaginary {:x}", value.to_bits()),
+        syntax => format!("{:?}", syntax),
+    }
+}
+fn tree_size(node: &Node) -> usize {
+    let (left, right) = node.serialized_children();
+    1 + left.map_or(0, tree_size) + right.map_or(0, tree_size)
+}
+
+impl<'a> Dag<'a> {
+    pub fn new(root: &'a Node) -> Self {
+        let mut classes = Classes::default();
+        let root_class = classes.class(root);
+        let mut order = Vec::new();
+        classes.post_order(root_class, &mut vec![false; classes.classes.len()], &mut order);
+        order.reverse();
+
+        let mut positions = vec![0; classes.classes.len()];
+        let mut position = 0;
+        for &class in &order {
+            positions[class] = position;
+            position += classes.classes[class].size;
+        }
+
+        let mut dag = Self {
+            nodes: Vec::new(),
+            children: Vec::new(),
+            indices: HashMap::new(),
+        };
+        for &class in &order {
+            let Class { node, left, right, .. } = classes.classes[class];
+            if node.binding().is_some() {
+                dag.push_tree(node);
+                continue;
+            }
+            let index = |child: Option<usize>| child.map_or(0, |child| positions[child]);
+            dag.nodes.push(node);
+            dag.children.push((index(left), index(right)));
+        }
+        for (node, class) in classes.members {
+            dag.indices.entry(node).or_insert(positions[class]);
+        }
+        dag
+    }
+    fn push_tree(&mut self, node: &'a Node) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(node);
+        self.children.push((0, 0));
+        self.indices.insert(node as *const Node, index);
+        let (left, right) = node.serialized_children();
+        let left = left.map_or(0, |left| self.push_tree(left));
+        let right = right.map_or(0, |right| self.push_tree(right));
+        self.children[index] = (left, right);
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn index_of(&self, node: &Node) -> Option<usize> {
+        self.indices.get(&(node as *const Node)).copied()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ tree_size, Dag };
+    use super::super::dot::check_nodes;
+    use super::super::serializer::Empty;
+    use super::super::testing::{ random_repeated, Xorshift };
+
+    fn same(lhs: f64, rhs: f64) -> bool {
+        lhs == rhs || (lhs.is_nan() && rhs.is_nan())
+    }
+
+    // Evaluating the shared subtrees once gives exactly the value of the tree,
+    // and the serialized nodes are valid and never more than the nodes of the tree.
+    #[test]
+    fn shared_subtrees_keep_values() {
+        let mut random = Xorshift(0x2545f491);
+        for _ in 0..1000 {
+            let tree = random_repeated(&mut random);
+            let dag = Dag::new(&tree);
+            let nodes = tree.serialize::<Empty, Empty>();
+            assert!(
+                nodes.len() == dag.len() && nodes.len() <= tree_size(&tree),
+                "'{}' has {} nodes as a tree, {} shared and {} serialized",
+                tree.to_source(), tree_size(&tree), dag.len(), nodes.len(),
+            );
+            if let Err(error) = check_nodes(&nodes) {
+                panic!("'{}' is serialized wrong: {}", tree.to_source(), error);
+            }
+
+            let interpreter = random.interpreter();
+            let (expected, found) = (interpreter.evaluate_node(&tree), interpreter.evaluate_dag(&dag));
+            assert!(
+                same(expected.re, found.re) && same(expected.im, found.im),
+                "'{}' is {}, but {} with shared subtrees", tree.to_source(), expected, found,
+            );
+        }
+    }
+}
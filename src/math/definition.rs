@@ -0,0 +1,217 @@
+use serde::{ Serialize, Deserialize };
+use super::diagnostic::Span;
+use super::keyword::Keyword;
+use super::parser::{ Lexeme, Node, Syntax, Token, TokenError };
+
+
+
+// User definition like "c = 0.28 + 0.01i" or "f(w) = w^2 + c".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Definition {
+    pub keyword: Keyword,
+    // Empty for variables.
+    pub parameters: Vec<String>,
+    pub body: Node,
+    // Covers the whole statement.
+    pub span: Span,
+}
+impl Definition {
+    pub fn new(keyword: Keyword, parameters: Vec<String>, body: Node, span: Span) -> Self {
+        Self {
+            keyword,
+            parameters,
+            body,
+            span,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.keyword.name()
+    }
+    pub fn is_function(&self) -> bool {
+        match self.keyword {
+            Keyword::Function(..) => true,
+            Keyword::Variable(_) => false,
+        }
+    }
+    // Body with all other definitions inlined, the parameters are left in place.
+    // Fails with the name of the first definition that refers to itself.
+    pub fn resolve(&self, definitions: &Vec<Definition>) -> Result<Node, String> {
+        self.body.inline_next(definitions, &self.parameters, &mut vec![format!("{}", self.name())])
+    }
+
+
+    // Finds the headers of all definitions before parsing, so the names can be lexed as keywords.
+    // Returns the defined keywords with the span of their name, and the parameter names.
+    pub fn scan(tokens: &[Lexeme], input: &str) -> (Vec<(Keyword, Span)>, Vec<String>) {
+        let mut keywords = Vec::new();
+        let mut parameters = Vec::new();
+
+        let name = |index: usize| Self::identifier(tokens, input, index);
+        let token = |index: usize| tokens.get(index).map(|lexeme| &lexeme.token);
+
+        let mut start = 0;
+        while start < tokens.len() {
+            if let Some((keyword, after)) = name(start) {
+                let span = tokens[start].span.join(tokens[after - 1].span);
+                match token(after) {
+                    Some(Token::Assignment) => {
+                        keywords.push((Keyword::Variable(keyword), span));
+                    }
+                    Some(Token::ParenthesisOpen) => {
+                        // Parameters are names separated by commas.
+                        let mut names = Vec::new();
+                        let mut index = after + 1;
+                        while let Some((parameter, after)) = name(index) {
+                            names.push(parameter);
+                            index = after;
+                            match token(index) {
+                                Some(Token::Comma) => index += 1,
+                                _ => break,
+                            }
+                        }
+                        if token(index) == Some(&Token::ParenthesisClosed) && token(index + 1) == Some(&Token::Assignment) {
+                            keywords.push((Keyword::Function(keyword, names.len()), span));
+                            parameters.append(&mut names);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            // Continue after the next semicolon.
+            while start < tokens.len() && tokens[start].token != Token::Semicolon {
+                start += 1;
+            }
+            start += 1;
+        }
+        (keywords, parameters)
+    }
+    // The lexer splits unknown names into known ones, so "zc = 1" is lexed as "z c = 1".
+    // Adjacent parts of an identifier are joined back together, returns the index after the name.
+    pub fn identifier(tokens: &[Lexeme], input: &str, index: usize) -> Option<(String, usize)> {
+        let mut next = index;
+        while let Some(lexeme) = tokens.get(next) {
+            let text = input.get(lexeme.span.start..lexeme.span.end).unwrap_or("");
+            let identifier = match &lexeme.token {
+                Token::Variable(_) | Token::Function(..) | Token::Invalid(TokenError::InvalidKeyword) => true,
+                Token::Invalid(TokenError::DuplicateKeyword) => true,
+                Token::Imaginary(..) => text == "i",
+                _ => false,
+            };
+            let adjacent = next == index || tokens[next - 1].span.end == lexeme.span.start;
+            if !identifier || !adjacent {
+                break;
+            }
+            next += 1;
+        }
+        match next > index {
+            true => {
+                let span = tokens[index].span.join(tokens[next - 1].span);
+                input.get(span.start..span.end).map(|name| (Keyword::normalize(name), next))
+            }
+            false => None,
+        }
+    }
+}
+
+
+
+impl Node {
+    // Replaces every reference to a definition with its body.
+    // Fails with the name of the first definition that refers to itself.
+    pub fn inline(&self, definitions: &Vec<Definition>) -> Result<Self, String> {
+        self.inline_next(definitions, &Vec::new(), &mut Vec::new())
+    }
+    // Bound names are parameters of the function currently being inlined and names bound by loops,
+    // they hide definitions with the same name.
+    fn inline_next(&self, definitions: &Vec<Definition>, bound: &Vec<String>, stack: &mut Vec<String>) -> Result<Self, String> {
+        if let Some((name, bounds, body)) = self.scoped() {
+            let mut arguments = bounds.iter()
+                .map(|argument| argument.inline_next(definitions, bound, stack))
+                .collect::<Result<Vec<Node>, String>>()?;
+            let mut inner = bound.clone();
+            inner.push(name.to_string());
+            arguments.push(body.inline_next(definitions, &inner, stack)?);
+            return Ok(self.with_arguments(arguments));
+        }
+        let definition = match &self.syntax {
+            Syntax::Variable(name) if !bound.contains(name) => {
+                definitions.iter().find(|definition| !definition.is_function() && definition.name() == name)
+            }
+            Syntax::Function(name) => {
+                definitions.iter().find(|definition| definition.is_function() && definition.name() == name)
+            }
+            _ => None,
+        };
+
+        match definition {
+            Some(definition) => {
+                let name = definition.name().to_string();
+                if stack.contains(&name) {
+                    return Err(name);
+                }
+                let arguments = self.arguments().iter()
+                    .map(|argument| argument.inline_next(definitions, bound, stack))
+                    .collect::<Result<Vec<Node>, String>>()?;
+
+                stack.push(name);
+                let body = definition.body.inline_next(definitions, &definition.parameters, stack)?;
+                stack.pop();
+                Ok(body.substitute(&definition.parameters, &arguments))
+            }
+            None => {
+                let mut node = self.clone();
+                node.left = match &self.left {
+                    Some(left) => Some(Box::new(left.inline_next(definitions, bound, stack)?)),
+                    None => None,
+                };
+                node.right = match &self.right {
+                    Some(right) => Some(Box::new(right.inline_next(definitions, bound, stack)?)),
+                    None => None,
+                };
+                Ok(node)
+            }
+        }
+    }
+
+    // Replaces the variables in names with the node at the same position in values.
+    // Names bound by a loop are left alone in its body.
+    pub fn substitute(&self, names: &Vec<String>, values: &Vec<Node>) -> Self {
+        if let Some((name, bounds, body)) = self.scoped() {
+            let mut arguments: Vec<Node> = bounds.iter().map(|argument| argument.substitute(names, values)).collect();
+            let (inner_names, inner_values) = names.iter().zip(values)
+                .filter(|(other, _)| *other != name)
+                .map(|(other, value)| (other.to_string(), value.clone()))
+                .unzip();
+            arguments.push(body.substitute(&inner_names, &inner_values));
+            return self.with_arguments(arguments);
+        }
+        if let Syntax::Variable(name) = &self.syntax {
+            if let Some(position) = names.iter().position(|other| other == name) {
+                if let Some(value) = values.get(position) {
+                    return value.clone();
+                }
+            }
+        }
+        let mut node = self.clone();
+        node.left = self.left.as_ref().map(|left| Box::new(left.substitute(names, values)));
+        node.right = self.right.as_ref().map(|right| Box::new(right.substitute(names, values)));
+        node
+    }
+
+    // First variable of names that is used in the tree, outside of loops that bind it.
+    pub fn uses(&self, names: &Vec<String>) -> Option<String> {
+        if let Some((name, bounds, body)) = self.scoped() {
+            let inner = names.iter().filter(|other| *other != name).cloned().collect();
+            return bounds.iter().find_map(|argument| argument.uses(names)).or_else(|| body.uses(&inner));
+        }
+        if let Syntax::Variable(name) = &self.syntax {
+            if names.contains(name) {
+                return Some(name.to_string());
+            }
+        }
+        let left = self.left.as_ref().and_then(|left| left.uses(names));
+        left.or_else(|| self.right.as_ref().and_then(|right| right.uses(names)))
+    }
+}
@@ -0,0 +1,317 @@
+use super::diagnostic::Diagnostic;
+use super::parser::{ Expression, Literal, Node, Syntax };
+use super::scope::LOOP_LIMIT;
+
+
+
+// Iterations are unrolled before they are differentiated, larger ones fail instead of blowing up the tree.
+const UNROLL_LIMIT: usize = 256;
+const HOLOMORPHIC: &str = "Only holomorphic functions have a complex derivative";
+
+fn number(value: f64) -> Node {
+    Node::from_syntax(Syntax::Real(value, Literal::Decimal))
+}
+fn add(lhs: Node, rhs: Node) -> Node {
+    Node::from(lhs, rhs, Syntax::Addition)
+}
+fn subtract(lhs: Node, rhs: Node) -> Node {
+    Node::from(lhs, rhs, Syntax::Subtraction)
+}
+fn multiply(lhs: Node, rhs: Node) -> Node {
+    Node::from(lhs, rhs, Syntax::Multiplication)
+}
+fn divide(lhs: Node, rhs: Node) -> Node {
+    Node::from(lhs, rhs, Syntax::Division)
+}
+fn power(base: Node, exponent: Node) -> Node {
+    Node::from(base, exponent, Syntax::Exponent)
+}
+fn negate(node: Node) -> Node {
+    subtract(number(0.0), node)
+}
+fn call(name: &str, arguments: Vec<Node>) -> Node {
+    Node::chain(arguments, Syntax::Function(name.to_string()))
+}
+
+
+
+impl Expression {
+    // Derivative with respect to the variable, simplified. Fails with a diagnostic at the part of the input
+    // that has no complex derivative, like |z|, or that can't be written as a tree, like iter with a variable count.
+    pub fn derivative(&self, variable: &str) -> Expression {
+        let tree = match &self.tree {
+            Some(tree) if self.errors().next().is_none() => tree,
+            _ => return self.clone(),
+        };
+        // "z - z" is 0 once simplified, its derivative doesn't have to go through prod(k, 1, 2, z - z).
+        match tree.simplify().derivative(variable) {
+            Ok(derivative) => {
+                let derivative = derivative.simplify();
+                Expression {
+                    input: derivative.to_source(),
+                    tree: Some(derivative),
+                    definitions: Vec::new(),
+                    diagnostics: Vec::new(),
+                }
+            }
+            Err(diagnostic) => Expression {
+                input: self.input.to_string(),
+                tree: None,
+                definitions: self.definitions.clone(),
+                diagnostics: vec![diagnostic],
+            },
+        }
+    }
+    // Derivative with respect to z as source, or why there is none.
+    pub fn derivative_source(&self) -> String {
+        let derivative = self.derivative("z");
+        match derivative.is_valid() {
+            true => derivative.to_source(),
+            false => derivative.pretty_error(),
+        }
+    }
+}
+
+
+
+impl Node {
+    // Derivative before simplifying, definitions have to be inlined already.
+    pub fn derivative(&self, variable: &str) -> Result<Node, Diagnostic> {
+        if !self.depends_on(variable) {
+            return Ok(number(0.0));
+        }
+        let d = |node: &Node| node.derivative(variable);
+        let (left, right) = (self.left.as_deref(), self.right.as_deref());
+
+        match (&self.syntax, left, right) {
+            // Numbers and other variables don't depend on it.
+            (Syntax::Variable(_), _, _) => Ok(number(1.0)),
+            (Syntax::Parenthesis, Some(u), _) | (Syntax::Bracket, Some(u), _) | (Syntax::Brace, Some(u), _) => d(u),
+
+            (Syntax::Addition, Some(u), Some(v)) => Ok(add(d(u)?, d(v)?)),
+            (Syntax::Subtraction, Some(u), Some(v)) => Ok(subtract(d(u)?, d(v)?)),
+            // (uv)' = u'v + uv'
+            (Syntax::Multiplication, Some(u), Some(v)) => Ok(add(multiply(d(u)?, v.clone()), multiply(u.clone(), d(v)?))),
+            // (u/v)' = (u'v - uv') / v^2
+            (Syntax::Division, Some(u), Some(v)) => Ok(divide(
+                subtract(multiply(d(u)?, v.clone()), multiply(u.clone(), d(v)?)),
+                power(v.clone(), number(2.0)),
+            )),
+            (Syntax::Exponent, Some(u), Some(v)) => Self::power_derivative(u, v, variable),
+
+            // Steps and conditions are constant almost everywhere.
+            (Syntax::Floor, _, _) | (Syntax::Ceil, _, _) => Ok(number(0.0)),
+            (Syntax::Less, _, _) | (Syntax::Greater, _, _) | (Syntax::LessEqual, _, _) | (Syntax::GreaterEqual, _, _) => Ok(number(0.0)),
+            (Syntax::Equal, _, _) | (Syntax::NotEqual, _, _) => Ok(number(0.0)),
+            (Syntax::And, _, _) | (Syntax::Or, _, _) | (Syntax::Not, _, _) => Ok(number(0.0)),
+            (Syntax::Condition, _, _) => match self.arguments()[..] {
+                [condition, u, v] => Ok(Node::chain(vec![condition.clone(), d(u)?, d(v)?], Syntax::Condition)),
+                _ => Err(self.not_differentiable("This condition")),
+            },
+
+            // u° = u π / 180 and u% = u / 100 are linear.
+            (Syntax::Degree, Some(u), _) | (Syntax::Percent, Some(u), _) => Ok(Node::new(Some(d(u)?), None, self.syntax.clone())),
+            (Syntax::Absolute, _, _) => Err(self.not_differentiable("The absolute value").with_hint(HOLOMORPHIC)),
+            (Syntax::Norm, _, _) => Err(self.not_differentiable("The norm").with_hint(HOLOMORPHIC)),
+            (Syntax::Conjugate, _, _) => Err(self.not_differentiable("The conjugate").with_hint(HOLOMORPHIC)),
+            (Syntax::Factorial, _, _) => Err(self.not_differentiable("The factorial")),
+
+            (Syntax::Sum(name), _, _) | (Syntax::Product(name), _, _) | (Syntax::Iterate(name), _, _) => {
+                self.loop_derivative(name, variable)
+            }
+            (Syntax::Function(name), _, _) => self.function_derivative(name, variable),
+
+            _ => Err(self.not_differentiable("This")),
+        }
+    }
+
+    // (u^v)' = v u^(v - 1) u' + u^v log(u) v', without the part that is 0 when only one side depends on the variable.
+    fn power_derivative(base: &Node, exponent: &Node, variable: &str) -> Result<Node, Diagnostic> {
+        let mut terms = Vec::new();
+        if base.depends_on(variable) {
+            let lowered = power(base.clone(), subtract(exponent.clone(), number(1.0)));
+            terms.push(multiply(multiply(exponent.clone(), lowered), base.derivative(variable)?));
+        }
+        if exponent.depends_on(variable) {
+            let logarithm = call("log", vec![base.clone()]);
+            terms.push(multiply(multiply(power(base.clone(), exponent.clone()), logarithm), exponent.derivative(variable)?));
+        }
+        Ok(terms.into_iter().fold(number(0.0), add))
+    }
+
+    fn loop_derivative(&self, name: &str, variable: &str) -> Result<Node, Diagnostic> {
+        let (_, bounds, body) = match self.scoped() {
+            Some(scoped) => scoped,
+            None => return Err(self.not_differentiable("This loop")),
+        };
+        match &self.syntax {
+            // The bounds are rounded, so only the body counts. A body that binds the variable hides it.
+            Syntax::Sum(_) if name == variable => Ok(number(0.0)),
+            Syntax::Sum(_) => {
+                let mut arguments: Vec<Node> = bounds.iter().map(|&bound| bound.clone()).collect();
+                arguments.push(body.derivative(variable)?);
+                Ok(self.with_arguments(arguments))
+            }
+            Syntax::Product(_) if name == variable => Ok(number(0.0)),
+            // Constant bounds are written out, so a factor that is 0 doesn't end up dividing by 0.
+            Syntax::Product(_) => match self.unroll_product(name, &bounds) {
+                Some(product) => product.simplify().derivative(variable),
+                // (Π f)' = Π f Σ f' / f
+                None => {
+                    let mut arguments: Vec<Node> = bounds.iter().map(|&bound| bound.clone()).collect();
+                    arguments.push(divide(body.derivative(variable)?, body.clone()));
+                    let sum = Node::chain(arguments, Syntax::Sum(name.to_string()));
+                    Ok(multiply(self.clone(), sum))
+                }
+            },
+            // The iterations are written out, like "iter(2, w = z, w^2)" is "(z^2)^2".
+            _ => {
+                let (count, start) = match bounds[..] {
+                    [count, start] => (count, start),
+                    _ => return Err(self.not_differentiable("This loop")),
+                };
+                let count = match count.simplify().syntax {
+                    Syntax::Real(count, _) => count.round().max(0.0).min(LOOP_LIMIT as f64) as usize,
+                    _ => {
+                        return Err(self.not_differentiable("iter")
+                            .with_hint("Only iter with a constant count can be differentiated"));
+                    }
+                };
+                let mut value = start.clone();
+                for _ in 0..count {
+                    value = body.substitute(&vec![format!("{}", name)], &vec![value]);
+                    if value.size() > UNROLL_LIMIT {
+                        return Err(self.not_differentiable("iter")
+                            .with_hint(&format!("Written out, the {} iterations have more than {} nodes", count, UNROLL_LIMIT)));
+                    }
+                }
+                value.derivative(variable)
+            }
+        }
+    }
+
+    // Product of the body for every value of the bound name, if the bounds are constant and it isn't too large.
+    fn unroll_product(&self, name: &str, bounds: &Vec<&Node>) -> Option<Node> {
+        let body = self.scoped()?.2;
+        let (start, end) = match (bounds.first()?.simplify().syntax, bounds.get(1)?.simplify().syntax) {
+            (Syntax::Real(start, _), Syntax::Real(end, _)) => (start.round(), end.round()),
+            _ => return None,
+        };
+        if end - start >= (UNROLL_LIMIT / body.size().max(1)) as f64 {
+            return None;
+        }
+        let mut product = number(1.0);
+        let mut k = start;
+        while k <= end {
+            product = multiply(product, body.substitute(&vec![format!("{}", name)], &vec![number(k)]));
+            k += 1.0;
+        }
+        Some(product)
+    }
+
+    fn function_derivative(&self, name: &str, variable: &str) -> Result<Node, Diagnostic> {
+        let arguments: Vec<Node> = self.arguments().into_iter().cloned().collect();
+        // Functions of several arguments are written with simpler ones.
+        match (name, &arguments[..]) {
+            ("pow", [base, exponent]) => return Self::power_derivative(base, exponent, variable),
+            ("logb", [value, base]) => {
+                let quotient = divide(call("log", vec![value.clone()]), call("log", vec![base.clone()]));
+                return quotient.derivative(variable);
+            }
+            ("lerp", [a, b, t]) => return add(a.clone(), multiply(subtract(b.clone(), a.clone()), t.clone())).derivative(variable),
+            // (x y' - y x') / (x^2 + y^2)
+            ("atan2", [y, x]) => {
+                let numerator = subtract(multiply(x.clone(), y.derivative(variable)?), multiply(y.clone(), x.derivative(variable)?));
+                let denominator = add(power(x.clone(), number(2.0)), power(y.clone(), number(2.0)));
+                return Ok(divide(numerator, denominator));
+            }
+            _ => (),
+        }
+
+        let u = match &arguments[..] {
+            [u] => u.clone(),
+            _ => return Err(self.not_differentiable(&format!("'{}'", name))),
+        };
+        let one_minus_square = || subtract(number(1.0), power(u.clone(), number(2.0)));
+        // Chain rule, f(u)' = f'(u) u'.
+        let outer = match name {
+            "sin" => call("cos", vec![u.clone()]),
+            "cos" => negate(call("sin", vec![u.clone()])),
+            "tan" => divide(number(1.0), power(call("cos", vec![u.clone()]), number(2.0))),
+            "sqrt" => divide(number(1.0), multiply(number(2.0), call("sqrt", vec![u.clone()]))),
+            "log" => divide(number(1.0), u.clone()),
+            "exp" => call("exp", vec![u.clone()]),
+            "asin" => divide(number(1.0), call("sqrt", vec![one_minus_square()])),
+            "acos" => negate(divide(number(1.0), call("sqrt", vec![one_minus_square()]))),
+            "atan" => divide(number(1.0), add(number(1.0), power(u.clone(), number(2.0)))),
+            "round" | "ceil" | "floor" => return Ok(number(0.0)),
+            "abs" | "arg" => {
+                return Err(self.not_differentiable(&format!("'{}'", name)).with_hint(HOLOMORPHIC));
+            }
+            _ => return Err(self.not_differentiable(&format!("'{}'", name))),
+        };
+        Ok(multiply(outer, u.derivative(variable)?))
+    }
+
+    fn not_differentiable(&self, what: &str) -> Diagnostic {
+        Diagnostic::error(self.span, &format!("{} can't be differentiated", what))
+    }
+
+    // Loops hide the variable in their body if they bind the same name.
+    pub fn depends_on(&self, variable: &str) -> bool {
+        if let Some((name, bounds, body)) = self.scoped() {
+            return bounds.iter().any(|bound| bound.depends_on(variable)) || (name != variable && body.depends_on(variable));
+        }
+        match &self.syntax {
+            Syntax::Variable(name) => name == variable,
+            _ => [&self.left, &self.right].iter().any(|child| child.iter().any(|child| child.depends_on(variable))),
+        }
+    }
+    pub fn size(&self) -> usize {
+        1 + [&self.left, &self.right].iter().map(|child| child.as_ref().map_or(0, |child| child.size())).sum::<usize>()
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::complex::Complex;
+    use super::super::testing::{ bounded, close_within, random_arithmetic, Xorshift };
+
+    // Compares the derivative of random trees to central differences at random points.
+    #[test]
+    fn derivative_matches_differences() {
+        let mut random = Xorshift(0x2545f491);
+        let step = 1e-6;
+
+        for _ in 0..1000 {
+            let tree = random_arithmetic(&mut random, 4);
+            let derivative = match tree.simplify().derivative("z") {
+                Ok(derivative) => derivative.simplify(),
+                Err(diagnostic) => panic!("'{}': {}", tree.to_source(), diagnostic.message),
+            };
+
+            let mut interpreter = random.interpreter();
+            let z = random.point();
+            let mut at = |z: Complex| {
+                interpreter.set_variable("z", z);
+                (interpreter.evaluate_node(&tree), bounded(&tree, &interpreter))
+            };
+            let ((after, after_bounded), (before, before_bounded)) = (at(z + Complex::real(step)), at(z - Complex::real(step)));
+            let (_, bounded_here) = at(z);
+            if !(after_bounded && before_bounded && bounded_here) {
+                continue;
+            }
+            let expected = (after - before) / Complex::real(2.0 * step);
+            let found = interpreter.evaluate_node(&derivative);
+            // Differences lose about half of the digits.
+            assert!(
+                close_within(expected, found, 1e-5 * (after.abs().max(before.abs()) / step).max(1.0)),
+                "'{}' at z = {} is about {}, but its derivative '{}' is {}",
+                tree.to_source(), z, expected, derivative.to_source(), found,
+            );
+        }
+    }
+}
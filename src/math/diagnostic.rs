@@ -0,0 +1,112 @@
+use std::fmt::Display;
+use serde::{ Serialize, Deserialize };
+
+
+
+// Byte range into the input string, end is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+        }
+    }
+    pub fn at(position: usize) -> Self {
+        Self::new(position, position)
+    }
+
+    pub fn join(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+    pub hint: Option<String>,
+}
+impl Diagnostic {
+    pub fn new(span: Span, severity: Severity, message: &str) -> Self {
+        Self {
+            span,
+            severity,
+            message: message.to_string(),
+            hint: None,
+        }
+    }
+    pub fn error(span: Span, message: &str) -> Self {
+        Self::new(span, Severity::Error, message)
+    }
+    pub fn warning(span: Span, message: &str) -> Self {
+        Self::new(span, Severity::Warning, message)
+    }
+    pub fn with_hint(mut self, hint: &str) -> Self {
+        self.hint = Some(hint.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+
+    pub fn pretty(&self, input: &str) -> String {
+        // Clamp the span, so a stale diagnostic can't slice outside the input.
+        let start = self.span.start.min(input.len());
+        let end = self.span.end.min(input.len()).max(start);
+
+        // Only print the line the span starts on.
+        let line_start = input[..start].rfind('\n').map_or(0, |position| position + 1);
+        let line_end = input[start..].find('\n').map_or(input.len(), |position| start + position);
+        let line = &input[line_start..line_end];
+
+        // Columns are counted in characters, not bytes.
+        let column = input[line_start..start].chars().count();
+        let width = input[start..end.min(line_end)].chars().count().max(1);
+
+        let padding = " ".repeat(column);
+        let mut result = format!("{}\n{}{} {}: {}", line, padding, "^".repeat(width), self.severity, self.message);
+        if let Some(hint) = &self.hint {
+            result.push_str(&format!("\n{}help: {}", padding, hint));
+        }
+        result
+    }
+}
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}: {}", self.severity, self.span, self.message)
+    }
+}
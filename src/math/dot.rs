@@ -0,0 +1,340 @@
+use super::parser::{ Expression, Node, Syntax };
+use super::serializer::NodeData;
+
+
+
+// Names of the syntax ids from Syntax::serialize_syntax, the index is the id.
+const SYNTAX_NAMES: [&str; Syntax::BOUND as usize + 1] = [
+    "Real", "Imaginary", "Variable", "Function",
+    "Parenthesis", "Addition", "Subtraction", "Multiplication", "Division", "Exponent", "Absolute", "Argument",
+    "Bracket", "Brace", "Floor", "Ceil", "Norm",
+    "Less", "Greater", "LessEqual", "GreaterEqual", "Equal", "NotEqual", "And", "Or", "Not", "Condition",
+    "Factorial", "Conjugate", "Degree", "Percent",
+    "Sum", "Product", "Iterate",
+    "Bound",
+];
+// Syntax ids of loops, their keyword is the index after their subtree.
+const LOOPS: [u16; 3] = [31, 32, 33];
+
+fn syntax_name(id: u16) -> &'static str {
+    SYNTAX_NAMES.get(id as usize).copied().unwrap_or("Unknown")
+}
+// Whether a child of a serialized node has to be there, see Node::serialized_children.
+#[derive(Clone, Copy, PartialEq)]
+enum Child {
+    Required,
+    Optional,
+    Absent,
+}
+// Left and right child of a syntax id, arguments after the first are chained through the right child.
+fn children(id: u16) -> (Child, Child) {
+    match id {
+        0 | 1 | 2 | Syntax::BOUND => (Child::Absent, Child::Absent),
+        3 | 11 => (Child::Required, Child::Optional),
+        4 | 10 | 12..=16 | 25 | 27..=30 => (Child::Required, Child::Absent),
+        _ => (Child::Required, Child::Required),
+    }
+}
+fn check_children(index: usize, syntax: u16, left: usize, right: usize) -> Result<(), String> {
+    let (expected_left, expected_right) = children(syntax);
+    for (child, expected, side) in [(left, expected_left, "left"), (right, expected_right, "right")].iter() {
+        match (*child, *expected) {
+            (0, Child::Required) => {
+                return Err(format!("Node {}: {} needs a {} child", index, syntax_name(syntax), side));
+            }
+            (child, Child::Absent) if child != 0 => {
+                return Err(format!("Node {}: {} can't have a {} child", index, syntax_name(syntax), side));
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+// Quotes and backslashes would end the label early.
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+
+impl Node {
+    // Graphviz source of the tree, nodes are labeled with their syntax and span.
+    pub fn to_dot(&self) -> String {
+        let mut lines = Vec::new();
+        self.dot_next(&mut lines, &mut 0);
+        format!("digraph tree {{\n    node [shape=box, fontname=\"monospace\"];\n{}\n}}\n", lines.join("\n"))
+    }
+    // Returns the id of the node, ids are given out in pre-order.
+    fn dot_next(&self, lines: &mut Vec<String>, next: &mut usize) -> usize {
+        let id = *next;
+        *next += 1;
+        let label = match &self.syntax {
+            Syntax::Real(value, literal) => literal.format(*value),
+            Syntax::Imaginary(value, literal) => format!("{}i", literal.format(*value)),
+            Syntax::Variable(name) | Syntax::Function(name) => name.to_string(),
+            Syntax::Sum(name) => format!("Sum {}", name),
+            Syntax::Product(name) => format!("Product {}", name),
+            Syntax::Iterate(name) => format!("Iterate {}", name),
+            syntax => format!("{:?}", syntax),
+        };
+        lines.push(format!("    n{} [label=\"{}\\n{}\"];", id, escape(&label), self.span));
+
+        for (child, side) in [(&self.left, "left"), (&self.right, "right")].iter() {
+            if let Some(child) = child {
+                let child = child.dot_next(lines, next);
+                lines.push(format!("    n{} -> n{} [label=\"{}\"];", id, child, side));
+            }
+        }
+        id
+    }
+}
+
+impl Expression {
+    pub fn to_dot(&self) -> String {
+        match &self.tree {
+            Some(tree) => tree.to_dot(),
+            None => "digraph tree {}\n".to_string(),
+        }
+    }
+}
+
+
+
+// Graphviz source of the array the shader gets, one record per node with its index, syntax, keyword and value.
+// Loops point back from the end of their subtree, bound variables point to their loop.
+pub fn nodes_to_dot(nodes: &[NodeData]) -> String {
+    let mut lines = Vec::new();
+    for (index, node) in nodes.iter().enumerate() {
+        let (left, right, syntax, keyword, value) = (node.left_index, node.right_index, node.syntax, node.keyword, node.value);
+        lines.push(format!(
+            "    n{} [label=\"{{{} | {} {} | keyword {} | ({}, {})}}\"];",
+            index, index, syntax, syntax_name(syntax), keyword, value.0, value.1,
+        ));
+        if left != 0 {
+            lines.push(format!("    n{} -> n{} [label=\"left\"];", index, left));
+        }
+        if right != 0 {
+            lines.push(format!("    n{} -> n{} [label=\"right\"];", index, right));
+        }
+        if syntax == Syntax::BOUND {
+            lines.push(format!("    n{} -> n{} [style=dashed, label=\"bound\"];", index, keyword));
+        }
+    }
+    format!("digraph nodes {{\n    node [shape=record, fontname=\"monospace\"];\n{}\n}}\n", lines.join("\n"))
+}
+// Same view as nodes_to_dot, for tools that don't read Graphviz.
+pub fn nodes_to_json(nodes: &[NodeData]) -> String {
+    let nodes: Vec<serde_json::Value> = nodes.iter().enumerate().map(|(index, node)| {
+        let (left, right, syntax, keyword, value) = (node.left_index, node.right_index, node.syntax, node.keyword, node.value);
+        serde_json::json!({
+            "index": index,
+            "syntax": syntax,
+            "name": syntax_name(syntax),
+            "keyword": keyword,
+            "left": left,
+            "right": right,
+            "value": [value.0, value.1],
+        })
+    }).collect();
+    serde_json::Value::Array(nodes).to_string()
+}
+
+
+
+// Checks that the array is a tree with shared subtrees, the shader would read garbage or loop forever otherwise.
+// Index 0 is the root, so 0 as a child index means there is no child.
+pub fn check_nodes(nodes: &[NodeData]) -> Result<(), String> {
+    if nodes.is_empty() {
+        return Ok(());
+    }
+    check_forest(nodes, &[0])
+}
+// Same check for several trees stored one after another, like a function and its derivative.
+// Every tree has to end right where the next root starts.
+pub fn check_forest(nodes: &[NodeData], roots: &[usize]) -> Result<(), String> {
+    for (position, &root) in roots.iter().enumerate() {
+        let end = roots.get(position + 1).copied().unwrap_or(nodes.len());
+        if end > nodes.len() {
+            return Err(format!("Root {} is out of range, there are {} nodes", end, nodes.len()));
+        }
+        if root >= end {
+            return Err(format!("Root {} is out of range, the tree ends at {}", root, end));
+        }
+        check_shared(nodes, root, end)?;
+    }
+    Ok(())
+}
+// Outside of loops children come after their parents, which rules out cycles, and can have several parents.
+// Loops are evaluated again for every iteration, so they have to be a tree in pre-order
+// that nothing outside of it points into.
+fn check_shared(nodes: &[NodeData], root: usize, end: usize) -> Result<(), String> {
+    let mut reached = vec![false; end];
+    reached[root] = true;
+    let mut edges = Vec::new();
+    let mut loops = Vec::new();
+    let mut index = root;
+    while index < end {
+        let node = nodes[index];
+        let (left, right, syntax, keyword) = (node.left_index as usize, node.right_index as usize, node.syntax, node.keyword);
+        if !reached[index] {
+            return Err(format!("Node {} can't be reached from the root at {}", index, root));
+        }
+        if syntax > Syntax::BOUND {
+            return Err(format!("Node {}: unknown syntax {}", index, syntax));
+        }
+        if syntax == Syntax::BOUND {
+            return Err(format!("Node {}: bound variable refers to {}, which is not an enclosing loop", index, keyword));
+        }
+        check_children(index, syntax, left, right)?;
+
+        if LOOPS.contains(&syntax) {
+            let next = check_nodes_next(nodes, index, &mut Vec::new())?;
+            if next > end {
+                return Err(format!("Node {}: {} ends at {}, after the tree ends at {}", index, syntax_name(syntax), next, end));
+            }
+            loops.push((index, next));
+            index = next;
+            continue;
+        }
+        for (child, side) in [(left, "left"), (right, "right")].iter() {
+            match *child {
+                0 => (),
+                child if child >= end => {
+                    return Err(format!("Node {}: {} child {} is out of range", index, side, child));
+                }
+                child if child <= index => {
+                    return Err(format!("Node {}: {} child {} comes before its parent", index, side, child));
+                }
+                child => {
+                    reached[child] = true;
+                    edges.push((index, child));
+                }
+            }
+        }
+        index += 1;
+    }
+
+    for (parent, child) in edges {
+        if let Some(&(start, _)) = loops.iter().find(|&&(start, next)| start < child && child < next) {
+            return Err(format!("Node {}: child {} is inside the loop at {}", parent, child, start));
+        }
+    }
+    Ok(())
+}
+// Returns the index after the subtree, loops are kept in scope for the variables they bind.
+fn check_nodes_next(nodes: &[NodeData], index: usize, loops: &mut Vec<usize>) -> Result<usize, String> {
+    let node = nodes[index];
+    let (left, right, syntax, keyword) = (node.left_index as usize, node.right_index as usize, node.syntax, node.keyword as usize);
+    if syntax > Syntax::BOUND {
+        return Err(format!("Node {}: unknown syntax {}", index, syntax));
+    }
+    check_children(index, syntax, left, right)?;
+
+    let is_loop = LOOPS.contains(&syntax);
+    if is_loop {
+        loops.push(index);
+    }
+    // Children directly follow their parent and each other, which also rules out cycles.
+    let mut next = index + 1;
+    for (child, side) in [(left, "left"), (right, "right")].iter() {
+        match *child {
+            0 => (),
+            child if child >= nodes.len() => {
+                return Err(format!("Node {}: {} child {} is out of range", index, side, child));
+            }
+            child if child != next => {
+                return Err(format!("Node {}: {} child is {}, expected {} in pre-order", index, side, child, next));
+            }
+            child => next = check_nodes_next(nodes, child, loops)?,
+        }
+    }
+    if is_loop {
+        loops.pop();
+    }
+
+    match syntax {
+        _ if is_loop && keyword != next => {
+            Err(format!("Node {}: {} ends at {}, but its keyword is {}", index, syntax_name(syntax), next, keyword))
+        }
+        Syntax::BOUND if !loops.contains(&keyword) => {
+            Err(format!("Node {}: bound variable refers to {}, which is not an enclosing loop", index, keyword))
+        }
+        _ => Ok(next),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ check_forest, check_nodes };
+    use super::super::keyword::Keyword;
+    use super::super::parser::{ Expression, Syntax };
+    use super::super::serializer::{ Empty, NodeData };
+
+    fn node(syntax: u16, left: u16, right: u16, keyword: u16) -> NodeData {
+        NodeData {
+            left_index: left,
+            right_index: right,
+            syntax,
+            keyword,
+            value: (0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn serialized_trees_pass() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        for input in ["z^2 + z^2", "sum(k, 1, 3, z^k) * sum(k, 1, 3, z^k)", "iter(3, 1, z^2 + 1) + |z|", "if(z < 1, z!, -z)"] {
+            let nodes = Expression::parse(input, &keywords).serialize::<Empty, Empty>();
+            assert_eq!(check_nodes(&nodes), Ok(()), "'{}'", input);
+        }
+    }
+
+    #[test]
+    fn invalid_nodes() {
+        let (real, add, parenthesis, sum, argument) = (0, 5, 4, 31, 11);
+        let cases: [(&[NodeData], &str); 9] = [
+            (&[node(add, 1, 5, 0), node(real, 0, 0, 0)], "Node 0: right child 5 is out of range"),
+            (&[node(add, 1, 2, 0), node(real, 0, 0, 0), node(parenthesis, 2, 0, 0)], "Node 2: left child 2 comes before its parent"),
+            (&[node(add, 1, 2, 0), node(parenthesis, 2, 0, 0), node(parenthesis, 1, 0, 0)], "Node 2: left child 1 comes before its parent"),
+            (&[node(add, 1, 0, 0), node(real, 0, 0, 0)], "Node 0: Addition needs a right child"),
+            (&[node(real, 1, 0, 0), node(real, 0, 0, 0)], "Node 0: Real can't have a left child"),
+            (&[node(parenthesis, 1, 2, 0), node(real, 0, 0, 0), node(real, 0, 0, 0)], "Node 0: Parenthesis can't have a right child"),
+            (&[node(real, 0, 0, 0), node(real, 0, 0, 0)], "Node 1 can't be reached from the root at 0"),
+            (&[node(Syntax::BOUND, 0, 0, 0)], "Node 0: bound variable refers to 0, which is not an enclosing loop"),
+            // sum(k, 1, 1, ...) without its body.
+            (&[
+                node(sum, 1, 2, 5), node(real, 0, 0, 0), node(argument, 3, 4, 0), node(real, 0, 0, 0), node(argument, 0, 0, 0),
+            ], "Node 4: Argument needs a left child"),
+        ];
+        for (nodes, expected) in &cases {
+            assert_eq!(check_nodes(nodes), Err(expected.to_string()));
+        }
+
+        let looped = [
+            node(sum, 1, 2, 5), node(real, 0, 0, 0), node(argument, 3, 4, 0), node(real, 0, 0, 0), node(argument, 0, 0, 0),
+        ];
+        let mut cycle = looped.to_vec();
+        cycle[4] = node(argument, 2, 0, 0);
+        assert_eq!(check_nodes(&cycle), Err("Node 4: left child is 2, expected 5 in pre-order".to_string()));
+        let mut bound = looped.to_vec();
+        bound[4] = node(argument, 5, 0, 0);
+        bound.push(node(Syntax::BOUND, 0, 0, 0));
+        bound[0] = node(sum, 1, 2, 6);
+        assert_eq!(check_nodes(&bound), Ok(()));
+        bound[0] = node(sum, 1, 2, 5);
+        assert_eq!(check_nodes(&bound), Err("Node 0: Sum ends at 6, but its keyword is 5".to_string()));
+    }
+
+    #[test]
+    fn invalid_forests() {
+        let (real, add) = (0, 5);
+        let nodes = [node(add, 1, 2, 0), node(real, 0, 0, 0), node(real, 0, 0, 0)];
+        assert_eq!(check_forest(&nodes, &[0]), Ok(()));
+        assert_eq!(check_forest(&nodes, &[0, 2]), Err("Node 0: right child 2 is out of range".to_string()));
+        assert_eq!(check_forest(&nodes, &[0, 5]), Err("Root 5 is out of range, there are 3 nodes".to_string()));
+        assert_eq!(check_forest(&nodes, &[2, 1]), Err("Root 2 is out of range, the tree ends at 1".to_string()));
+        assert_eq!(check_forest(&nodes, &[1]), Err("Node 2 can't be reached from the root at 1".to_string()));
+    }
+}
@@ -0,0 +1,528 @@
+use super::definition::Definition;
+use super::keyword::Keyword;
+use super::parser::{ Expression, Literal, Node, Operator, Syntax };
+use super::printer::ATOM;
+
+
+
+// Functions LaTeX has a command for, the others are set with \operatorname.
+const LATEX_FUNCTIONS: [(&str, &str); 9] = [
+    ("sin", "\\sin"),
+    ("cos", "\\cos"),
+    ("tan", "\\tan"),
+    ("asin", "\\arcsin"),
+    ("acos", "\\arccos"),
+    ("atan", "\\arctan"),
+    ("log", "\\log"),
+    ("exp", "\\exp"),
+    ("arg", "\\arg"),
+];
+// Names and symbols that are typeset as a single letter, with their LaTeX command and character.
+const LETTERS: [(&str, &str, char); 4] = [
+    ("pi", "\\pi", 'π'),
+    ("tau", "\\tau", 'τ'),
+    ("phi", "\\varphi", 'φ'),
+    ("inf", "\\infty", '∞'),
+];
+
+
+
+impl Node {
+    // LaTeX math mode source, parentheses follow the same rules as Node::to_source,
+    // except that fractions, cases and overlines already group their content.
+    pub fn to_latex(&self) -> String {
+        match &self.syntax {
+            Syntax::Ignore => String::new(),
+            Syntax::Error => "\\square".to_string(),
+
+            Syntax::Real(value, literal) => Self::latex_number(*value, literal),
+            Syntax::Imaginary(value, _) if *value == 1.0 => "i".to_string(),
+            Syntax::Imaginary(value, literal) => format!("{}i", Self::latex_number(*value, literal)),
+            Syntax::Variable(name) => Self::latex_name(name),
+            Syntax::Function(name) => {
+                let arguments = self.arguments().iter()
+                    .map(|argument| argument.to_latex())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                match (name.as_str(), LATEX_FUNCTIONS.iter().find(|(function, _)| function == name)) {
+                    ("sqrt", _) => format!("\\sqrt{{{}}}", arguments),
+                    ("abs", _) => format!("\\left|{}\\right|", arguments),
+                    (_, Some((_, command))) => format!("{}\\left({}\\right)", command, arguments),
+                    (_, None) => format!("\\operatorname{{{}}}\\left({}\\right)", name, arguments),
+                }
+            }
+
+            Syntax::Parenthesis | Syntax::Argument => self.latex_left(0),
+            Syntax::Absolute => format!("\\left|{}\\right|", self.latex_left(0)),
+            Syntax::Bracket => format!("\\left[{}\\right]", self.latex_left(0)),
+            Syntax::Brace => format!("\\left\\{{{}\\right\\}}", self.latex_left(0)),
+            Syntax::Floor => format!("\\left\\lfloor {}\\right\\rfloor", self.latex_left(0)),
+            Syntax::Ceil => format!("\\left\\lceil {}\\right\\rceil", self.latex_left(0)),
+            Syntax::Norm => format!("\\left\\|{}\\right\\|", self.latex_left(0)),
+
+            Syntax::Division => format!("\\frac{{{}}}{{{}}}", self.latex_left(0), self.latex_right(0)),
+            Syntax::Exponent => format!("{}^{{{}}}", self.latex_left(Operator::EXPONENT + 1), self.latex_right(0)),
+            Syntax::Conjugate => format!("\\overline{{{}}}", self.latex_left(0)),
+            Syntax::Condition => {
+                let arguments: Vec<String> = self.arguments().iter().map(|argument| argument.to_latex()).collect();
+                match &arguments[..] {
+                    [condition, consequent, alternative] => format!(
+                        "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+                        consequent, condition, alternative,
+                    ),
+                    _ => "\\square".to_string(),
+                }
+            }
+
+            Syntax::Sum(name) | Syntax::Product(name) => {
+                let command = match &self.syntax {
+                    Syntax::Sum(_) => "\\sum",
+                    _ => "\\prod",
+                };
+                match self.arguments()[..] {
+                    [start, end, body] => format!(
+                        "{}_{{{} = {}}}^{{{}}} {}",
+                        command, Self::latex_name(name), start.to_latex(), end.to_latex(), body.latex_child(Operator::MULTIPLICATIVE),
+                    ),
+                    _ => "\\square".to_string(),
+                }
+            }
+            Syntax::Iterate(name) => match self.arguments()[..] {
+                [count, start, body] => format!(
+                    "\\operatorname{{iter}}\\left({}, {} = {}, {}\\right)",
+                    count.to_latex(), Self::latex_name(name), start.to_latex(), body.to_latex(),
+                ),
+                _ => "\\square".to_string(),
+            },
+
+            syntax => match Self::operator(syntax) {
+                Some(_) if self.prefix() => {
+                    let sign = match syntax {
+                        Syntax::Subtraction => "-",
+                        _ => "+",
+                    };
+                    format!("{}{}", sign, self.latex_right(Operator::PREFIX))
+                }
+                Some((operator, _)) => {
+                    let (left, right) = operator.operand_precedence();
+                    match syntax {
+                        Syntax::Not => format!("\\neg {}", self.latex_left(operator.next_precedence())),
+                        Syntax::Factorial => format!("{}!", self.latex_left(Operator::POSTFIX)),
+                        Syntax::Degree => format!("{}^{{\\circ}}", self.latex_left(Operator::POSTFIX)),
+                        Syntax::Percent => format!("{}\\%", self.latex_left(Operator::POSTFIX)),
+                        _ => format!("{} {} {}", self.latex_left(left), Self::latex_operator(syntax), self.latex_right(right)),
+                    }
+                }
+                None => "\\square".to_string(),
+            },
+        }
+    }
+    fn latex_left(&self, precedence: u8) -> String {
+        match &self.left {
+            Some(left) => left.latex_child(precedence),
+            None => "\\square".to_string(),
+        }
+    }
+    fn latex_right(&self, precedence: u8) -> String {
+        match &self.right {
+            Some(right) => right.latex_child(precedence),
+            None => "\\square".to_string(),
+        }
+    }
+    fn latex_child(&self, precedence: u8) -> String {
+        match self.layout_precedence() < precedence {
+            true => format!("\\left({}\\right)", self.to_latex()),
+            false => self.to_latex(),
+        }
+    }
+    fn latex_number(value: f64, literal: &Literal) -> String {
+        match literal {
+            _ if value.is_nan() => "\\mathrm{NaN}".to_string(),
+            _ if value.is_infinite() => format!("{}\\infty", if value < 0.0 { "-" } else { "" }),
+            Literal::Symbol(symbol) => match LETTERS.iter().find(|&&(_, _, letter)| letter == *symbol) {
+                Some((_, command, _)) => command.to_string(),
+                None => format!("{}", symbol),
+            },
+            // "2.5e-3" is set as "2.5 \cdot 10^{-3}".
+            Literal::Scientific => {
+                let source = literal.format(value);
+                match source.split_once('e') {
+                    Some((mantissa, exponent)) => format!("{} \\cdot 10^{{{}}}", mantissa, exponent),
+                    None => source,
+                }
+            }
+            // The letters of "0x1f" would be set as variables, so it's kept in typewriter letters.
+            Literal::Hexadecimal | Literal::Binary => {
+                let source = literal.format(value);
+                match source.contains(|c: char| c.is_ascii_alphabetic()) {
+                    true => format!("\\mathtt{{{}}}", source),
+                    false => source,
+                }
+            }
+            _ => literal.format(value),
+        }
+    }
+    // Trailing digits become a subscript, so "z0" is set as "z_{0}".
+    fn latex_name(name: &str) -> String {
+        let (letters, digits) = Self::split_digits(name);
+        let letters = match LETTERS.iter().find(|(other, _, _)| *other == letters) {
+            Some((_, command, _)) => command.to_string(),
+            None if letters.chars().count() > 1 => format!("\\mathit{{{}}}", letters),
+            None => letters.to_string(),
+        };
+        match digits.is_empty() {
+            true => letters,
+            false => format!("{}_{{{}}}", letters, digits),
+        }
+    }
+    fn latex_operator(syntax: &Syntax) -> &'static str {
+        match syntax {
+            Syntax::Addition => "+",
+            Syntax::Subtraction => "-",
+            Syntax::Multiplication => "\\cdot",
+            Syntax::Less => "<",
+            Syntax::Greater => ">",
+            Syntax::LessEqual => "\\leq",
+            Syntax::GreaterEqual => "\\geq",
+            Syntax::Equal => "=",
+            Syntax::NotEqual => "\\neq",
+            Syntax::And => "\\land",
+            Syntax::Or => "\\lor",
+            _ => "\\square",
+        }
+    }
+
+
+    // Presentation MathML in a <math> element.
+    pub fn to_mathml(&self) -> String {
+        Self::math_element(&self.mathml_next())
+    }
+    fn math_element(content: &str) -> String {
+        format!("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>", content)
+    }
+    // Every node is a single element, so it can be the child of <mfrac> or <msup>.
+    fn mathml_next(&self) -> String {
+        match &self.syntax {
+            Syntax::Ignore => "<mrow></mrow>".to_string(),
+            Syntax::Error => "<merror><mtext>?</mtext></merror>".to_string(),
+
+            Syntax::Real(value, literal) => Self::mathml_number(*value, literal),
+            Syntax::Imaginary(value, _) if *value == 1.0 => "<mi>i</mi>".to_string(),
+            Syntax::Imaginary(value, literal) => {
+                format!("<mrow>{}<mo>&#x2062;</mo><mi>i</mi></mrow>", Self::mathml_number(*value, literal))
+            }
+            Syntax::Variable(name) => Self::mathml_name(name),
+            Syntax::Function(name) => {
+                let arguments = self.arguments().iter()
+                    .map(|argument| argument.mathml_next())
+                    .collect::<Vec<String>>()
+                    .join("<mo>,</mo>");
+                match name.as_str() {
+                    "sqrt" => format!("<msqrt>{}</msqrt>", arguments),
+                    "abs" => Self::mathml_fence("|", &arguments, "|"),
+                    _ => format!("<mrow><mi>{}</mi><mo>&#x2061;</mo>{}</mrow>", name, Self::mathml_fence("(", &arguments, ")")),
+                }
+            }
+
+            Syntax::Parenthesis | Syntax::Argument => self.mathml_left(0),
+            Syntax::Absolute => Self::mathml_fence("|", &self.mathml_left(0), "|"),
+            Syntax::Bracket => Self::mathml_fence("[", &self.mathml_left(0), "]"),
+            Syntax::Brace => Self::mathml_fence("{", &self.mathml_left(0), "}"),
+            Syntax::Floor => Self::mathml_fence("⌊", &self.mathml_left(0), "⌋"),
+            Syntax::Ceil => Self::mathml_fence("⌈", &self.mathml_left(0), "⌉"),
+            Syntax::Norm => Self::mathml_fence("‖", &self.mathml_left(0), "‖"),
+
+            Syntax::Division => format!("<mfrac>{}{}</mfrac>", self.mathml_left(0), self.mathml_right(0)),
+            Syntax::Exponent => format!("<msup>{}{}</msup>", self.mathml_left(Operator::EXPONENT + 1), self.mathml_right(0)),
+            Syntax::Conjugate => format!("<mover accent=\"true\">{}<mo>&#x203E;</mo></mover>", self.mathml_left(0)),
+            Syntax::Condition => match self.arguments()[..] {
+                [condition, consequent, alternative] => format!(
+                    "<mrow><mo>{{</mo><mtable>\
+                    <mtr><mtd>{}</mtd><mtd><mtext>if&#xA0;</mtext>{}</mtd></mtr>\
+                    <mtr><mtd>{}</mtd><mtd><mtext>otherwise</mtext></mtd></mtr>\
+                    </mtable></mrow>",
+                    consequent.mathml_next(), condition.mathml_next(), alternative.mathml_next(),
+                ),
+                _ => "<merror><mtext>?</mtext></merror>".to_string(),
+            },
+
+            Syntax::Sum(name) | Syntax::Product(name) => {
+                let operator = match &self.syntax {
+                    Syntax::Sum(_) => "&#x2211;",
+                    _ => "&#x220F;",
+                };
+                match self.arguments()[..] {
+                    [start, end, body] => format!(
+                        "<mrow><munderover><mo>{}</mo><mrow>{}<mo>=</mo>{}</mrow>{}</munderover>{}</mrow>",
+                        operator, Self::mathml_name(name), start.mathml_next(), end.mathml_next(), body.mathml_child(Operator::MULTIPLICATIVE),
+                    ),
+                    _ => "<merror><mtext>?</mtext></merror>".to_string(),
+                }
+            }
+            Syntax::Iterate(name) => match self.arguments()[..] {
+                [count, start, body] => {
+                    let arguments = format!(
+                        "{}<mo>,</mo><mrow>{}<mo>=</mo>{}</mrow><mo>,</mo>{}",
+                        count.mathml_next(), Self::mathml_name(name), start.mathml_next(), body.mathml_next(),
+                    );
+                    format!("<mrow><mi>iter</mi><mo>&#x2061;</mo>{}</mrow>", Self::mathml_fence("(", &arguments, ")"))
+                }
+                _ => "<merror><mtext>?</mtext></merror>".to_string(),
+            },
+
+            syntax => match Self::operator(syntax) {
+                Some(_) if self.prefix() => {
+                    let sign = match syntax {
+                        Syntax::Subtraction => "&#x2212;",
+                        _ => "+",
+                    };
+                    format!("<mrow><mo>{}</mo>{}</mrow>", sign, self.mathml_right(Operator::PREFIX))
+                }
+                Some((operator, _)) => {
+                    let (left, right) = operator.operand_precedence();
+                    match syntax {
+                        Syntax::Not => format!("<mrow><mo>&#xAC;</mo>{}</mrow>", self.mathml_left(operator.next_precedence())),
+                        Syntax::Factorial => format!("<mrow>{}<mo>!</mo></mrow>", self.mathml_left(Operator::POSTFIX)),
+                        Syntax::Degree => format!("<msup>{}<mo>&#xB0;</mo></msup>", self.mathml_left(Operator::POSTFIX)),
+                        Syntax::Percent => format!("<mrow>{}<mo>%</mo></mrow>", self.mathml_left(Operator::POSTFIX)),
+                        _ => format!("<mrow>{}<mo>{}</mo>{}</mrow>", self.mathml_left(left), Self::mathml_operator(syntax), self.mathml_right(right)),
+                    }
+                }
+                None => "<merror><mtext>?</mtext></merror>".to_string(),
+            },
+        }
+    }
+    fn mathml_left(&self, precedence: u8) -> String {
+        match &self.left {
+            Some(left) => left.mathml_child(precedence),
+            None => "<merror><mtext>?</mtext></merror>".to_string(),
+        }
+    }
+    fn mathml_right(&self, precedence: u8) -> String {
+        match &self.right {
+            Some(right) => right.mathml_child(precedence),
+            None => "<merror><mtext>?</mtext></merror>".to_string(),
+        }
+    }
+    fn mathml_child(&self, precedence: u8) -> String {
+        match self.layout_precedence() < precedence {
+            true => Self::mathml_fence("(", &self.mathml_next(), ")"),
+            false => self.mathml_next(),
+        }
+    }
+    fn mathml_fence(open: &str, content: &str, closed: &str) -> String {
+        format!("<mrow><mo>{}</mo>{}<mo>{}</mo></mrow>", open, content, closed)
+    }
+    fn mathml_number(value: f64, literal: &Literal) -> String {
+        match literal {
+            _ if value.is_nan() => "<mi>NaN</mi>".to_string(),
+            _ if value.is_infinite() && value < 0.0 => "<mrow><mo>&#x2212;</mo><mi>&#x221E;</mi></mrow>".to_string(),
+            _ if value.is_infinite() => "<mi>&#x221E;</mi>".to_string(),
+            _ if value < 0.0 => format!("<mrow><mo>&#x2212;</mo>{}</mrow>", Self::mathml_number(-value, literal)),
+            Literal::Symbol(symbol) => format!("<mi>{}</mi>", symbol),
+            Literal::Scientific => {
+                let source = literal.format(value);
+                match source.split_once('e') {
+                    Some((mantissa, exponent)) => format!(
+                        "<mrow><mn>{}</mn><mo>&#x22C5;</mo><msup><mn>10</mn><mn>{}</mn></msup></mrow>",
+                        mantissa, exponent,
+                    ),
+                    None => format!("<mn>{}</mn>", source),
+                }
+            }
+            _ => format!("<mn>{}</mn>", literal.format(value)),
+        }
+    }
+    fn mathml_name(name: &str) -> String {
+        let (letters, digits) = Self::split_digits(name);
+        let letters = match LETTERS.iter().find(|(other, _, _)| *other == letters) {
+            Some((_, _, letter)) => format!("<mi>{}</mi>", letter),
+            None => format!("<mi>{}</mi>", letters),
+        };
+        match digits.is_empty() {
+            true => letters,
+            false => format!("<msub>{}<mn>{}</mn></msub>", letters, digits),
+        }
+    }
+    fn mathml_operator(syntax: &Syntax) -> &'static str {
+        match syntax {
+            Syntax::Addition => "+",
+            Syntax::Subtraction => "&#x2212;",
+            Syntax::Multiplication => "&#x22C5;",
+            Syntax::Less => "&lt;",
+            Syntax::Greater => "&gt;",
+            Syntax::LessEqual => "&#x2264;",
+            Syntax::GreaterEqual => "&#x2265;",
+            Syntax::Equal => "=",
+            Syntax::NotEqual => "&#x2260;",
+            Syntax::And => "&#x2227;",
+            Syntax::Or => "&#x2228;",
+            _ => "?",
+        }
+    }
+
+
+    // Precedence when typeset, signs are grouped like a subtraction, so "3 \cdot -2" gets parentheses.
+    fn layout_precedence(&self) -> u8 {
+        match &self.syntax {
+            Syntax::Parenthesis | Syntax::Argument => match &self.left {
+                Some(left) => left.layout_precedence(),
+                None => ATOM,
+            },
+            Syntax::Division | Syntax::Condition | Syntax::Conjugate => ATOM,
+            // The body of a sum reaches up to the next addition.
+            Syntax::Sum(_) | Syntax::Product(_) => Operator::ADDITIVE,
+            Syntax::Real(value, _) | Syntax::Imaginary(value, _) if *value < 0.0 => Operator::ADDITIVE,
+            Syntax::Addition | Syntax::Subtraction if self.prefix() => Operator::ADDITIVE,
+            _ => self.precedence(),
+        }
+    }
+    fn split_digits(name: &str) -> (&str, &str) {
+        let end = name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        match end {
+            0 => (name, ""),
+            _ => name.split_at(end),
+        }
+    }
+    // Variables and functions in the tree, so the input can be lexed the same way without the original keywords.
+    fn used_keywords(&self, keywords: &mut Vec<Keyword>) {
+        let keyword = match &self.syntax {
+            Syntax::Variable(name) => Some(Keyword::Variable(name.to_string())),
+            Syntax::Function(name) => Some(Keyword::Function(name.to_string(), self.arguments().len())),
+            _ => None,
+        };
+        if let Some(keyword) = keyword {
+            if !keywords.contains(&keyword) {
+                keywords.push(keyword);
+            }
+        }
+        for child in self.left.iter().chain(self.right.iter()) {
+            child.used_keywords(keywords);
+        }
+    }
+}
+
+impl Expression {
+    // Definitions come first, each statement is separated like in the input.
+    pub fn to_latex(&self) -> String {
+        let (definitions, tree) = self.written();
+        let mut statements: Vec<String> = definitions.iter()
+            .map(|definition| format!("{} = {}", Self::header(definition).to_latex(), definition.body.to_latex()))
+            .collect();
+        statements.extend(tree.map(|tree| tree.to_latex()));
+        statements.join(";\\quad ")
+    }
+    pub fn to_mathml(&self) -> String {
+        let (definitions, tree) = self.written();
+        if definitions.is_empty() {
+            return tree.map_or(String::new(), |tree| tree.to_mathml());
+        }
+        let mut statements: Vec<String> = definitions.iter()
+            .map(|definition| format!("<mrow>{}<mo>=</mo>{}</mrow>", Self::header(definition).mathml_next(), definition.body.mathml_next()))
+            .collect();
+        statements.extend(tree.map(|tree| tree.mathml_next()));
+        match statements.len() {
+            1 => Node::math_element(&statements[0]),
+            _ => Node::math_element(&format!("<mrow>{}</mrow>", statements.join("<mo separator=\"true\">;</mo><mspace width=\"1em\"/>"))),
+        }
+    }
+    // The input parsed again without inlining, so calls to definitions are exported as calls.
+    // Trees that aren't the one parsed from the input anymore, like optimized ones, are exported as they are.
+    fn written(&self) -> (Vec<Definition>, Option<Node>) {
+        let mut keywords = Vec::new();
+        for tree in self.tree.iter().chain(self.definitions.iter().map(|definition| &definition.body)) {
+            tree.used_keywords(&mut keywords);
+        }
+        let unchanged = match (&Expression::parse(&self.input, &keywords).tree, &self.tree) {
+            (Some(parsed), Some(tree)) => parsed.same(tree),
+            (None, None) => true,
+            _ => false,
+        };
+        let written = Expression::parse_written(&self.input, &keywords);
+        match unchanged && written.errors().next().is_none() {
+            true => (written.definitions, written.tree),
+            false => (self.definitions.clone(), self.tree.clone()),
+        }
+    }
+    // Left side of a definition, "f(w)" or "c".
+    fn header(definition: &Definition) -> Node {
+        match definition.is_function() {
+            true => {
+                let parameters = definition.parameters.iter()
+                    .map(|parameter| Node::from_syntax(Syntax::Variable(parameter.to_string())))
+                    .collect();
+                Node::chain(parameters, Syntax::Function(definition.name().to_string()))
+            }
+            false => Node::from_syntax(Syntax::Variable(definition.name().to_string())),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::keyword::Keyword;
+    use super::super::parser::Expression;
+
+    // Hexadecimal and binary literals stay one term in math mode, other numbers are set as they are.
+    #[test]
+    fn latex_literals() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        let cases = [
+            ("0x1f + z", "\\mathtt{0x1f} + z"),
+            ("0b101 * z", "\\mathtt{0b101} \\cdot z"),
+            ("2.5 + z", "2.5 + z"),
+        ];
+        for &(input, expected) in &cases {
+            assert_eq!(Expression::parse(input, &keywords).to_latex(), expected, "'{}'", input);
+        }
+    }
+
+    // Calls to definitions are exported as calls, after the definitions themselves.
+    #[test]
+    fn definitions() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        let cases = [
+            ("f(w) = w^2; f(z)", "\\operatorname{f}\\left(w\\right) = w^{2};\\quad \\operatorname{f}\\left(z\\right)"),
+            ("c = 2; c z", "c = 2;\\quad c \\cdot z"),
+            ("c = 2", "c = 2"),
+        ];
+        for &(input, expected) in &cases {
+            assert_eq!(Expression::parse(input, &keywords).to_latex(), expected, "'{}'", input);
+        }
+        let mut optimized = Expression::parse("c = 2; c + 1", &keywords);
+        optimized.optimize();
+        assert_eq!(optimized.to_latex(), "c = 2;\\quad 3");
+    }
+
+    #[test]
+    fn latex_layout() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        let cases = [
+            ("(z + 1) / 2", "\\frac{z + 1}{2}"),
+            ("z^(2 / 3)", "z^{\\frac{2}{3}}"),
+            ("if(z < 1, z, 0)", "\\begin{cases} z & \\text{if } z < 1 \\\\ 0 & \\text{otherwise} \\end{cases}"),
+            ("sum(k, 1, 10, z^k / k)", "\\sum_{k = 1}^{10} \\frac{z^{k}}{k}"),
+            ("prod(k, 1, 3, z - k)", "\\prod_{k = 1}^{3} \\left(z - k\\right)"),
+            ("iter(3, w0 = z, w0^2)", "\\operatorname{iter}\\left(3, w_{0} = z, w_{0}^{2}\\right)"),
+        ];
+        for &(input, expected) in &cases {
+            assert_eq!(Expression::parse(input, &keywords).to_latex(), expected, "'{}'", input);
+        }
+    }
+
+    #[test]
+    fn mathml() {
+        let keywords = Keyword::vars(&vec!["z", "z1"]);
+        let math = |content: &str| format!("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>", content);
+        let cases = [
+            ("z / 2", math("<mfrac><mi>z</mi><mn>2</mn></mfrac>")),
+            ("z1^2", math("<msup><msub><mi>z</mi><mn>1</mn></msub><mn>2</mn></msup>")),
+            ("c = 2; c", math("<mrow><mrow><mi>c</mi><mo>=</mo><mn>2</mn></mrow><mo separator=\"true\">;</mo><mspace width=\"1em\"/><mi>c</mi></mrow>")),
+        ];
+        for (input, expected) in &cases {
+            assert_eq!(&Expression::parse(input, &keywords).to_mathml(), expected, "'{}'", input);
+        }
+    }
+}
@@ -42,7 +42,7 @@ impl Extended for f64 {
         if a < 3000.0 && b < 3000.0 {
             return (a * a + b * b).ln() * 0.5;
         }
-        (a / b.atan2(a).cos()).ln()
+        a.hypot(b).ln()
     }
 }
 impl Extended for f32 {
@@ -84,6 +84,29 @@ impl Extended for f32 {
         if a < 3000.0 && b < 3000.0 {
             return (a * a + b * b).ln() * 0.5;
         }
-        (a / b.atan2(a).cos()).ln()
+        a.hypot(b).ln()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::Extended;
+
+    #[test]
+    fn log_hypot_of_large_parts() {
+        // 3-4-5 triangles on both sides of the 3000 cutoff.
+        for &scale in [100.0, 1000.0, 1e100, 1e300].iter() {
+            let (a, b): (f64, f64) = (3.0 * scale, 4.0 * scale);
+            let expected = (5.0 * scale).ln();
+            assert!((a.log_hypot(b) - expected).abs() < 1e-12 * expected.abs());
+            assert!((b.log_hypot(-a) - expected).abs() < 1e-12 * expected.abs());
+        }
+        // One part much larger than the other, the angle is close to a right angle.
+        assert!((3001.0f64.log_hypot(1e300) - 1e300f64.ln()).abs() < 1e-12);
+        assert!((1e30f32.log_hypot(3001.0) - 1e30f32.ln()).abs() < 1e-4);
+        assert!((3001.0f32.log_hypot(1e30) - 1e30f32.ln()).abs() < 1e-4);
+        assert!((4000.0f32.log_hypot(3000.0) - 5000.0f32.ln()).abs() < 1e-5);
     }
 }
@@ -0,0 +1,124 @@
+use super::complex::Complex;
+use super::diagnostic::Span;
+use super::interpreter::{ Intepreter, FUNCTIONS };
+use super::parser::{ Literal, Node, Syntax };
+use super::types::Type;
+
+
+
+impl Node {
+    // Evaluates the node if it doesn't depend on any variable, the result is a single constant,
+    // or an addition of a real and an imaginary constant.
+    // Conditions are kept, so the tree still type checks, and numbers are kept with their literal.
+    pub fn fold(&self) -> Option<Node> {
+        match self.syntax {
+            // Arguments only link the arguments of their call, they have no value on their own.
+            Syntax::Real(..) | Syntax::Imaginary(..) | Syntax::Argument => return None,
+            _ => (),
+        }
+        if !self.is_constant(&mut Vec::new()) || self.check(&mut Vec::new()) != Some(Type::Complex) {
+            return None;
+        }
+        // Constants can't have a negative zero part, which matters on branch cuts like log(-1 - 0i).
+        let value = Intepreter::new().evaluate_node(self);
+        let negative_zero = |part: f64| part == 0.0 && part.is_sign_negative();
+        if negative_zero(value.re) || negative_zero(value.im) {
+            return None;
+        }
+        Some(Self::from_complex(value, self.span))
+    }
+    // Names bound by enclosing loops are constant inside of them.
+    pub fn is_constant(&self, bound: &mut Vec<String>) -> bool {
+        let children = |bound: &mut Vec<String>| {
+            [&self.left, &self.right].iter().all(|child| match child {
+                Some(child) => child.is_constant(bound),
+                None => true,
+            })
+        };
+        match &self.syntax {
+            Syntax::Ignore | Syntax::Error => false,
+            Syntax::Real(..) | Syntax::Imaginary(..) => true,
+            Syntax::Variable(name) => bound.contains(name),
+            // Functions the interpreter doesn't know, like rand, only exist in the shader.
+            Syntax::Function(name) => {
+                let arity = self.arguments().len();
+                FUNCTIONS.iter().any(|&(other, other_arity)| other == name && other_arity == arity) && children(bound)
+            }
+            _ => match self.scoped() {
+                Some((name, bounds, body)) => {
+                    let name = name.to_string();
+                    if !bounds.iter().all(|bound_node| bound_node.is_constant(bound)) {
+                        return false;
+                    }
+                    bound.push(name);
+                    let constant = body.is_constant(bound);
+                    bound.pop();
+                    constant
+                }
+                None => children(bound),
+            },
+        }
+    }
+    pub fn from_complex(value: Complex, span: Span) -> Node {
+        let real = Node::from_syntax(Syntax::Real(value.re, Literal::Decimal)).with_span(span);
+        let imaginary = Node::from_syntax(Syntax::Imaginary(value.im, Literal::Decimal)).with_span(span);
+        match value {
+            _ if value.im == 0.0 => real,
+            _ if value.re == 0.0 => imaginary,
+            _ => Node::from(real, imaginary, Syntax::Addition).with_span(span),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::complex::Complex;
+    use super::super::parser::{ Node, Syntax };
+    use super::super::testing::{ random_tree, Xorshift };
+
+    // Folds every constant subtree, children first.
+    fn fold_all(node: &Node) -> Node {
+        let mut new = node.clone();
+        for child in new.left.iter_mut().chain(new.right.iter_mut()) {
+            **child = fold_all(child);
+        }
+        new.fold().unwrap_or(new)
+    }
+    fn loops(node: &Node) -> usize {
+        let own = match node.syntax {
+            Syntax::Sum(_) | Syntax::Product(_) | Syntax::Iterate(_) => 1,
+            _ => 0,
+        };
+        own + [&node.left, &node.right].iter().map(|child| child.as_deref().map_or(0, loops)).sum::<usize>()
+    }
+    // Equal up to rounding, NaN and infinite parts have to match exactly.
+    fn close(lhs: Complex, rhs: Complex) -> bool {
+        let part = |lhs: f64, rhs: f64| match (lhs.is_finite(), rhs.is_finite()) {
+            (true, true) => (lhs - rhs).abs() <= 1e-9 * lhs.abs().max(rhs.abs()).max(1.0),
+            _ => (lhs.is_nan() && rhs.is_nan()) || lhs == rhs,
+        };
+        part(lhs.re, rhs.re) && part(lhs.im, rhs.im)
+    }
+
+    #[test]
+    fn folding_keeps_values() {
+        let mut random = Xorshift(0x2545f491);
+        for _ in 0..1000 {
+            let tree = random_tree(&mut random, 4);
+            // Nested loops run up to LOOP_LIMIT times each, the test would never finish.
+            if loops(&tree) > 1 {
+                continue;
+            }
+            let folded = fold_all(&tree);
+
+            let interpreter = random.interpreter();
+            let (expected, found) = (interpreter.evaluate_node(&tree), interpreter.evaluate_node(&folded));
+            assert!(
+                close(expected, found),
+                "'{}' is {}, but folded to '{}' it is {}", tree.to_source(), expected, folded.to_source(), found,
+            );
+        }
+    }
+}
@@ -0,0 +1,577 @@
+use super::definition::Definition;
+use super::diagnostic::Span;
+use super::keyword::Keyword;
+use super::parser::{ Expression, Lexeme, Node, Operator, Syntax, Token };
+use super::serializer::{ KeywordType, NodeData };
+use super::types::Type;
+
+
+
+// Replacement of a byte range of the input with new text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+impl Edit {
+    pub fn new(span: Span, text: &str) -> Self {
+        Self {
+            span,
+            text: text.to_string(),
+        }
+    }
+    // Smallest edit that turns the old input into the new one, found from their common start and end.
+    pub fn between(old: &str, new: &str) -> Self {
+        let prefix = old.char_indices().zip(new.chars())
+            .find(|&((_, lhs), rhs)| lhs != rhs)
+            .map_or(old.len().min(new.len()), |((position, _), _)| position);
+        // The end can't overlap the start, "aa" to "aaa" inserts a single 'a' at the end.
+        let limit = old.len().min(new.len()) - prefix;
+        let suffix = old[prefix..].chars().rev().zip(new[prefix..].chars().rev())
+            .take_while(|(lhs, rhs)| lhs == rhs)
+            .map(|(c, _)| c.len_utf8())
+            .scan(0, |length, c| { *length += c; Some(*length) })
+            .take_while(|&length| length <= limit)
+            .last()
+            .unwrap_or(0);
+        Self::new(Span::new(prefix, old.len() - suffix), &new[prefix..new.len() - suffix])
+    }
+    pub fn apply(&self, input: &str) -> String {
+        format!("{}{}{}", &input[..self.span.start], self.text, &input[self.span.end..])
+    }
+}
+
+// What a reparse changed, so the caller knows how much of the serialized tree it has to replace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    // Only the value of the number at this span of the new input changed,
+    // every node parsed from it, including the ones inlined from definitions.
+    Value(Span),
+    // Only the binary operator at this span changed, to one that binds the same way, so only its node changed.
+    Operator(Span),
+    // The tokens at this span were parsed again, the nodes parsed from them replace the old subtree
+    // and every other node was kept, with its span moved by the edit.
+    Subtree(Span),
+    // The input was parsed again, no subtree of the old tree was kept.
+    Tree,
+}
+
+
+
+impl Expression {
+    // Parses the input with the edit applied, reusing as much of the old tree as the edit allows.
+    // Edits inside a single number only update the nodes of that number, and replacing an operator with one
+    // that binds the same way, like "+" with "-", only changes its node. Other edits inside parentheses, another
+    // delimiter or an argument only parse that part again. Edits that change the definitions, the names loops bind
+    // or how the text around them is lexed parse the whole input again.
+    pub fn reparse(&self, edit: &Edit, keywords: &Vec<Keyword>) -> (Self, Change) {
+        let input = edit.apply(&self.input);
+        if let Some((expression, span)) = self.edit_number(edit, &input, keywords) {
+            return (expression, Change::Value(span));
+        }
+        if let Some((expression, change)) = self.edit_subtree(edit, &input, keywords) {
+            return (expression, change);
+        }
+        (Self::parse(&input, keywords), Change::Tree)
+    }
+    fn edit_number(&self, edit: &Edit, input: &str, keywords: &[Keyword]) -> Option<(Self, Span)> {
+        // Errors could change with any edit. Warnings are only about names, which the edit keeps.
+        if self.errors().next().is_some() || self.tree.is_none() {
+            return None;
+        }
+        // The edit has to stay inside one number, every other token has to be lexed the same,
+        // so "2" to "2e" in "2+3" isn't mistaken for a new value of "2".
+        let old = Token::parse(&self.input, keywords);
+        let new = Token::parse(input, keywords);
+        if old.len() != new.len() {
+            return None;
+        }
+        let index = old.iter().position(|lexeme| {
+            let number = matches!(lexeme.token, Token::Real(..) | Token::Imaginary(..));
+            number && lexeme.span.start <= edit.span.start && edit.span.end <= lexeme.span.end
+        })?;
+        let same = old.iter().zip(new.iter()).enumerate()
+            .all(|(other, (lhs, rhs))| other == index || lhs.token == rhs.token);
+        let syntax = match (&old[index].token, &new[index].token) {
+            (Token::Real(..), Token::Real(value, literal)) => Syntax::Real(*value, *literal),
+            (Token::Imaginary(..), Token::Imaginary(value, literal)) => Syntax::Imaginary(*value, *literal),
+            _ => return None,
+        };
+        if !same {
+            return None;
+        }
+
+        let span = old[index].span;
+        let delta = edit.text.len() as isize - edit.span.len() as isize;
+        let mut expression = self.clone();
+        expression.input = input.to_string();
+        let mut changed = 0;
+        if let Some(tree) = &mut expression.tree {
+            changed += tree.edit_number(span, &syntax, delta);
+        }
+        for definition in &mut expression.definitions {
+            changed += definition.body.edit_number(span, &syntax, delta);
+            definition.span = definition.span.shifted(span.end, delta);
+        }
+        for diagnostic in &mut expression.diagnostics {
+            diagnostic.span = diagnostic.span.shifted(span.end, delta);
+        }
+        // The number was part of something else, like the name of a definition.
+        if changed == 0 {
+            return None;
+        }
+        Some((expression, new[index].span))
+    }
+    // Definitions are inlined into the tree, so with them an edit can change more than one subtree.
+    fn edit_subtree(&self, edit: &Edit, input: &str, keywords: &[Keyword]) -> Option<(Self, Change)> {
+        let tree = self.tree.as_ref()?;
+        if self.errors().next().is_some() || !self.definitions.is_empty() {
+            return None;
+        }
+        // Names bound by loops are lexed as variables, the edit must not bind other ones.
+        let bound = |input: &str| {
+            let tokens = Token::parse(input, keywords);
+            let (defined, parameters) = Definition::scan(&tokens, input);
+            let bindings = Token::bindings(&tokens, input);
+            let names: Vec<String> = bindings.iter().map(|(name, _)| name.to_string()).collect();
+            (defined.is_empty() && parameters.is_empty(), names, bindings)
+        };
+        let (_, old_names, bindings) = bound(&self.input);
+        let (undefined, new_names, _) = bound(input);
+        if !undefined || old_names != new_names {
+            return None;
+        }
+        let known = Expression::known(keywords, &[], &[], &bindings);
+        let old = Token::parse(&self.input, &known);
+        let (new, warnings) = Token::parse_diagnostics(input, &known);
+        if new.iter().any(|lexeme| matches!(lexeme.token, Token::Invalid(_))) {
+            return None;
+        }
+
+        // Tokens in front of the edit are the same, the ones after it moved by the length of the edit.
+        let delta = input.len() as isize - self.input.len() as isize;
+        let before = old.iter().zip(new.iter())
+            .take_while(|(lhs, rhs)| lhs == rhs && lhs.span.end <= edit.span.start)
+            .count();
+        let after = old.iter().rev().zip(new.iter().rev())
+            .take_while(|(lhs, rhs)| lhs.token == rhs.token && lhs.span.shifted(0, delta) == rhs.span && lhs.span.start >= edit.span.end)
+            .count()
+            .min(old.len().min(new.len()) - before);
+        let (old_end, new_end) = (old.len() - after, new.len() - after);
+
+        let mut expression = self.clone();
+        expression.input = input.to_string();
+        for diagnostic in &mut expression.diagnostics {
+            diagnostic.span = diagnostic.span.shifted(edit.span.end, delta);
+        }
+        let mut changed = tree.clone();
+        changed.shift(edit.span.end, delta);
+
+        // An operator replaced with one of the same precedence and associativity keeps the structure of the tree.
+        if old_end == before + 1 && new_end == before + 1 {
+            let operator = |token: &Token| match token {
+                // "*" can also be a conjugate, depending on what follows it, and "?" takes a third operand.
+                Token::Multiplication | Token::Question => None,
+                token => Operator::binary(token).map(|operator| (operator.precedence, operator.associativity, operator.syntax)),
+            };
+            if let (Some((precedence, associativity, _)), Some((other_precedence, other_associativity, syntax))) =
+                (operator(&old[before].token), operator(&new[before].token))
+            {
+                // A minus can also be a negation, which has no node of its own to change.
+                let path = tree.operator_at(&old, before, &mut Vec::new());
+                if let (true, Some(path)) = (precedence == other_precedence && associativity == other_associativity, path) {
+                    changed.at(&path).syntax = syntax;
+                    expression.tree = Some(changed);
+                    return Some((expression, Change::Operator(new[before].span)));
+                }
+            }
+        }
+
+        // The innermost delimited part that contains every changed token is parsed again on its own.
+        let (path, first, last) = tree.delimited_at(&old, before, old_end, &mut Vec::new())?;
+        let last = (last as isize + new.len() as isize - old.len() as isize) as usize;
+        let subtree = Node::parse_tokens(&new[first..=last].to_vec(), &known)?;
+        let span = subtree.span;
+        *changed.at(&path) = subtree;
+
+        // The new part could use a bound name outside of its loop or have the wrong type.
+        let names: Vec<String> = new_names.into_iter()
+            .filter(|name| !keywords.iter().any(|keyword| keyword.name() == name))
+            .collect();
+        let mut diagnostics = Vec::new();
+        changed.expect(Type::Complex, &mut diagnostics);
+        if changed.uses(&names).is_some() || !diagnostics.is_empty() {
+            return None;
+        }
+        expression.tree = Some(changed);
+        expression.diagnostics = warnings;
+        Some((expression, Change::Subtree(span)))
+    }
+}
+
+impl Node {
+    // Replaces the syntax of the nodes parsed from the number at the span, spans after it are moved.
+    // Returns the number of replaced nodes.
+    fn edit_number(&mut self, span: Span, syntax: &Syntax, delta: isize) -> usize {
+        let mut changed = 0;
+        if self.span == span && matches!(self.syntax, Syntax::Real(..) | Syntax::Imaginary(..)) {
+            self.syntax = syntax.clone();
+            changed += 1;
+        }
+        self.span = self.span.shifted(span.end, delta);
+        for child in self.left.iter_mut().chain(self.right.iter_mut()) {
+            changed += child.edit_number(span, syntax, delta);
+        }
+        changed
+    }
+    fn shift(&mut self, position: usize, delta: isize) {
+        self.span = self.span.shifted(position, delta);
+        for child in self.left.iter_mut().chain(self.right.iter_mut()) {
+            child.shift(position, delta);
+        }
+    }
+    // Node at the path from this one, false is the left child and true the right one.
+    fn at(&mut self, path: &[bool]) -> &mut Node {
+        match path.split_first() {
+            Some((&right, rest)) => {
+                let child = match right {
+                    true => self.right.as_mut(),
+                    false => self.left.as_mut(),
+                };
+                child.expect("path leads to a missing child").at(rest)
+            }
+            None => self,
+        }
+    }
+    // First and last index of the tokens the node was parsed from, if its span covers them exactly.
+    fn tokens(&self, tokens: &[Lexeme]) -> Option<(usize, usize)> {
+        let first = tokens.partition_point(|lexeme| lexeme.span.start < self.span.start);
+        let end = tokens.partition_point(|lexeme| lexeme.span.end <= self.span.end);
+        match end > first && tokens[first].span.start == self.span.start && tokens[end - 1].span.end == self.span.end {
+            true => Some((first, end - 1)),
+            false => None,
+        }
+    }
+    // Path to the binary operator node parsed from the token at the index, its operands are right next to it.
+    fn operator_at(&self, tokens: &[Lexeme], index: usize, path: &mut Vec<bool>) -> Option<Vec<bool>> {
+        if let (Some(left), Some(right)) = (&self.left, &self.right) {
+            let operands = (left.tokens(tokens), right.tokens(tokens));
+            if let (Some((_, left_last)), Some((right_first, _))) = operands {
+                if left_last + 1 == index && right_first == index + 1 && Operator::binary(&tokens[index].token).is_some() {
+                    return Some(path.clone());
+                }
+            }
+        }
+        for (child, right) in [(&self.left, false), (&self.right, true)].iter() {
+            if let Some(child) = child {
+                path.push(*right);
+                let found = child.operator_at(tokens, index, path);
+                path.pop();
+                if found.is_some() {
+                    return found;
+                }
+            }
+        }
+        None
+    }
+    // Path to the innermost node between delimiters or commas that covers the tokens from start up to end,
+    // with the index of its first and last token. Inside of them a part is parsed the same way as on its own.
+    fn delimited_at(&self, tokens: &[Lexeme], start: usize, end: usize, path: &mut Vec<bool>) -> Option<(Vec<bool>, usize, usize)> {
+        let mut found = None;
+        for (child, right) in [(&self.left, false), (&self.right, true)].iter() {
+            let child = match child {
+                Some(child) => child,
+                None => continue,
+            };
+            let (first, last) = match child.tokens(tokens) {
+                Some(range) if range.0 <= start && end <= range.1 + 1 => range,
+                _ => continue,
+            };
+            path.push(*right);
+            let delimited = !right && first > 0 && last + 1 < tokens.len() && matches!(self.syntax,
+                Syntax::Parenthesis | Syntax::Absolute | Syntax::Bracket | Syntax::Brace | Syntax::Floor | Syntax::Ceil | Syntax::Norm |
+                Syntax::Function(_) | Syntax::Argument | Syntax::Sum(_) | Syntax::Product(_) | Syntax::Iterate(_) | Syntax::Condition
+            ) && Self::opens(&tokens[first - 1].token) && Self::closes(&tokens[last + 1].token);
+            found = child.delimited_at(tokens, start, end, path);
+            if found.is_none() && delimited {
+                found = Some((path.clone(), first, last));
+            }
+            path.pop();
+            if found.is_some() {
+                break;
+            }
+        }
+        found
+    }
+    fn opens(token: &Token) -> bool {
+        matches!(token,
+            Token::ParenthesisOpen | Token::AbsoluteOpen | Token::BracketOpen | Token::BraceOpen |
+            Token::FloorOpen | Token::CeilOpen | Token::NormOpen | Token::Comma
+        )
+    }
+    fn closes(token: &Token) -> bool {
+        matches!(token,
+            Token::ParenthesisClosed | Token::AbsoluteClosed | Token::BracketClosed | Token::BraceClosed |
+            Token::FloorClosed | Token::CeilClosed | Token::NormClosed | Token::Comma
+        )
+    }
+}
+
+
+
+// Nodes in the UBO, so a new tree only uploads the ones that changed.
+#[derive(Debug, Clone)]
+pub struct Uploaded {
+    pub nodes: Vec<NodeData>,
+    // Number of ways to reach each node from the root, a node reached more than once is shared by several subtrees.
+    paths: Vec<usize>,
+}
+impl Uploaded {
+    pub fn new(nodes: Vec<NodeData>) -> Self {
+        let mut paths = vec![0; nodes.len()];
+        if let Some(root) = paths.first_mut() {
+            *root = 1;
+        }
+        // Children come after their parents, so every path to a node is counted before its children are.
+        for index in 0..nodes.len() {
+            let count = paths[index];
+            for &child in [nodes[index].left_index, nodes[index].right_index].iter() {
+                if child != 0 && (child as usize) < paths.len() {
+                    paths[child as usize] += count;
+                }
+            }
+        }
+        Self {
+            nodes,
+            paths,
+        }
+    }
+    // Writes the new tree into the nodes in place, if it only differs from the uploaded one in nodes of its own,
+    // like numbers and operators that changed. The tree is compared with the nodes while walking it, so nothing is
+    // serialized again. Returns the indices of the changed nodes, or None if the tree has to be serialized again.
+    pub fn update_values<V: KeywordType, F: KeywordType>(&mut self, tree: &Node) -> Option<Vec<usize>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut changes = Vec::new();
+        self.compare::<V, F>(tree, 0, &mut Vec::new(), &mut changes)?;
+        let mut changed = Vec::new();
+        for (index, node) in changes {
+            // A shared node can change for one subtree only if another subtree reached it the same way.
+            if changed.contains(&index) {
+                continue;
+            }
+            self.nodes[index] = node;
+            changed.push(index);
+        }
+        Some(changed)
+    }
+    fn compare<V: KeywordType, F: KeywordType>(&self, node: &Node, index: usize, scope: &mut Vec<(String, usize)>, changes: &mut Vec<(usize, NodeData)>) -> Option<()> {
+        let old = *self.nodes.get(index)?;
+        let bound = match &node.syntax {
+            Syntax::Variable(name) => scope.iter().rev().find(|(other, _)| other == name).map(|&(_, index)| index),
+            _ => None,
+        };
+        let mut new = old;
+        new.syntax = match bound {
+            Some(_) => Syntax::BOUND,
+            None => node.syntax.serialize_syntax() as u16,
+        };
+        // The keyword of a loop is the end of its subtree, which stays the same with the structure.
+        new.keyword = match bound {
+            Some(index) => index as u16,
+            None if node.binding().is_some() => old.keyword,
+            None => node.syntax.serialize_keyword::<V, F>() as u16,
+        };
+        new.value = node.syntax.serialize_value();
+        let (left, right, old_syntax, new_syntax) = (old.left_index, old.right_index, old.syntax, new.syntax);
+        // Loops of one kind can't become another, their nodes are written in a different order.
+        let loops = [31, 32, 33];
+        if loops.contains(&old_syntax) != loops.contains(&new_syntax) || (old_syntax == Syntax::BOUND) != (new_syntax == Syntax::BOUND) {
+            return None;
+        }
+        let (old_value, new_value) = (old.value, new.value);
+        if (old_syntax, old.keyword, old_value) != (new_syntax, new.keyword, new_value) {
+            if self.paths[index] != 1 {
+                return None;
+            }
+            changes.push((index, new));
+        }
+
+        if let Some(name) = node.binding() {
+            scope.push((name.to_string(), index));
+        }
+        let (left_node, right_node) = node.serialized_children();
+        for (child, child_index) in [(left_node, left), (right_node, right)].iter() {
+            match (child, *child_index) {
+                (None, 0) => (),
+                (Some(child), child_index) if child_index != 0 => self.compare::<V, F>(child, child_index as usize, scope, changes)?,
+                _ => return None,
+            }
+        }
+        if node.binding().is_some() {
+            scope.pop();
+        }
+        Some(())
+    }
+    // Indices where the nodes differ from the uploaded ones, None if there is a different number of them.
+    pub fn changed(&self, nodes: &[NodeData]) -> Option<Vec<usize>> {
+        if nodes.len() != self.nodes.len() {
+            return None;
+        }
+        let fields = |node: &NodeData| (node.left_index, node.right_index, node.syntax, node.keyword, node.value);
+        Some((0..nodes.len()).filter(|&index| fields(&nodes[index]) != fields(&self.nodes[index])).collect())
+    }
+}
+
+impl Span {
+    // Moves the positions at or after the given one, for text that was inserted or removed there.
+    pub fn shifted(self, position: usize, delta: isize) -> Self {
+        let shift = |offset: usize| match offset >= position {
+            true => (offset as isize + delta).max(0) as usize,
+            false => offset,
+        };
+        Self::new(shift(self.start), shift(self.end))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ Change, Edit, Uploaded };
+    use super::super::diagnostic::Span;
+    use super::super::interpreter::FUNCTIONS;
+    use super::super::keyword::Keyword;
+    use super::super::parser::Expression;
+    use super::super::serializer::Empty;
+    use super::super::testing::{ random_tree, Xorshift };
+
+    #[test]
+    fn warnings_keep_the_fast_path() {
+        let mut keywords = Keyword::vars(&vec!["z"]);
+        keywords.append(&mut Keyword::funcs(&FUNCTIONS.to_vec()));
+        let old = Expression::parse("a = 2; sin = 1.5; z + 3", &keywords);
+        assert!(!old.diagnostics.is_empty() && old.is_valid());
+
+        let input = "a = 25; sin = 1.5; z + 3";
+        let (new, change) = old.reparse(&Edit::between(&old.input, input), &keywords);
+        let full = Expression::parse(input, &keywords);
+        assert!(matches!(change, Change::Value(_)));
+        assert_eq!(new.diagnostics, full.diagnostics);
+        assert!(new.tree.unwrap().same(&full.tree.unwrap()));
+    }
+
+    fn keywords() -> Vec<Keyword> {
+        let mut keywords = Keyword::vars(&vec!["z", "x"]);
+        keywords.append(&mut Keyword::funcs(&FUNCTIONS.to_vec()));
+        keywords
+    }
+
+    // Reparses the input with the edit and checks it against a full parse of the new input.
+    fn edit(input: &str, edited: &str) -> Change {
+        let keywords = keywords();
+        let old = Expression::parse(input, &keywords);
+        let (new, change) = old.reparse(&Edit::between(input, edited), &keywords);
+        let full = Expression::parse(edited, &keywords);
+        assert_eq!(new.input, full.input);
+        assert_eq!(new.diagnostics, full.diagnostics, "{} to {}", input, edited);
+        match (&new.tree, &full.tree) {
+            (Some(tree), Some(other)) => assert!(tree.same(other), "{} to {}: {:?}", input, edited, change),
+            (tree, other) => assert_eq!(tree.is_some(), other.is_some()),
+        }
+        change
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(edit("z + 3", "z - 3"), Change::Operator(Span::new(2, 3)));
+        assert_eq!(edit("if(z < 1 and x >= 2, z, 1)", "if(z < 1 and x <= 2, z, 1)"), Change::Operator(Span::new(15, 17)));
+        assert_eq!(edit("sin(z + 1) * 2", "sin(z - 1) * 2"), Change::Operator(Span::new(6, 7)));
+        // Precedence changes, and "*" can be a conjugate.
+        assert!(matches!(edit("z + 3", "z / 3"), Change::Subtree(_) | Change::Tree));
+        assert!(!matches!(edit("z * 3", "z + 3"), Change::Operator(_)));
+        // A minus that negates has no node of its own.
+        assert!(!matches!(edit("z + -3", "z + +3"), Change::Operator(_)));
+    }
+
+    #[test]
+    fn nested_subtrees() {
+        let input = "sin(z + 1) * (2z - |z + 3|)";
+        assert_eq!(edit(input, "sin(z^2 + 1) * (2z - |z + 3|)"), Change::Subtree(Span::new(4, 11)));
+        assert_eq!(edit(input, "sin(z + 1) * (2z - |z/2 + 3|)"), Change::Subtree(Span::new(20, 27)));
+        assert_eq!(edit(input, "sin(z + 1) * (2z x - |z + 3|)"), Change::Subtree(Span::new(14, 28)));
+        assert_eq!(edit("sum(k, 1, 3, (z + k))", "sum(k, 1, 3, (z k + 1))"), Change::Subtree(Span::new(14, 21)));
+        assert_eq!(edit("iter(3, w = 1, 2w + x)", "iter(3, w = 1, 2w^2 + x)"), Change::Subtree(Span::new(15, 23)));
+        assert_eq!(edit("if(z < 1, z, 2)", "if(z < 1, z^2, 2)"), Change::Subtree(Span::new(10, 13)));
+        assert_eq!(edit("pow(z, 1 + x)", "pow(z, 1 + x^2)"), Change::Subtree(Span::new(7, 14)));
+    }
+
+    #[test]
+    fn other_edits_parse_again() {
+        // Adding a bar changes how the others pair, so do delimiters and new definitions.
+        assert_eq!(edit("|z + 1| + 2", "|z + |1| + 2"), Change::Tree);
+        assert_eq!(edit("(z + 1) * 2", "(z + 1 * 2"), Change::Tree);
+        assert_eq!(edit("(z + 1) * 2", "(z + 1)) * (2"), Change::Tree);
+        assert_eq!(edit("(z + 1) * 2", "a = 2; (z + a) * 2"), Change::Tree);
+        // A bound name outside of its loop is an error the full parse reports.
+        assert_eq!(edit("sum(k, 1, 3, k) + (z)", "sum(k, 1, 3, k) + (z k)"), Change::Tree);
+    }
+
+    #[test]
+    fn random_edits() {
+        const SNIPPETS: [&str; 14] = ["", " ", "1", "2.5", "z", "x", "+", "-", "*", "^", "(", ")", "|", ","];
+        let keywords = keywords();
+        let mut random = Xorshift(0x5eed_cafe);
+        let mut reused = 0;
+        let mut valid = 0;
+        for _ in 0..1000 {
+            let input = random_tree(&mut random, 3).to_source();
+            let old = Expression::parse(&input, &keywords);
+            let boundaries: Vec<usize> = input.char_indices().map(|(index, _)| index).chain(Some(input.len())).collect();
+            let start = boundaries[random.below(boundaries.len())];
+            let end = boundaries[random.below(boundaries.len())].max(start);
+            let end = boundaries.iter().copied().filter(|&index| index >= start).min_by_key(|&index| index.abs_diff(end)).unwrap();
+            let text = SNIPPETS[random.below(SNIPPETS.len())];
+            let mut edited = input.clone();
+            edited.replace_range(start..end, text);
+
+            let (new, change) = old.reparse(&Edit::new(Span::new(start, end), text), &keywords);
+            let full = Expression::parse(&edited, &keywords);
+            assert_eq!(new.is_valid(), full.is_valid(), "{} to {}", input, edited);
+            if old.is_valid() && full.is_valid() {
+                valid += 1;
+            }
+            if change != Change::Tree {
+                reused += 1;
+                assert_eq!(new.diagnostics, full.diagnostics, "{} to {}", input, edited);
+                assert!(new.tree.unwrap().same(&full.tree.unwrap()), "{} to {}: {:?}", input, edited, change);
+            }
+        }
+        // Edits at the top level of the input parse it again, so not every valid edit can reuse the tree.
+        assert!(reused * 3 > valid, "only {} of {} valid edits reused the old tree", reused, valid);
+    }
+
+    #[test]
+    fn values_in_place() {
+        let keywords = keywords();
+        let check = |input: &str, edited: &str| {
+            let old = Expression::parse(input, &keywords);
+            let mut uploaded = Uploaded::new(old.serialize::<Empty, Empty>());
+            let (new, _) = old.reparse(&Edit::between(input, edited), &keywords);
+            let changed = uploaded.update_values::<Empty, Empty>(new.tree.as_ref().unwrap());
+            if changed.is_some() {
+                let nodes = new.serialize::<Empty, Empty>();
+                assert_eq!(format!("{:?}", uploaded.nodes), format!("{:?}", nodes), "{} to {}", input, edited);
+            }
+            changed
+        };
+                assert_eq!(check("z + 3", "z + 4"), Some(vec![2]));
+        assert_eq!(check("z + 3", "z - 3"), Some(vec![0]));
+        assert_eq!(check("sin(z + 1) * 2", "sin(z * 1) * 2"), Some(vec![2]));
+        assert_eq!(check("sum(k, 1, 3, k z)", "sum(k, 1, 4, k z)"), Some(vec![3]));
+        // Shared nodes and a different shape need a new serialization.
+        assert_eq!(check("(z + 2) * (z + 2)", "(z + 2) * (z + 3)"), None);
+        assert_eq!(check("z + 3", "z + 3 x"), None);
+    }
+}
@@ -1,11 +1,39 @@
 use std::io::BufRead;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use super::parser::{ Expression, Keyword, Node, Syntax };
+use super::definition::Definition;
+use super::keyword::Keyword;
+use super::parser::{ Expression, Node, Syntax };
 use super::complex::Complex;
+use super::dag::Dag;
+use super::scope::LOOP_LIMIT;
 
 
 
 
+// Functions the interpreter can evaluate, with their number of arguments.
+pub const FUNCTIONS: [(&str, usize); 18] = [
+    ("sin", 1),
+    ("cos", 1),
+    ("tan", 1),
+    ("asin", 1),
+    ("acos", 1),
+    ("atan", 1),
+    ("sqrt", 1),
+    ("log", 1),
+    ("exp", 1),
+    ("abs", 1),
+    ("arg", 1),
+    ("round", 1),
+    ("ceil", 1),
+    ("floor", 1),
+    ("pow", 2),
+    ("logb", 2),
+    ("lerp", 3),
+    ("atan2", 2),
+];
+
+
 pub fn sanitize_input(input: &str) -> String {
     input.replace(|c| {
         match c {
@@ -26,15 +54,20 @@ pub enum ReplCommand {
     SetVariable(String, Complex),
     RemoveVariable(String),
 }
+#[derive(Clone)]
 pub struct Intepreter {
     variables: HashMap<String, Complex>,
-    //functions: HashMap<String, dyn Fn(Complex) -> Complex>,
+    // User functions, their bodies only call built in functions.
+    functions: HashMap<String, Definition>,
+    // Names bound by loops and parameters while evaluating, the last one with a name hides the others.
+    bound: RefCell<Vec<(String, Complex)>>,
 }
 impl Intepreter {
     pub fn new() -> Self {
         Self {
             variables: HashMap::new(),
-            //functions: HashMap::new(),
+            functions: HashMap::new(),
+            bound: RefCell::new(Vec::new()),
         }
     }
 
@@ -54,17 +87,33 @@ impl Intepreter {
                 Ok(_) => (),
             };
             let input = sanitize_input(&input);
+            // ":latex z^2", ":mathml z^2" and ":derivative z^2" print the expression instead of evaluating it.
+            let (export, input) = match input.split_once(' ') {
+                Some((":latex", rest)) => (Some(Expression::to_latex as fn(&Expression) -> String), rest.to_string()),
+                Some((":mathml", rest)) => (Some(Expression::to_mathml as fn(&Expression) -> String), rest.to_string()),
+                Some((":derivative", rest)) => (Some(Expression::derivative_source as fn(&Expression) -> String), rest.to_string()),
+                _ => (None, input),
+            };
             match &input as &str {
                 "exit" => break,
                 _ => (),
             }
 
-            let vars = Keyword::vars(&self.variables.keys().map(|s| s as &str).collect());
-            let expr =  Expression::parse(&input, &vars);
-            if !expr.is_valid() {
+            let expr =  Expression::parse(&input, &self.keywords());
+            if expr.errors().next().is_some() {
                 println!("{}", expr.pretty_error());
                 continue;
             }
+            if let (Some(export), Some(_)) = (export, &expr.tree) {
+                println!("{}\n", export(&expr));
+                continue;
+            }
+            for definition in &expr.definitions {
+                self.define(definition);
+            }
+            if expr.tree.is_none() {
+                continue;
+            }
 
 
             for command in handler(expr.clone()) {
@@ -83,6 +132,32 @@ impl Intepreter {
     }
 
 
+    pub fn keywords(&self) -> Vec<Keyword> {
+        let mut keywords = Keyword::vars(&self.variables.keys().map(|s| s as &str).collect());
+        keywords.append(&mut Keyword::funcs(&FUNCTIONS.to_vec()));
+        // User functions hide built in ones with the same name.
+        keywords.retain(|keyword| !self.functions.contains_key(keyword.name()));
+        keywords.extend(self.functions.values().map(|definition| definition.keyword.clone()));
+        keywords
+    }
+    // Variables are evaluated right away, functions are stored with earlier user functions inlined,
+    // so redefining a function later doesn't change the ones using it.
+    pub fn define(&mut self, definition: &Definition) {
+        let body = match definition.resolve(&self.functions.values().cloned().collect()) {
+            Ok(body) => body,
+            Err(_) => return,
+        };
+        if definition.is_function() {
+            let mut definition = definition.clone();
+            definition.body = body;
+            self.variables.remove(definition.name());
+            self.functions.insert(definition.name().to_string(), definition);
+        } else {
+            let value = self.evaluate_node(&body);
+            self.functions.remove(definition.name());
+            self.set_variable(definition.name(), value);
+        }
+    }
     pub fn set_variable(&mut self, name: &str, value: Complex) {
         self.variables.entry(format!("{}", name))
             .and_modify( |old| *old = value)
@@ -98,68 +173,336 @@ impl Intepreter {
             None => Complex::zero(),
         }
     }
-    fn evaluate_node_left(&self, node: &Node) -> Complex {
+    // Same value as evaluate_node, but subtrees that are stored once in the DAG are also evaluated once.
+    pub fn evaluate_dag(&self, dag: &Dag) -> Complex {
+        let mut values = vec![None; dag.len()];
+        match dag.nodes.first() {
+            Some(root) => self.evaluate_shared(root, dag, &mut values),
+            None => Complex::zero(),
+        }
+    }
+    fn evaluate_shared(&self, node: &Node, dag: &Dag, values: &mut Vec<Option<Complex>>) -> Complex {
+        let index = match dag.index_of(node) {
+            Some(index) => index,
+            None => return self.evaluate_node(node),
+        };
+        if let Some(value) = values[index] {
+            return value;
+        }
+        let value = self.evaluate_parts(node, &mut |child| self.evaluate_shared(child, dag, values));
+        values[index] = Some(value);
+        value
+    }
+    fn evaluate_left(node: &Node, evaluate: &mut dyn FnMut(&Node) -> Complex) -> Complex {
         match &node.left {
-            Some(left)=> self.evaluate_node(&left),
+            Some(left)=> evaluate(left),
             None => Complex::zero(),
         }
     }
-    fn evaluate_node_right(&self, node: &Node) -> Complex {
+    fn evaluate_right(node: &Node, evaluate: &mut dyn FnMut(&Node) -> Complex) -> Complex {
         match &node.right {
-            Some(right)=> self.evaluate_node(&right),
+            Some(right)=> evaluate(right),
             None => Complex::zero(),
         }
     }
-    fn evaluate_node(&self, node: &Node) -> Complex {
+    pub fn evaluate_node(&self, node: &Node) -> Complex {
+        self.evaluate_parts(node, &mut |child| self.evaluate_node(child))
+    }
+    // Evaluates the node with the given function for the children outside of loop bodies,
+    // the bodies and user functions are evaluated in their own scope.
+    fn evaluate_parts(&self, node: &Node, evaluate: &mut dyn FnMut(&Node) -> Complex) -> Complex {
         match &node.syntax {
-            Syntax::Real(value) => Complex::real(*value),
-            Syntax::Imaginary(value) => Complex::imaginary(*value),
+            Syntax::Error => Complex::nan(),
+            Syntax::Real(value, _) => Complex::real(*value),
+            Syntax::Imaginary(value, _) => Complex::imaginary(*value),
 
             Syntax::Parenthesis => {
-                self.evaluate_node_left(&node)
+                Self::evaluate_left(node, evaluate)
             },
 
             Syntax::Addition => {
-                let lhs = self.evaluate_node_left(&node);
-                let rhs = self.evaluate_node_right(&node);
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
                 lhs + rhs
             },
             Syntax::Subtraction => {
-                let lhs = self.evaluate_node_left(&node);
-                let rhs = self.evaluate_node_right(&node);
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
                 lhs - rhs
             },
             Syntax::Multiplication => {
-                let lhs = self.evaluate_node_left(&node);
-                let rhs = self.evaluate_node_right(&node);
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
                 lhs * rhs
             },
             Syntax::Division => {
-                let lhs = self.evaluate_node_left(&node);
-                let rhs = self.evaluate_node_right(&node);
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
                 lhs / rhs
             },
             Syntax::Exponent => {
-                let lhs = self.evaluate_node_left(&node);
-                let rhs = self.evaluate_node_right(&node);
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
                 lhs.pow(rhs)
             },
             Syntax::Absolute => {
-                let lhs = self.evaluate_node_left(&node);
+                let lhs = Self::evaluate_left(node, evaluate);
                 Complex::real(lhs.abs())
             },
-            _ => Complex::zero(),
-
-
+            Syntax::Bracket | Syntax::Brace => {
+                Self::evaluate_left(node, evaluate)
+            },
+            Syntax::Floor => {
+                Self::evaluate_left(node, evaluate).floor()
+            },
+            Syntax::Ceil => {
+                Self::evaluate_left(node, evaluate).ceil()
+            },
+            // Euclidean norm, the same as the absolute value for a single complex number.
+            Syntax::Norm => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                Complex::real(lhs.abs())
+            },
+            // z! = gamma(z + 1)
+            Syntax::Factorial => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                (lhs + Complex::real(1.0)).gamma()
+            },
+            Syntax::Conjugate => {
+                Self::evaluate_left(node, evaluate).conjugate()
+            },
+            Syntax::Degree => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                lhs * Complex::real(std::f64::consts::PI / 180.0)
+            },
+            Syntax::Percent => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                lhs / Complex::real(100.0)
+            },
             Syntax::Variable(name) => {
+                if let Some((_, value)) = self.bound.borrow().iter().rev().find(|(bound, _)| bound == name) {
+                    return *value;
+                }
                 match self.variables.get(name) {
                     Some(value) => *value,
                     None => Complex::zero(),
                 }
             }
             Syntax::Function(name) => {
-                Complex::zero()
+                let arguments = node.arguments().iter()
+                    .map(|argument| evaluate(argument))
+                    .collect::<Vec<Complex>>();
+                match self.functions.get(name) {
+                    Some(definition) => {
+                        // Parameters hide variables with the same name, names bound by the caller aren't visible.
+                        let parameters = definition.parameters.iter().cloned().zip(arguments).collect();
+                        let caller = self.bound.replace(parameters);
+                        let value = self.evaluate_node(&definition.body);
+                        self.bound.replace(caller);
+                        value
+                    }
+                    None => self.evaluate_function(name, &arguments),
+                }
+            }
+            Syntax::Argument => {
+                Self::evaluate_left(node, evaluate)
+            }
+
+            // The body is evaluated in a scope with the bound name set, the bounds are rounded.
+            Syntax::Sum(name) | Syntax::Product(name) => {
+                let (start, end, body) = match node.arguments()[..] {
+                    [start, end, body] => (evaluate(start), evaluate(end), body),
+                    _ => return Complex::nan(),
+                };
+                let (mut result, sum) = match &node.syntax {
+                    Syntax::Sum(_) => (Complex::zero(), true),
+                    _ => (Complex::real(1.0), false),
+                };
+                let index = self.bind(name, Complex::zero());
+                let mut k = start.re.round();
+                let mut count = 0;
+                while k <= end.re.round() && count < LOOP_LIMIT {
+                    self.rebind(index, Complex::real(k));
+                    let value = self.evaluate_node(body);
+                    result = match sum {
+                        true => result + value,
+                        false => result * value,
+                    };
+                    k += 1.0;
+                    count += 1;
+                }
+                self.unbind(index);
+                result
             }
+            Syntax::Iterate(name) => {
+                let (count, start, body) = match node.arguments()[..] {
+                    [count, start, body] => (evaluate(count), evaluate(start), body),
+                    _ => return Complex::nan(),
+                };
+                let index = self.bind(name, start);
+                let mut value = start;
+                let count = count.re.round().max(0.0).min(LOOP_LIMIT as f64) as usize;
+                for _ in 0..count {
+                    self.rebind(index, value);
+                    value = self.evaluate_node(body);
+                }
+                self.unbind(index);
+                value
+            }
+
+            // Comparisons use the real part, "|z| < 1" compares the modulus.
+            Syntax::Less => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(lhs.re < rhs.re)
+            }
+            Syntax::Greater => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(lhs.re > rhs.re)
+            }
+            Syntax::LessEqual => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(lhs.re <= rhs.re)
+            }
+            Syntax::GreaterEqual => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(lhs.re >= rhs.re)
+            }
+            // Equality compares both parts.
+            Syntax::Equal => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(lhs.re == rhs.re && lhs.im == rhs.im)
+            }
+            Syntax::NotEqual => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(lhs.re != rhs.re || lhs.im != rhs.im)
+            }
+            Syntax::And => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(Self::truth(lhs) && Self::truth(rhs))
+            }
+            Syntax::Or => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                let rhs = Self::evaluate_right(node, evaluate);
+                Self::boolean(Self::truth(lhs) || Self::truth(rhs))
+            }
+            Syntax::Not => {
+                let lhs = Self::evaluate_left(node, evaluate);
+                Self::boolean(!Self::truth(lhs))
+            }
+            // Only the chosen branch is evaluated.
+            Syntax::Condition => {
+                let arguments = node.arguments();
+                let branch = match Self::truth(Self::evaluate_left(node, evaluate)) {
+                    true => arguments.get(1),
+                    false => arguments.get(2),
+                };
+                match branch {
+                    Some(branch) => evaluate(branch),
+                    None => Complex::nan(),
+                }
+            }
+            _ => Complex::zero(),
+        }
+    }
+    fn boolean(value: bool) -> Complex {
+        match value {
+            true => Complex::real(1.0),
+            false => Complex::zero(),
+        }
+    }
+    fn truth(value: Complex) -> bool {
+        value.re != 0.0
+    }
+    // Loop bodies see the bound name until unbind, the index stays valid since nested scopes unbind first.
+    fn bind(&self, name: &str, value: Complex) -> usize {
+        let mut bound = self.bound.borrow_mut();
+        bound.push((name.to_string(), value));
+        bound.len() - 1
+    }
+    fn rebind(&self, index: usize, value: Complex) {
+        self.bound.borrow_mut()[index].1 = value;
+    }
+    fn unbind(&self, index: usize) {
+        self.bound.borrow_mut().truncate(index);
+    }
+    fn evaluate_function(&self, name: &str, arguments: &[Complex]) -> Complex {
+        match (name, arguments) {
+            ("sin", [z]) => z.sin(),
+            ("cos", [z]) => z.cos(),
+            ("tan", [z]) => z.tan(),
+            ("asin", [z]) => z.asin(),
+            ("acos", [z]) => z.acos(),
+            ("atan", [z]) => z.atan(),
+            ("sqrt", [z]) => z.sqrt(),
+            ("log", [z]) => z.log(),
+            ("exp", [z]) => z.exp(),
+            ("abs", [z]) => Complex::real(z.abs()),
+            ("arg", [z]) => Complex::real(z.arg()),
+            ("round", [z]) => z.round(),
+            ("ceil", [z]) => z.ceil(),
+            ("floor", [z]) => z.floor(),
+            ("pow", [z, exponent]) => z.pow(*exponent),
+            ("logb", [z, base]) => z.log() / base.log(),
+            ("lerp", [a, b, t]) => *a + (*b - *a) * *t,
+            ("atan2", [y, x]) => y.atan2(*x),
+            _ => Complex::nan(),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::Intepreter;
+    use super::super::complex::Complex;
+    use super::super::parser::Expression;
+
+    // "<" and the others look at the real part, bars around both sides compare the moduli instead.
+    #[test]
+    fn comparisons_use_real_parts() {
+        let mut interpreter = Intepreter::new();
+        interpreter.set_variable("z", Complex::new(1.0, 5.0));
+        let keywords = interpreter.keywords();
+        let cases = [
+            ("z < 2", true), ("|z| < 2", false), ("z > 2i", true), ("|z| > |2i|", true),
+            ("z <= 1 - 5i", true), ("z >= 1 + 9i", true), ("z == 1", false), ("z != 1", true),
+        ];
+        for &(condition, expected) in &cases {
+            let input = format!("if({}, 1, 0)", condition);
+            let expression = Expression::parse(&input, &keywords);
+            assert!(expression.is_valid(), "'{}': {}", input, expression.pretty_error());
+            assert_eq!(interpreter.evaluate(&expression), Complex::real(if expected { 1.0 } else { 0.0 }), "'{}'", input);
+        }
+    }
+
+    // Loop names hide variables and outer loops, user functions only see their parameters and the variables.
+    #[test]
+    fn bound_names_are_scoped() {
+        let mut interpreter = Intepreter::new();
+        for definition in Expression::parse("k = 10; f(w) = w + k", &interpreter.keywords()).definitions {
+            interpreter.define(&definition);
+        }
+        let keywords = interpreter.keywords();
+        let cases = [
+            ("sum(k, 1, 3, k)", 6.0),
+            ("sum(k, 1, 2, sum(k, 1, 3, k)) + k", 22.0),
+            ("prod(k, 1, 3, sum(j, 1, k, j))", 18.0),
+            ("sum(k, 1, 3, f(k))", 36.0),
+            ("iter(3, w = 1, 2w + k)", 78.0),
+            ("iter(3, 1, 2z + k)", 78.0),
+        ];
+        for &(input, expected) in &cases {
+            let expression = Expression::parse(input, &keywords);
+            assert!(expression.is_valid(), "'{}': {}", input, expression.pretty_error());
+            assert_eq!(interpreter.evaluate(&expression), Complex::real(expected), "'{}'", input);
         }
     }
 }
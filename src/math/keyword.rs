@@ -0,0 +1,134 @@
+use serde::{ Serialize, Deserialize };
+
+
+
+// Functions and their inverse, "sin^-1(z)" is parsed as "asin(z)".
+pub const INVERSES: [(&str, &str); 4] = [
+    ("sin", "asin"),
+    ("cos", "acos"),
+    ("tan", "atan"),
+    ("exp", "log"),
+];
+
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Keyword {
+    // Name and number of arguments.
+    Function(String, usize),
+    Variable(String),
+}
+impl Keyword {
+    pub fn vars(input: &Vec<&str>) -> Vec<Self> {
+        input.iter().map(|&name| {
+            Self::Variable(name.to_string())
+        }).collect()
+    }
+    pub fn funcs(input: &Vec<(&str, usize)>) -> Vec<Self> {
+        input.iter().map(|&(name, arity)| {
+            Self::Function(name.to_string(), arity)
+        }).collect()
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Function(name, _) => name,
+            Self::Variable(name) => name,
+        }
+    }
+
+    pub fn inverse(name: &str) -> Option<&'static str> {
+        INVERSES.iter().find_map(|&(function, inverse)| {
+            match name {
+                _ if name == function => Some(inverse),
+                _ if name == inverse => Some(function),
+                _ => None,
+            }
+        })
+    }
+
+    // Spelling of an identifier that is used for lookups, subscript digits become normal digits.
+    pub fn normalize(name: &str) -> String {
+        name.chars().map(Self::subscript).collect()
+    }
+    pub fn subscript(c: char) -> char {
+        match c {
+            '₀'..='₉' => std::char::from_digit(c as u32 - '₀' as u32, 10).unwrap_or(c),
+            _ => c,
+        }
+    }
+}
+
+
+
+// Sorted table of keywords, so the lexer can find every keyword an identifier starts with.
+pub struct Keywords {
+    // Sorted by the normalized name, a name is in here more than once if it was defined twice.
+    table: Vec<(String, Keyword)>,
+}
+impl Keywords {
+    pub fn new(keywords: &[Keyword]) -> Self {
+        let mut table: Vec<(String, Keyword)> = keywords.iter()
+            .map(|keyword| (Keyword::normalize(keyword.name()), keyword.clone()))
+            .collect();
+        table.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+        Self {
+            table,
+        }
+    }
+
+    // All keywords with exactly this name.
+    pub fn get(&self, name: &str) -> &[(String, Keyword)] {
+        let start = self.table.partition_point(|(other, _)| other.as_str() < name);
+        let end = self.table.partition_point(|(other, _)| other.as_str() <= name);
+        &self.table[start..end]
+    }
+    // The imaginary unit can be part of a split identifier like "zi" as well.
+    pub fn contains(&self, name: &str) -> bool {
+        name == "i" || !self.get(name).is_empty()
+    }
+
+
+    // Splits an identifier into known names and returns the length of each part in characters.
+    // A known name is never split, otherwise longer parts are preferred, so "pizr" is "pi zr".
+    // The second result is another way to split it, if there is one.
+    pub fn split(&self, identifier: &[char]) -> Option<(Vec<usize>, Option<Vec<usize>>)> {
+        let name: String = identifier.iter().collect();
+        if self.contains(&name) {
+            return Some((vec![identifier.len()], None));
+        }
+        let mut results = Vec::new();
+        let mut dead = vec![false; identifier.len()];
+        self.split_next(identifier, 0, &mut Vec::new(), &mut dead, &mut results);
+
+        let mut results = results.into_iter();
+        results.next().map(|first| (first, results.next()))
+    }
+    // Depth first search that stops after two results,
+    // positions that can't be split are remembered so the search doesn't blow up.
+    fn split_next(&self, identifier: &[char], start: usize, parts: &mut Vec<usize>, dead: &mut Vec<bool>, results: &mut Vec<Vec<usize>>) {
+        if start == identifier.len() {
+            results.push(parts.clone());
+            return;
+        }
+        if dead[start] {
+            return;
+        }
+        let found = results.len();
+        for end in (start + 1..=identifier.len()).rev() {
+            let part: String = identifier[start..end].iter().collect();
+            if !self.contains(&part) {
+                continue;
+            }
+            parts.push(end - start);
+            self.split_next(identifier, end, parts, dead, results);
+            parts.pop();
+            if results.len() >= 2 {
+                return;
+            }
+        }
+        if results.len() == found {
+            dead[start] = true;
+        }
+    }
+}
@@ -1,11 +1,37 @@
 pub mod complex;
+pub mod completion;
+pub mod dag;
+pub mod definition;
+pub mod derivative;
+pub mod diagnostic;
+pub mod dot;
+pub mod export;
 pub mod extended;
+pub mod fold;
+pub mod incremental;
 pub mod interpreter;
+pub mod keyword;
+pub mod newton;
 pub mod parser;
+pub mod printer;
+pub mod save;
+pub mod scope;
 pub mod serializer;
+pub mod simplify;
+#[cfg(test)]
+pub mod testing;
+pub mod types;
 
 pub use complex::*;
+pub use completion::*;
+pub use dag::*;
+pub use diagnostic::*;
+pub use dot::*;
 pub use extended::*;
+pub use incremental::*;
 pub use interpreter::*;
+pub use keyword::*;
+pub use newton::*;
 pub use parser::*;
 pub use serializer::*;
+pub use simplify::*;
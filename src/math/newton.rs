@@ -0,0 +1,251 @@
+use super::complex::Complex;
+use super::dag::Dag;
+use super::interpreter::Intepreter;
+use super::parser::Expression;
+use super::serializer::{ KeywordType, NodeData };
+use serde::Serialize;
+
+
+
+// The shader has room for this many roots, it gets it as MAX_ROOTS. The ones closest to 0 are kept.
+pub const MAX_ROOTS: usize = 16;
+// Steps before a point counts as not converging, the shader gets it as MAX_ITERATIONS.
+pub const MAX_ITERATIONS: usize = 200;
+// Starting points of the root search are a grid over this square around 0.
+const ROOT_SEARCH: f64 = 4.0;
+const ROOT_GRID: usize = 24;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NewtonSettings {
+    // Every step is scaled by it, 1 is Newton's method itself.
+    pub relaxation: f64,
+    // The iteration stops once a step is shorter than this.
+    pub tolerance: f64,
+    pub max_iterations: usize,
+}
+impl Default for NewtonSettings {
+    fn default() -> Self {
+        Self {
+            relaxation: 1.0,
+            tolerance: 1e-4,
+            max_iterations: MAX_ITERATIONS,
+        }
+    }
+}
+
+// Where a starting point ends up, root is an index into Newton::roots.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NewtonPoint {
+    pub root: Option<usize>,
+    pub iterations: usize,
+}
+
+// Newton's method "z - relaxation * p(z) / p'(z)" for the expression p, the CPU version of the shader's Newton mode.
+#[derive(Clone)]
+pub struct Newton {
+    pub function: Expression,
+    pub derivative: Expression,
+    pub roots: Vec<Complex>,
+    pub settings: NewtonSettings,
+    // Values of the variables other than z.
+    interpreter: Intepreter,
+}
+impl Newton {
+    // The derivative holds the diagnostics when the expression is invalid or has no derivative,
+    // there are no roots then.
+    pub fn new(function: &Expression, interpreter: Intepreter, settings: NewtonSettings) -> Self {
+        let mut function = function.clone();
+        function.optimize();
+        let derivative = function.derivative("z");
+        let mut newton = Self {
+            function,
+            derivative,
+            roots: Vec::new(),
+            settings,
+            interpreter,
+        };
+        if newton.is_valid() {
+            newton.roots = newton.find_roots();
+        }
+        newton
+    }
+    pub fn is_valid(&self) -> bool {
+        self.function.is_valid() && self.derivative.is_valid()
+    }
+
+
+    // Returns where the iteration stopped and the number of steps, None if it didn't converge.
+    fn converge(&self, start: Complex) -> Option<(Complex, usize)> {
+        // The derivative often repeats parts of the function, like the shader each part is only evaluated once.
+        let (function, derivative) = match (&self.function.tree, &self.derivative.tree) {
+            (Some(function), Some(derivative)) => (Dag::new(function), Dag::new(derivative)),
+            _ => return None,
+        };
+        let mut interpreter = self.interpreter.clone();
+        let mut z = start;
+        for iteration in 0..self.settings.max_iterations {
+            interpreter.set_variable("z", z);
+            let step = Complex::real(self.settings.relaxation)
+                * (interpreter.evaluate_dag(&function) / interpreter.evaluate_dag(&derivative));
+            if !step.is_finite() {
+                return None;
+            }
+            z = z - step;
+            if step.abs() < self.settings.tolerance {
+                return Some((z, iteration + 1));
+            }
+        }
+        None
+    }
+    // Points that converged this close to a root belong to it. Multiple roots and small relaxations
+    // converge slowly, so the point can still be several steps away from the root.
+    fn root_radius(&self) -> f64 {
+        self.settings.tolerance.sqrt()
+    }
+    // Same as the loop in the shader, the nearest root within the radius.
+    pub fn iterate(&self, start: Complex) -> NewtonPoint {
+        match self.converge(start) {
+            Some((z, iterations)) => NewtonPoint {
+                root: self.roots.iter()
+                    .enumerate()
+                    .map(|(index, root)| (index, (*root - z).abs()))
+                    .filter(|&(_, distance)| distance < self.root_radius())
+                    .min_by(|lhs, rhs| lhs.1.partial_cmp(&rhs.1).unwrap())
+                    .map(|(index, _)| index),
+                iterations,
+            },
+            None => NewtonPoint {
+                root: None,
+                iterations: self.settings.max_iterations,
+            },
+        }
+    }
+    // Runs the iteration from a grid of starting points, points closer than the root radius are the same root.
+    // The one with the smallest |p(z)| is kept. Roots are sorted by modulus, then by argument,
+    // so the colors don't depend on the order they were found in. Moduli within the radius count as equal,
+    // the roots of "z^3 - 1" would be sorted by their rounding errors otherwise.
+    fn find_roots(&self) -> Vec<Complex> {
+        let mut interpreter = self.interpreter.clone();
+        let mut residual = |z: Complex| {
+            interpreter.set_variable("z", z);
+            interpreter.evaluate(&self.function).abs()
+        };
+        let mut roots: Vec<(Complex, f64)> = Vec::new();
+        let cell = 2.0 * ROOT_SEARCH / ROOT_GRID as f64;
+        for row in 0..ROOT_GRID {
+            for column in 0..ROOT_GRID {
+                // Cell centers, 0 and the axes are often critical points, like for "z^3 - 1".
+                let start = Complex::new(
+                    -ROOT_SEARCH + (column as f64 + 0.5) * cell,
+                    -ROOT_SEARCH + (row as f64 + 0.5) * cell,
+                );
+                let z = match self.converge(start) {
+                    Some((z, _)) => z,
+                    None => continue,
+                };
+                let error = residual(z);
+                if !error.is_finite() {
+                    continue;
+                }
+                match roots.iter_mut().find(|(root, _)| (*root - z).abs() < self.root_radius()) {
+                    Some(found) if error < found.1 => *found = (z, error),
+                    Some(_) => (),
+                    None => roots.push((z, error)),
+                }
+            }
+        }
+        let mut roots: Vec<Complex> = roots.into_iter().map(|(root, _)| root).collect();
+        let key = |root: &Complex| ((root.abs() / self.root_radius()).round(), root.arg());
+        roots.sort_by(|lhs, rhs| key(lhs).partial_cmp(&key(rhs)).unwrap());
+        roots.truncate(MAX_ROOTS);
+        roots
+    }
+
+
+    // Nodes of the function followed by the nodes of its derivative, with the index where the derivative starts.
+    pub fn serialize<V: KeywordType, F: KeywordType>(&self) -> (Vec<NodeData>, usize) {
+        let mut nodes = self.function.serialize::<V, F>();
+        let start = nodes.len();
+        if let Some(tree) = &self.derivative.tree {
+            tree.serialize_into::<V, F>(&mut nodes);
+        }
+        (nodes, start)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ Newton, NewtonSettings };
+    use super::super::complex::Complex;
+    use super::super::interpreter::Intepreter;
+    use super::super::parser::{ Expression, Literal, Node, Syntax };
+    use super::super::testing::Xorshift;
+
+    fn number(value: Complex) -> Node {
+        Node::from(
+            Node::from_syntax(Syntax::Real(value.re, Literal::Decimal)),
+            Node::from_syntax(Syntax::Imaginary(value.im, Literal::Decimal)),
+            Syntax::Addition,
+        )
+    }
+
+    // Builds polynomials from random roots, some of them repeated, checks that every root is found,
+    // and that starting next to a root converges to it for random relaxations.
+    #[test]
+    fn finds_roots() {
+        let mut random = Xorshift(0x2545f491);
+        // Roots are on a grid, so they are far apart compared to the root radius.
+        let point = |random: &mut Xorshift| (random.below(7) as f64 - 3.0) / 2.0;
+
+        for _ in 0..20 {
+            let mut expected: Vec<Complex> = Vec::new();
+            let mut tree: Option<Node> = None;
+            for _ in 0..1 + random.below(5) {
+                let root = match random.below(4) {
+                    0 if !expected.is_empty() => expected[random.below(expected.len())],
+                    _ => Complex::new(point(&mut random), point(&mut random)),
+                };
+                if !expected.contains(&root) {
+                    expected.push(root);
+                }
+                let factor = Node::from(Node::from_syntax(Syntax::Variable("z".to_string())), number(root), Syntax::Subtraction);
+                tree = Some(match tree {
+                    Some(tree) => Node::from(tree, factor, Syntax::Multiplication),
+                    None => factor,
+                });
+            }
+            let tree = tree.unwrap();
+            let function = Expression {
+                input: tree.to_source(),
+                tree: Some(tree),
+                definitions: Vec::new(),
+                diagnostics: Vec::new(),
+            };
+            let settings = NewtonSettings {
+                relaxation: 0.5 + random.below(11) as f64 / 10.0,
+                tolerance: 1e-8,
+                max_iterations: 400,
+            };
+            let newton = Newton::new(&function, Intepreter::new(), settings);
+            assert!(newton.is_valid(), "'{}' has no derivative: {}", function.input, newton.derivative.pretty_error());
+
+            let found = |root: &Complex| newton.roots.iter().position(|other| (*other - *root).abs() < newton.root_radius());
+            let roots: Vec<String> = newton.roots.iter().map(|root| format!("{}", root)).collect();
+            assert!(
+                newton.roots.len() == expected.len() && expected.iter().all(|root| found(root).is_some()),
+                "'{}' has the roots [{}]", function.input, roots.join(", "),
+            );
+            for root in &expected {
+                let start = *root + Complex::new(0.01, -0.01);
+                let point = newton.iterate(start);
+                assert_eq!(
+                    point.root, found(root),
+                    "'{}' with relaxation {} goes from {} to the wrong root after {} iterations, expected {}",
+                    function.input, settings.relaxation, start, point.iterations, root,
+                );
+            }
+        }
+    }
+}
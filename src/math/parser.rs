@@ -2,65 +2,36 @@ use std::iter::Peekable;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fmt::Display;
-use super::complex::Complex;
+use serde::{ Serialize, Deserialize };
+use super::definition::Definition;
+use super::keyword::{ Keyword, Keywords };
+use super::diagnostic::{ Diagnostic, Span };
+use super::save::number;
+use super::scope::ITERATE_BINDING;
+use super::types::Type;
 
 
 
-/*
-// Validate duplicate definition in wrapper struct araung Vec<KeywordType>
-pub enum KeywordError {
-    DuplicateVariable(String),
-    DuplicateFunction(String),
+// Notation a number was written in, kept so it can be printed back the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Literal {
+    Decimal,
+    Scientific,
+    Hexadecimal,
+    Binary,
+    // Constants like π and ∞.
+    Symbol(char),
 }
-pub struct Keywords {
-    definitions: Result<Vec<KeywordType>, Vec<KeywordError>>
-}
-impl Keywords {
-    pub fn is_valid() -> bool {
-        false
-    }
-
-    pub fn new(variables: Vec<String>, functions: Vec<String>) -> Self {
-
-    }
-    pub fn vars(input: Vec<String>) -> Option<Self> {
-        let vars = input.iter().map(|name| {
-            Self::Variable(name)
-        }).collect();
-
-        
-    }
-    pub fn funcs(input: Vec<String>) -> Option<Self> {
-        input.iter().map(|name| {
-            Self::Function(name)
-        }).collect()
-    }
-
-
-    pub fn 
-
-    fn validate(&self) {
-
-    }
-}
-impl std::ops::Add {
-}*/
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum Keyword {
-    Function(String),
-    Variable(String),
-}
-impl Keyword {
-    pub fn vars(input: &Vec<&str>) -> Vec<Self> {
-        input.iter().map(|&name| {
-            Self::Variable(format!("{}", name))
-        }).collect()
-    }
-    pub fn funcs(input: &Vec<&str>) -> Vec<Self> {
-        input.iter().map(|&name| {
-            Self::Function(format!("{}", name))
-        }).collect()
+impl Literal {
+    pub fn format(&self, value: f64) -> String {
+        let integer = value >= 0.0 && value.fract() == 0.0 && value < u64::MAX as f64;
+        match self {
+            Self::Symbol(symbol) => format!("{}", symbol),
+            Self::Scientific => format!("{:e}", value),
+            Self::Hexadecimal if integer => format!("{:#x}", value as u64),
+            Self::Binary if integer => format!("{:#b}", value as u64),
+            _ => format!("{}", value),
+        }
     }
 }
 
@@ -68,10 +39,10 @@ impl Keyword {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Real(f64),
-    Imaginary(f64),
+    Real(f64, Literal),
+    Imaginary(f64, Literal),
     Variable(String),
-    Function(String),
+    Function(String, usize),
     Invalid(TokenError),
 
     Addition,
@@ -79,193 +50,503 @@ pub enum Token {
     Multiplication,
     Division,
     Exponent,
+    SquareRoot,
+    // Postfix operators, "*" is a conjugate only when nothing follows it that it could multiply.
+    Factorial,
+    Conjugate,
+    Degree,
+    Percent,
+    Comma,
+    Semicolon,
+    Assignment,
+
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    If,
+    Question,
+    Colon,
+    // Loops, "sum(k, a, b, expr)", "prod(k, a, b, expr)", "iter(n, w = z0, expr)" and "iter(n, z0, expr)".
+    Sum,
+    Product,
+    Iterate,
 
     ParenthesisOpen,
     ParenthesisClosed,
     
     AbsoluteOpen,
     AbsoluteClosed,
+    BracketOpen,
+    BracketClosed,
+    BraceOpen,
+    BraceClosed,
+    FloorOpen,
+    FloorClosed,
+    CeilOpen,
+    CeilClosed,
+    NormOpen,
+    NormClosed,
 }
 impl Token {
-    pub fn parse(input: &str, keywords: &Vec<Keyword>) -> Vec<Self> {
-        let mut result = Vec::new();
-        let mut delimiters: HashMap<char, bool> = HashMap::new();
+    pub fn parse(input: &str, keywords: &[Keyword]) -> Vec<Lexeme> {
+        Self::parse_diagnostics(input, keywords).0
+    }
+    // Also returns warnings for identifiers that can be split into keywords in more than one way.
+    pub fn parse_diagnostics(input: &str, keywords: &[Keyword]) -> (Vec<Lexeme>, Vec<Diagnostic>) {
+        let mut result: Vec<Lexeme> = Vec::new();
+        let mut warnings: Vec<Diagnostic> = Vec::new();
+        let mut delimiters: HashMap<char, usize> = HashMap::new();
+        let keywords = Keywords::new(keywords);
     
-        let mut index: isize = 0;
-        let mut iter = input.chars().peekable();
-        while let Some(&c) = iter.peek() {
-            match c {
-                ' ' => { index -= 1; iter.next(); },
+        let mut iter = input.char_indices().peekable();
+        while let Some(&(start, c)) = iter.peek() {
+            let token = match c {
+                _ if c.is_whitespace() => { iter.next(); continue; },
                 
                 'a'..='z' | 'A'..='Z' => {
-                    result.push(Token::tokenize_keyword(
-                        c, 
-                        &mut iter, 
-                        index as usize,
-                        &keywords,
-                    ));
+                    let (mut lexemes, warning) = Token::tokenize_keyword(&mut iter, &keywords, input.len());
+                    result.append(&mut lexemes);
+                    warnings.extend(warning);
+                    continue;
                 }
                 '0'..='9' | '.' => {
-                    match result.last() {
-                        Some(Token::Real(_)) | Some(Token::Imaginary(_)) => {
-                            result.push(Token::Invalid(TokenError::InvalidNumber(index as usize)));
-                            iter.next(); 
-                        }
-                        _ => {
-                            result.push(Token::tokenize_number(
-                                c, 
-                                &mut iter, 
-                                index as usize,
-                            ));
+                    let token = Token::tokenize_number(c, &mut iter);
+                    // Superscript exponents can be followed by a number, like in "sin⁻¹ 0.5".
+                    let exponent = match result.last() {
+                        Some(lexeme) => input[lexeme.span.start..].starts_with(|c| Token::superscript(c).is_some()),
+                        None => false,
+                    };
+                    match result.last().map(|lexeme| &lexeme.token) {
+                        Some(Token::Real(..)) | Some(Token::Imaginary(..)) if !exponent => {
+                            Token::Invalid(TokenError::InvalidNumber)
                         }
+                        _ => token,
                     }
                 }
 
-                '(' => { result.push(Token::ParenthesisOpen);      iter.next(); },
-                ')' => { result.push(Token::ParenthesisClosed);    iter.next(); },
-                '+' => { result.push(Token::Addition);             iter.next(); },
-                '-' => { result.push(Token::Subtraction);          iter.next(); },
-                '*' => { result.push(Token::Multiplication);       iter.next(); },
-                '/' => { result.push(Token::Division);             iter.next(); },
-                '^' => { result.push(Token::Exponent);             iter.next(); },
+                '(' => { iter.next(); Token::ParenthesisOpen },
+                ')' => { iter.next(); Token::ParenthesisClosed },
+                '+' => { iter.next(); Token::Addition },
+                '-' | '−' => { iter.next(); Token::Subtraction },
+                '*' | '×' | '·' | '⋅' => { iter.next(); Token::Multiplication },
+                '/' | '÷' => { iter.next(); Token::Division },
+                '^' => { iter.next(); Token::Exponent },
+                '√' => { iter.next(); Token::SquareRoot },
+                '\'' | '′' => { iter.next(); Token::Conjugate },
+                '°' => { iter.next(); Token::Degree },
+                '%' => { iter.next(); Token::Percent },
+
+                'π' => { iter.next(); Token::Real(std::f64::consts::PI, Literal::Symbol(c)) },
+                'τ' => { iter.next(); Token::Real(2.0 * std::f64::consts::PI, Literal::Symbol(c)) },
+                'φ' => { iter.next(); Token::Real((1.0 + 5.0_f64.sqrt()) / 2.0, Literal::Symbol(c)) },
+                // "∞i" is a single number, "∞ * i" would have a NaN real part.
+                '∞' => { iter.next(); Token::tokenize_imaginary(&mut iter, f64::INFINITY, Literal::Symbol(c)) },
+                _ if Token::superscript(c).is_some() => {
+                    let mut lexemes = Token::tokenize_superscript(&mut iter, input.len());
+                    result.append(&mut lexemes);
+                    continue;
+                }
+                ',' => { iter.next(); Token::Comma },
+                ';' => { iter.next(); delimiters.clear(); Token::Semicolon },
+                '=' => { iter.next(); Token::tokenize_comparison(&mut iter, Token::Assignment, Token::Equal) },
+                '<' => { iter.next(); Token::tokenize_comparison(&mut iter, Token::Less, Token::LessEqual) },
+                '>' => { iter.next(); Token::tokenize_comparison(&mut iter, Token::Greater, Token::GreaterEqual) },
+                '!' => { iter.next(); Token::tokenize_comparison(&mut iter, Token::Factorial, Token::NotEqual) },
+                '≤' => { iter.next(); Token::LessEqual },
+                '≥' => { iter.next(); Token::GreaterEqual },
+                '≠' => { iter.next(); Token::NotEqual },
+                '∧' => { iter.next(); Token::And },
+                '∨' => { iter.next(); Token::Or },
+                '¬' => { iter.next(); Token::Not },
+                '?' => { iter.next(); Token::Question },
+                ':' => { iter.next(); Token::Colon },
+
+                '[' => { iter.next(); Token::BracketOpen },
+                ']' => { iter.next(); Token::BracketClosed },
+                '{' => { iter.next(); Token::BraceOpen },
+                '}' => { iter.next(); Token::BraceClosed },
+                '⌊' => { iter.next(); Token::FloorOpen },
+                '⌋' => { iter.next(); Token::FloorClosed },
+                '⌈' => { iter.next(); Token::CeilOpen },
+                '⌉' => { iter.next(); Token::CeilClosed },
     
                 '|' => {
-                    result.push(Token::tokenize_delimiter( 
+                    iter.next(); 
+                    Token::tokenize_delimiter( 
                         '|', 
                         &Token::AbsoluteOpen, &Token::AbsoluteClosed, 
                         &mut delimiters,
-                    ));
+                        result.last().map(|lexeme| &lexeme.token),
+                    )
+                },
+                '‖' => {
                     iter.next(); 
+                    Token::tokenize_delimiter( 
+                        '‖', 
+                        &Token::NormOpen, &Token::NormClosed, 
+                        &mut delimiters,
+                        result.last().map(|lexeme| &lexeme.token),
+                    )
                 },
     
                 _ => { 
-                    result.push(Token::Invalid(TokenError::InvalidCharacter(index as usize))); 
                     iter.next(); 
+                    Token::Invalid(TokenError::InvalidCharacter)
                 },
             };
-            index += 1;
+            let end = match iter.peek() {
+                Some(&(end, _)) => end,
+                None => input.len(),
+            };
+            result.push(Lexeme::new(token, Span::new(start, end)));
         }
-        result
+        (result, warnings)
     }
-    fn tokenize_number<T>(first: char, iter: &mut Peekable<T>, index: usize) -> Token
-        where T: Iterator<Item = char>
+    // Numbers are decimals with an optional exponent ("2.5e-3"),
+    // or hexadecimal ("0x1f") and binary ("0b101") integers.
+    // Any of them can be suffixed with "i" to make them imaginary.
+    fn tokenize_number<T>(first: char, iter: &mut Peekable<T>) -> Token
+        where T: Iterator<Item = (usize, char)> + Clone
     {
-        let mut buffer = first.to_string();
-    
         iter.next();
-        while let Some(&c) = iter.peek() {
+        let radix = match (first, iter.peek()) {
+            ('0', Some((_, 'x'))) | ('0', Some((_, 'X'))) => 16,
+            ('0', Some((_, 'b'))) | ('0', Some((_, 'B'))) => 2,
+            _ => 10,
+        };
+        // Only treat it as a prefix if a digit follows, otherwise "0b" is zero times b.
+        let mut ahead = iter.clone();
+        ahead.next();
+        let prefixed = radix != 10 && match ahead.peek() {
+            Some((_, c)) => c.is_digit(radix),
+            None => false,
+        };
+
+        let number = if prefixed {
+            iter.next();
+            Self::tokenize_integer(iter, radix)
+        } else {
+            Self::tokenize_decimal(first, iter)
+        };
+        let (value, literal) = match number {
+            Some(number) => number,
+            None => return Token::Invalid(TokenError::InvalidNumber),
+        };
+
+        Self::tokenize_imaginary(iter, value, literal)
+    }
+    // A number followed by i is imaginary, the suffix must not be the start of a keyword, like in "2if".
+    fn tokenize_imaginary<T>(iter: &mut Peekable<T>, value: f64, literal: Literal) -> Token
+        where T: Iterator<Item = (usize, char)> + Clone
+    {
+        let mut ahead = iter.clone();
+        let imaginary = match (ahead.next(), ahead.peek()) {
+            (Some((_, 'i')), Some((_, c))) => !(c.is_alphanumeric() || *c == '_'),
+            (Some((_, 'i')), None) => true,
+            _ => false,
+        };
+        if imaginary {
+            iter.next();
+            Token::Imaginary(value, literal)
+        } else {
+            Token::Real(value, literal)
+        }
+    }
+    fn tokenize_integer<T>(iter: &mut Peekable<T>, radix: u32) -> Option<(f64, Literal)>
+        where T: Iterator<Item = (usize, char)>
+    {
+        let mut buffer = String::new();
+        while let Some(&(_, c)) = iter.peek() {
             match c {
-                '0'..='9' | '.' => buffer.push(c),
                 '_' => (),
+                _ if c.is_digit(radix) => buffer.push(c),
                 _ => break,
             };
             iter.next();
         }
-        match buffer.parse::<f64>() {
-            Ok(value) => Token::Real(value),
-            Err(_) => Token::Invalid(TokenError::InvalidNumber(index)),
+        let literal = match radix {
+            16 => Literal::Hexadecimal,
+            _ => Literal::Binary,
+        };
+        u64::from_str_radix(&buffer, radix).ok().map(|value| (value as f64, literal))
+    }
+    fn tokenize_decimal<T>(first: char, iter: &mut Peekable<T>) -> Option<(f64, Literal)>
+        where T: Iterator<Item = (usize, char)> + Clone
+    {
+        let mut buffer = first.to_string();
+        let mut literal = Literal::Decimal;
+        while let Some(&(_, c)) = iter.peek() {
+            match c {
+                '0'..='9' | '.' => buffer.push(c),
+                '_' => (),
+                'e' | 'E' => {
+                    // Only an exponent if digits follow, otherwise "2e" is two times e.
+                    let mut ahead = iter.clone();
+                    ahead.next();
+                    let sign = match ahead.peek() {
+                        Some(&(_, sign @ '+')) | Some(&(_, sign @ '-')) => {
+                            ahead.next();
+                            Some(sign)
+                        }
+                        _ => None,
+                    };
+                    match ahead.peek() {
+                        Some((_, '0'..='9')) if literal == Literal::Decimal => (),
+                        _ => break,
+                    };
+                    literal = Literal::Scientific;
+                    buffer.push('e');
+                    iter.next();
+                    if let Some(sign) = sign {
+                        buffer.push(sign);
+                        iter.next();
+                    }
+                    continue;
+                }
+                _ => break,
+            };
+            iter.next();
         }
+        buffer.parse::<f64>().ok().map(|value| (value, literal))
     }
-    fn tokenize_keyword<T>(first: char, iter: &mut Peekable<T>, index: usize, keywords: &Vec<Keyword>) -> Token
-        where T: Iterator<Item = char>
+    // Identifiers start with a letter, followed by letters, digits, '_' or subscript digits.
+    // A number in front is a separate token, so "2z" is "2 * z", but "z2" is one identifier.
+    // Subscript digits are the same as normal ones, "z₀" and "z0" name the same keyword.
+    // Identifiers that aren't a keyword are split into keywords, which are multiplied implicitly.
+    fn tokenize_keyword<T>(iter: &mut Peekable<T>, keywords: &Keywords, length: usize) -> (Vec<Lexeme>, Option<Diagnostic>)
+        where T: Iterator<Item = (usize, char)>
     {
-        let mut keyword = first.to_string();
-
-        iter.next();
-        while let Some(&c) = iter.peek() {
+        // Normalized characters with their position in the input.
+        let mut identifier: Vec<(usize, char)> = Vec::new();
+        while let Some(&(position, c)) = iter.peek() {
             match c {
-                'a'..='z' | 'A'..='Z' | '_' => keyword.push(c),
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '₀'..='₉' => identifier.push((position, Keyword::subscript(c))),
                 _ => break,
             };
             iter.next();
         };
-        if keyword == "i" {
-            return Token::Imaginary(1.0);
-        }
-        
-        let mut token = Token::Invalid(TokenError::InvalidKeyword(index));
-        for definition in keywords {
-            match &definition {
-                Keyword::Function(name) => {
-                    if name == &keyword {
-                        match token {
-                            Token::Function(_) | Token::Variable(_) =>{
-                                return Token::Invalid(TokenError::DuplicateKeyword(index));
-                            }
-                            _ => {
-                                token = Token::Function(keyword.clone());
-                            }
-                        }
-                    }
-                }
-                Keyword::Variable(name) => {
-                    if name == &keyword {
-                        match token {
-                            Token::Function(_) | Token::Variable(_) =>{
-                                return Token::Invalid(TokenError::DuplicateKeyword(index));
-                            }
-                            _ => {
-                                token = Token::Variable(keyword.clone());
-                            }
-                        }
+        let end = iter.peek().map_or(length, |&(position, _)| position);
+        let span = Span::new(identifier.first().map_or(end, |&(position, _)| position), end);
+        let chars: Vec<char> = identifier.iter().map(|&(_, c)| c).collect();
+
+        // Reserved words are never split or looked up.
+        let reserved = match &chars.iter().collect::<String>() as &str {
+            "and" => Some(Token::And),
+            "or" => Some(Token::Or),
+            "not" => Some(Token::Not),
+            "if" => Some(Token::If),
+            "sum" => Some(Token::Sum),
+            "prod" => Some(Token::Product),
+            "iter" => Some(Token::Iterate),
+            _ => None,
+        };
+        if let Some(token) = reserved {
+            return (vec![Lexeme::new(token, span)], None);
+        }
+
+        let (parts, other) = match keywords.split(&chars) {
+            Some(parts) => parts,
+            None => return (vec![Lexeme::new(Token::Invalid(TokenError::InvalidKeyword), span)], None),
+        };
+        let mut lexemes = Vec::new();
+        let mut start = 0;
+        for length in &parts {
+            let name: String = chars[start..start + length].iter().collect();
+            let part_end = identifier.get(start + length).map_or(end, |&(position, _)| position);
+            lexemes.push(Lexeme::new(Self::keyword(&name, keywords), Span::new(identifier[start].0, part_end)));
+            start += length;
+        }
+
+        let warning = other.map(|other| {
+            let message = format!(
+                "'{}' is read as '{}', but could also be '{}'",
+                chars.iter().collect::<String>(), Self::join(&chars, &parts), Self::join(&chars, &other),
+            );
+            Diagnostic::warning(span, &message).with_hint("separate the names with a space or '*'")
+        });
+        (lexemes, warning)
+    }
+    fn keyword(name: &str, keywords: &Keywords) -> Token {
+        if name == "i" {
+            return Token::Imaginary(1.0, Literal::Decimal);
+        }
+        match keywords.get(name) {
+            [(_, Keyword::Function(_, arity))] => Token::Function(name.to_string(), *arity),
+            [(_, Keyword::Variable(_))] => Token::Variable(name.to_string()),
+            [] => Token::Invalid(TokenError::InvalidKeyword),
+            _ => Token::Invalid(TokenError::DuplicateKeyword),
+        }
+    }
+    // Identifier split into parts of the given lengths, separated by spaces.
+    fn join(chars: &[char], parts: &[usize]) -> String {
+        let mut start = 0;
+        parts.iter().map(|length| {
+            start += length;
+            chars[start - length..start].iter().collect::<String>()
+        }).collect::<Vec<String>>().join(" ")
+    }
+    // Returns the second token if the character is followed by '=', like in "<=".
+    fn tokenize_comparison<T>(iter: &mut Peekable<T>, single: Token, double: Token) -> Token
+        where T: Iterator<Item = (usize, char)>
+    {
+        match iter.peek() {
+            Some((_, '=')) => {
+                iter.next();
+                double
+            }
+            _ => single,
+        }
+    }
+    // Superscripts are exponents, "z⁻¹" is the same as "z^-1".
+    fn tokenize_superscript<T>(iter: &mut Peekable<T>, length: usize) -> Vec<Lexeme>
+        where T: Iterator<Item = (usize, char)>
+    {
+        let mut result = Vec::new();
+        if let Some(&(start, _)) = iter.peek() {
+            result.push(Lexeme::new(Token::Exponent, Span::at(start)));
+        }
+        let mut digits = String::new();
+        let mut digits_start = 0;
+        while let Some(&(position, c)) = iter.peek() {
+            let token = match Token::superscript(c) {
+                Some('+') => Token::Addition,
+                Some('-') => Token::Subtraction,
+                Some(digit) => {
+                    if digits.is_empty() {
+                        digits_start = position;
                     }
+                    digits.push(digit);
+                    iter.next();
+                    continue;
                 }
+                None => break,
+            };
+            // Signs are only allowed in front of the digits.
+            if !digits.is_empty() {
+                break;
             }
+            iter.next();
+            let end = iter.peek().map_or(length, |&(end, _)| end);
+            result.push(Lexeme::new(token, Span::new(position, end)));
+        }
+        if !digits.is_empty() {
+            let end = iter.peek().map_or(length, |&(end, _)| end);
+            let token = match digits.parse::<f64>() {
+                Ok(value) => Token::Real(value, Literal::Decimal),
+                Err(_) => Token::Invalid(TokenError::InvalidNumber),
+            };
+            result.push(Lexeme::new(token, Span::new(digits_start, end)));
         }
-        token
+        result
     }
-    fn tokenize_delimiter(delimiter: char, open: &Token, closed: &Token, delimiters: &mut HashMap<char, bool>) -> Token {
-        let mut token = open.clone();
-        delimiters.entry(delimiter).and_modify(|state| {
-            match state {
-                true => token = closed.clone(),
-                false => token = open.clone(),
-            }
-            *state = !*state;
-        }).or_insert_with(|| {
-            token = open.clone();
-            true
-        });
-        token
+    fn superscript(c: char) -> Option<char> {
+        match c {
+            '⁰' => Some('0'),
+            '¹' => Some('1'),
+            '²' => Some('2'),
+            '³' => Some('3'),
+            '⁴'..='⁹' => std::char::from_digit(c as u32 - '⁰' as u32, 10),
+            '⁺' => Some('+'),
+            '⁻' => Some('-'),
+            _ => None,
+        }
+    }
+    // Delimiters that open and close with the same character are resolved from the token before them.
+    // After an operand they close the innermost open one, otherwise they open a new one,
+    // so "||z|-1|" is "| |z| - 1 |" and "2|z|" is "2 * |z|".
+    fn tokenize_delimiter(delimiter: char, open: &Token, closed: &Token, delimiters: &mut HashMap<char, usize>, previous: Option<&Token>) -> Token {
+        let depth = delimiters.entry(delimiter).or_insert(0);
+        let operand = match previous {
+            Some(token) => token.ends_operand(),
+            None => false,
+        };
+        if *depth > 0 && operand {
+            *depth -= 1;
+            closed.clone()
+        } else {
+            *depth += 1;
+            open.clone()
+        }
+    }
+    fn ends_operand(&self) -> bool {
+        matches!(self,
+            Self::Real(..) | Self::Imaginary(..) | Self::Variable(_) | Self::Invalid(_) |
+            Self::ParenthesisClosed | Self::AbsoluteClosed | Self::BracketClosed | Self::BraceClosed |
+            Self::FloorClosed | Self::CeilClosed | Self::NormClosed |
+            Self::Factorial | Self::Conjugate | Self::Degree | Self::Percent
+        )
+    }
+}
+
+
+// Source token together with the byte range it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+}
+impl Lexeme {
+    pub fn new(token: Token, span: Span) -> Self {
+        Self {
+            token,
+            span,
+        }
     }
 }
 
 
 #[derive(Clone, PartialEq, Eq)]
 pub enum TokenError {
-    InvalidKeyword(usize),
-    DuplicateKeyword(usize),
-    InvalidCharacter(usize),
-    InvalidNumber(usize),
+    InvalidKeyword,
+    DuplicateKeyword,
+    InvalidCharacter,
+    InvalidNumber,
 }
 impl TokenError {
-    pub fn get_index(&self) -> usize {
+    pub fn diagnostic(&self, span: Span, input: &str) -> Diagnostic {
+        let text = input.get(span.start..span.end).unwrap_or("");
         match self {
-            Self::DuplicateKeyword(index) => *index,
-            Self::InvalidCharacter(index) => *index,
-            Self::InvalidNumber(index) => *index,
-            Self::InvalidKeyword(index) => *index,
+            Self::InvalidKeyword => {
+                Diagnostic::error(span, &format!("Unknown keyword '{}'", text))
+            }
+            Self::DuplicateKeyword => {
+                Diagnostic::error(span, &format!("Keyword '{}' is defined more than once", text))
+            }
+            Self::InvalidCharacter => {
+                Diagnostic::error(span, &format!("Invalid character '{}'", text))
+                    .with_hint("remove the character")
+            }
+            Self::InvalidNumber => {
+                Diagnostic::error(span, &format!("Invalid number '{}'", text))
+                    .with_hint("separate numbers with an operator")
+            }
         }
     }
 }
 impl Display for TokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} at token index {}", self, self.get_index())
+        write!(f, "{:?}", self)
     }
 }
 impl Debug for TokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::DuplicateKeyword(_) => {
+            Self::DuplicateKeyword => {
                 write!(f, "Duplicate definition of keyword")
             }
-            Self::InvalidCharacter(_) => {
+            Self::InvalidCharacter => {
                 write!(f, "Invalid character")
             }
-            Self::InvalidNumber(_) => {
+            Self::InvalidNumber => {
                 write!(f, "Invalid number")
             }
-            Self::InvalidKeyword(_) => {
+            Self::InvalidKeyword => {
                 write!(f, "Invalid keyword")
             }
         }
@@ -275,12 +556,15 @@ impl Debug for TokenError {
 
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
 pub enum Syntax {
     Ignore,
+    // Placeholder for input that could not be parsed.
+    Error,
     
-    Real(f64),
-    Imaginary(f64),
+    Real(#[serde(with = "number")] f64, Literal),
+    Imaginary(#[serde(with = "number")] f64, Literal),
     Variable(String),
     Function(String),
 
@@ -291,130 +575,228 @@ pub enum Syntax {
     Division,
     Exponent,
     Absolute,
+    // Links the arguments of a function after the first one.
+    Argument,
+    // Grouping like Parenthesis, kept so they print back the same.
+    Bracket,
+    Brace,
+    Floor,
+    Ceil,
+    Norm,
+
+    // Comparisons and logic evaluate to conditions instead of numbers.
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    // Condition is the left child, the branches are chained through the right child like arguments.
+    Condition,
+
+    // Postfix operators, the operand is the left child.
+    Factorial,
+    Conjugate,
+    Degree,
+    Percent,
+
+    // Loops with the name they bind for their body, the arguments are chained like the ones of a function.
+    // Sum and Product have the bounds and the body, Iterate has the count, the start value and the body.
+    Sum(String),
+    Product(String),
+    Iterate(String),
 }
 
-#[derive(Clone, PartialEq, Eq)]
-pub enum SyntaxError {
-    UnexpectedEndOfInput(usize),
-    UnexpectedToken(usize),
-    MissingClosingDelimiter(usize),
-    EmptyDelimiter(usize),
-    TokenError(TokenError),
-
-    // "_*2"
-    // MissingLeftHandSide
-    //"2*_"
-    // MissingRightHandSide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
 }
-impl SyntaxError {
-    pub fn get_index(&self) -> usize {
-        match self {
-            Self::UnexpectedEndOfInput(index) => *index,
-            Self::UnexpectedToken(index) => *index,
-            Self::MissingClosingDelimiter(index) => *index,
-            Self::EmptyDelimiter(index) => *index,
-            Self::TokenError(error) => error.get_index(),
-        }
-    }
 
+// Entry in the operator table, a higher precedence binds tighter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    pub syntax: Syntax,
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+impl Operator {
+    // "c ? a : b"
+    pub const CONDITIONAL: u8 = 1;
+    pub const OR: u8 = 2;
+    pub const AND: u8 = 3;
+    pub const NOT: u8 = 4;
+    pub const COMPARISON: u8 = 5;
+    pub const ADDITIVE: u8 = 6;
+    pub const MULTIPLICATIVE: u8 = 7;
+    // Terms next to each other bind tighter than explicit operators, so "1/2z" is "1/(2z)".
+    pub const IMPLICIT: u8 = 8;
+    // Unary signs bind looser than exponents, so "-2^2" is "-(2^2)".
+    pub const PREFIX: u8 = 9;
+    pub const EXPONENT: u8 = 10;
+    // Postfix operators bind tighter than exponents, so "2^3!" is "2^(3!)".
+    pub const POSTFIX: u8 = 11;
 
-    pub fn pretty(&self, input: &str) -> String {
-        // Get error message and index of token, that generated the message
-        let message = format!("{:?}", self);
-        let index = self.get_index();
-
-        // Parse tokens and calculate index in input string
-        // for the token, that generated the error.
-        // The index will be used for padding and aligning the error message.
-        let mut padding = 0;
-
-        let mut iter = input.chars().peekable();
-        let mut count: isize = 0;
-        while let Some(&c) = iter.peek() {
-            // Subtract size of the token,
-            // so the pointer points to the token and not after the token.
-            let mut size = 1;
-            match c {
-                // Account for whitespace.
-                ' ' => {
-                    count -= 1;
-                    padding += 1;
-                    iter.next();
-                    while let Some(&c) = iter.peek() {
-                        match c {
-                            ' ' => padding += 1,
-                            _ => break,
-                        };
-                        iter.next();
-                    }
-                }
+    pub fn new(syntax: Syntax, precedence: u8, associativity: Associativity) -> Self {
+        Self {
+            syntax,
+            precedence,
+            associativity,
+        }
+    }
 
-                // Account for numbers.
-                '0'..='9' | '.' => {
-                    padding += 1;
-                    iter.next();
-                    while let Some(&c) = iter.peek() {
-                        match c {
-                            '0'..='9' | '.' => {
-                                size += 1;
-                                padding += 1;
-                                iter.next();
-                            },
-                            'i' => {
-                                size += 1;
-                                padding += 1;
-                                iter.next();
-                                break;
-                            },
-                            _ => break,
-                        };
-                    }
-                }
+    pub fn binary(token: &Token) -> Option<Self> {
+        match token {
+            Token::Addition         => Some(Self::new(Syntax::Addition,       Self::ADDITIVE,       Associativity::Left)),
+            Token::Subtraction      => Some(Self::new(Syntax::Subtraction,    Self::ADDITIVE,       Associativity::Left)),
+            Token::Multiplication   => Some(Self::new(Syntax::Multiplication, Self::MULTIPLICATIVE, Associativity::Left)),
+            Token::Division         => Some(Self::new(Syntax::Division,       Self::MULTIPLICATIVE, Associativity::Left)),
+            Token::Exponent         => Some(Self::new(Syntax::Exponent,       Self::EXPONENT,       Associativity::Right)),
 
-                // Account for keywords.
-                'a'..='z' | 'A'..='Z' => {
-                    padding += 1;
-                    iter.next();
-                    while let Some(&c) = iter.peek() {
-                        match c {
-                            'a'..='z' | 'A'..='Z' | '_' => {
-                                size += 1;
-                                padding += 1;
-                            }
-                            _ => break,
-                        };
-                        iter.next();
-                    }
-                }
+            Token::Less             => Some(Self::new(Syntax::Less,           Self::COMPARISON,     Associativity::Left)),
+            Token::Greater          => Some(Self::new(Syntax::Greater,        Self::COMPARISON,     Associativity::Left)),
+            Token::LessEqual        => Some(Self::new(Syntax::LessEqual,      Self::COMPARISON,     Associativity::Left)),
+            Token::GreaterEqual     => Some(Self::new(Syntax::GreaterEqual,   Self::COMPARISON,     Associativity::Left)),
+            Token::Equal            => Some(Self::new(Syntax::Equal,          Self::COMPARISON,     Associativity::Left)),
+            Token::NotEqual         => Some(Self::new(Syntax::NotEqual,       Self::COMPARISON,     Associativity::Left)),
+            Token::And              => Some(Self::new(Syntax::And,            Self::AND,            Associativity::Left)),
+            Token::Or               => Some(Self::new(Syntax::Or,             Self::OR,             Associativity::Left)),
+            Token::Question         => Some(Self::new(Syntax::Condition,      Self::CONDITIONAL,    Associativity::Right)),
+            _ => None,
+        }
+    }
+    // Multiplication of terms without an operator in between, like "2z".
+    pub fn implicit() -> Self {
+        Self::new(Syntax::Multiplication, Self::IMPLICIT, Associativity::Left)
+    }
+    // Prefix signs are parsed as "0 + x" and "0 - x".
+    // The square root takes a single power as its argument, so "√2z" is "(√2)z", but "√z^2" is "√(z^2)".
+    pub fn prefix(token: &Token) -> Option<Self> {
+        match token {
+            Token::Addition     => Some(Self::new(Syntax::Addition,    Self::PREFIX, Associativity::Right)),
+            Token::Subtraction  => Some(Self::new(Syntax::Subtraction, Self::PREFIX, Associativity::Right)),
+            Token::SquareRoot   => Some(Self::new(Syntax::Function("sqrt".to_string()), Self::EXPONENT, Associativity::Right)),
+            Token::Not          => Some(Self::new(Syntax::Not,         Self::NOT,    Associativity::Right)),
+            _ => None,
+        }
+    }
 
-                // Account for single characters.
-                _ => {
-                    padding += 1;
-                    iter.next();
-                }
-            };
+    // "z!" is gamma(z + 1), "z'" and "z*" are the conjugate, "30°" is in degrees and "5%" is 0.05.
+    pub fn postfix(token: &Token) -> Option<Self> {
+        match token {
+            Token::Factorial        => Some(Self::new(Syntax::Factorial, Self::POSTFIX, Associativity::Left)),
+            Token::Conjugate        => Some(Self::new(Syntax::Conjugate, Self::POSTFIX, Associativity::Left)),
+            Token::Multiplication   => Some(Self::new(Syntax::Conjugate, Self::POSTFIX, Associativity::Left)),
+            Token::Degree           => Some(Self::new(Syntax::Degree,    Self::POSTFIX, Associativity::Left)),
+            Token::Percent          => Some(Self::new(Syntax::Percent,   Self::POSTFIX, Associativity::Left)),
+            _ => None,
+        }
+    }
 
-            // Only count padding up until the token.
-            if count >= index as isize {
-                // Subtract size / 2, so that the pointer points to the center of the token.
-                padding -= (size as f32 / 2.0).ceil() as usize;
-                break;
-            }
-            count += 1;
+    // Minimum precedence of the right hand side.
+    pub fn next_precedence(&self) -> u8 {
+        match self.associativity {
+            Associativity::Left => self.precedence + 1,
+            Associativity::Right => self.precedence,
+        }
+    }
+    // Minimum precedence of the left and right operand, so they don't need parentheses when printed.
+    pub fn operand_precedence(&self) -> (u8, u8) {
+        match self.associativity {
+            Associativity::Left => (self.precedence, self.precedence + 1),
+            Associativity::Right => (self.precedence + 1, self.precedence),
         }
+    }
+}
 
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum SyntaxError {
+    UnexpectedEndOfInput(Span),
+    UnexpectedToken(Span),
+    MissingOperand(Span),
+    MissingClosingDelimiter(Span),
+    EmptyDelimiter(Span),
+    // Expected and found number of arguments.
+    ArgumentCount(Span, usize, usize),
+    // Only the last statement is evaluated.
+    UnusedExpression(Span),
+    DuplicateDefinition(Span),
+    // Function without an argument in parentheses or a term after it.
+    MissingParenthesis(Span),
+    MissingInverse(Span),
+    // "c ? a" without ": b".
+    MissingAlternative(Span),
+    // Loop without the name it binds.
+    MissingBinding(Span),
+}
+impl SyntaxError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::UnexpectedEndOfInput(span) => *span,
+            Self::UnexpectedToken(span) => *span,
+            Self::MissingOperand(span) => *span,
+            Self::MissingClosingDelimiter(span) => *span,
+            Self::EmptyDelimiter(span) => *span,
+            Self::ArgumentCount(span, ..) => *span,
+            Self::UnusedExpression(span) => *span,
+            Self::DuplicateDefinition(span) => *span,
+            Self::MissingParenthesis(span) => *span,
+            Self::MissingInverse(span) => *span,
+            Self::MissingAlternative(span) => *span,
+            Self::MissingBinding(span) => *span,
+        }
+    }
 
-        // Generete padding for error message to be properly aligned.
-        // Create the final string combining input string and aligned error message.
-        match String::from_utf8(vec![' ' as u8; padding]) {
-            Ok(padding) => format!("{}\n{}^ {}", input, padding, message),
-            Err(_) => format!("{}\n{}", input, message)
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = self.span();
+        let message = format!("{:?}", self);
+        match self {
+            Self::UnexpectedEndOfInput(_) => {
+                Diagnostic::error(span, &message).with_hint("complete the expression")
+            }
+            Self::UnexpectedToken(_) => {
+                Diagnostic::error(span, &message).with_hint("remove the token or add an operator in front of it")
+            }
+            Self::MissingOperand(_) => {
+                Diagnostic::error(span, &message).with_hint("add an expression in front of the token")
+            }
+            Self::MissingClosingDelimiter(_) => {
+                Diagnostic::error(span, &message).with_hint("add the matching closing delimiter")
+            }
+            Self::EmptyDelimiter(_) => {
+                Diagnostic::error(span, &message).with_hint("put an expression between the delimiters")
+            }
+            Self::ArgumentCount(..) => {
+                Diagnostic::error(span, &message)
+            }
+            Self::UnusedExpression(_) => {
+                Diagnostic::error(span, &message).with_hint("only the last statement is evaluated, remove this one")
+            }
+            Self::DuplicateDefinition(_) => {
+                Diagnostic::error(span, &message).with_hint("rename or remove one of the definitions")
+            }
+            Self::MissingParenthesis(_) => {
+                Diagnostic::error(span, &message).with_hint("put the arguments in parentheses, like sin(z) or pow(z, 2)")
+            }
+            Self::MissingInverse(_) => {
+                Diagnostic::error(span, &message).with_hint("only sin, cos, tan, exp and their inverses can be raised to -1")
+            }
+            Self::MissingAlternative(_) => {
+                Diagnostic::error(span, &message).with_hint("add the value for when the condition is false, like c ? a : b")
+            }
+            Self::MissingBinding(_) => {
+                Diagnostic::error(span, &message).with_hint("name the variable of the loop, like sum(k, 1, 10, z^k)")
+            }
         }
     }
 }
 impl Display for SyntaxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} at token index {}", self, self.get_index())
+        write!(f, "{:?} at {}", self, self.span())
     }
 }
 impl Debug for SyntaxError {
@@ -426,14 +808,35 @@ impl Debug for SyntaxError {
             Self::UnexpectedToken(_) => {
                 write!(f, "Unexpected token")
             }
+            Self::MissingOperand(_) => {
+                write!(f, "Missing operand")
+            }
             Self::MissingClosingDelimiter(_) => {
                 write!(f, "Missing closing delimter")
             }
             Self::EmptyDelimiter(_)=> {
                 write!(f, "Empty delimiter")
             }
-            Self::TokenError(error) => {
-                write!(f, "{:?}", error)
+            Self::ArgumentCount(_, expected, found) => {
+                write!(f, "Expected {} argument(s), found {}", expected, found)
+            }
+            Self::UnusedExpression(_) => {
+                write!(f, "Unused expression")
+            }
+            Self::DuplicateDefinition(_) => {
+                write!(f, "Name is already defined")
+            }
+            Self::MissingParenthesis(_) => {
+                write!(f, "Expected '(' after function")
+            }
+            Self::MissingInverse(_) => {
+                write!(f, "Function has no known inverse")
+            }
+            Self::MissingAlternative(_) => {
+                write!(f, "Expected ':' after the condition")
+            }
+            Self::MissingBinding(_) => {
+                write!(f, "Expected the name the loop binds")
             }
         }
     }
@@ -442,11 +845,15 @@ impl Debug for SyntaxError {
 
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub left: Option<Box<Self>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub right: Option<Box<Self>>,
     pub syntax: Syntax,
+    // Part of the input the node was parsed from, empty for nodes that weren't parsed.
+    pub span: Span,
 }
 impl Node {
     pub fn new(left: Option<Node>, right: Option<Node>, syntax: Syntax) -> Self {
@@ -460,6 +867,7 @@ impl Node {
                 None => None,
             },
             syntax,
+            span: Span::default(),
         }
     }
     pub fn from(left: Node, right: Node, syntax: Syntax) -> Self {
@@ -467,6 +875,7 @@ impl Node {
             left: Some(Box::new(left)),
             right: Some(Box::new(right)),
             syntax,
+            span: Span::default(),
         }
     }
     pub fn from_syntax(syntax: Syntax) -> Self {
@@ -474,6 +883,7 @@ impl Node {
             left: None,
             right: None,
             syntax,
+            span: Span::default(),
         }
     }
     pub fn empty() -> Self {
@@ -481,10 +891,57 @@ impl Node {
             left: None,
             right: None,
             syntax: Syntax::Ignore,
+            span: Span::default(),
         }
     }
+    pub fn error() -> Self {
+        Self::from_syntax(Syntax::Error)
+    }
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
 
 
+    // The first argument is the left child,
+    // the rest are chained through the right child as Syntax::Argument nodes.
+    pub fn chain(mut arguments: Vec<Node>, syntax: Syntax) -> Node {
+        let mut rest: Option<Node> = None;
+        for argument in arguments.drain(1.min(arguments.len())..).rev() {
+            let span = match &rest {
+                Some(rest) => argument.span.join(rest.span),
+                None => argument.span,
+            };
+            rest = Some(Node::new(Some(argument), rest, Syntax::Argument).with_span(span));
+        }
+        Node::new(arguments.pop(), rest, syntax)
+    }
+    // Arguments of a Syntax::Function node in order.
+    pub fn arguments(&self) -> Vec<&Node> {
+        let mut result = Vec::new();
+        if let Some(first) = &self.left {
+            result.push(&**first);
+        }
+        let mut next = &self.right;
+        while let Some(argument) = next {
+            if let Some(node) = &argument.left {
+                result.push(&**node);
+            }
+            next = &argument.right;
+        }
+        result
+    }
+    // Value of a real number, optionally with a sign in front.
+    pub fn constant(&self) -> Option<f64> {
+        let (left, right) = (self.left.as_ref(), self.right.as_ref());
+        match (&self.syntax, left.map(|node| &node.syntax)) {
+            (Syntax::Real(value, _), _) => Some(*value),
+            (Syntax::Parenthesis, _) => left?.constant(),
+            (Syntax::Addition, Some(Syntax::Real(zero, _))) if *zero == 0.0 => right?.constant(),
+            (Syntax::Subtraction, Some(Syntax::Real(zero, _))) if *zero == 0.0 => right?.constant().map(|value| -value),
+            _ => None,
+        }
+    }
     pub fn child_syntax(&self) -> (Syntax, Syntax) {
         let left = match &self.left {
             Some(node) => node.syntax.clone(),
@@ -503,11 +960,12 @@ impl Display for Node {
             let next_prefix = format!("{}|   ", prefix);
             let result = match &node.syntax {
                 Syntax::Ignore => format!(""),
+                Syntax::Error => "|> Error\n".to_string(),
 
-                Syntax::Real(value) => format!("|> {}\n", value),
-                Syntax::Imaginary(value) => format!("|> {}i\n", value),
+                Syntax::Real(value, literal) => format!("|> {}\n", literal.format(*value)),
+                Syntax::Imaginary(value, literal) => format!("|> {}i\n", literal.format(*value)),
                 Syntax::Variable(variable)  => format!("|> {}\n", variable),
-                Syntax::Function(function)  => format!("|> {}\n{}", function, fmt_pretty(&*node.left.clone().unwrap(), &next_prefix)),
+                Syntax::Function(function)  => format!("|> {}\n{}{}",         function, fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
 
                 Syntax::Parenthesis     => format!("|> Parenthesis\n{}",        fmt_pretty(&*node.left.clone().unwrap(), &next_prefix)),
                 Syntax::Addition        => format!("|> Addition\n{}{}",         fmt_pretty(&*node.left.clone().unwrap(), &next_prefix), fmt_pretty(&*node.right.clone().unwrap(), &next_prefix)),
@@ -516,402 +974,891 @@ impl Display for Node {
                 Syntax::Division        => format!("|> Division\n{}{}",         fmt_pretty(&*node.left.clone().unwrap(), &next_prefix), fmt_pretty(&*node.right.clone().unwrap(), &next_prefix)),
                 Syntax::Exponent        => format!("|> Exponent\n{}{}",         fmt_pretty(&*node.left.clone().unwrap(), &next_prefix), fmt_pretty(&*node.right.clone().unwrap(), &next_prefix)),
                 Syntax::Absolute        => format!("|> Absolute\n{}",           fmt_pretty(&*node.left.clone().unwrap(), &next_prefix)),
+                Syntax::Argument        => format!("|> Argument\n{}{}",         fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Bracket         => format!("|> Bracket\n{}",            fmt_optional(&node.left, &next_prefix)),
+                Syntax::Brace           => format!("|> Brace\n{}",              fmt_optional(&node.left, &next_prefix)),
+                Syntax::Floor           => format!("|> Floor\n{}",              fmt_optional(&node.left, &next_prefix)),
+                Syntax::Ceil            => format!("|> Ceil\n{}",               fmt_optional(&node.left, &next_prefix)),
+                Syntax::Norm            => format!("|> Norm\n{}",               fmt_optional(&node.left, &next_prefix)),
+
+                Syntax::Less            => format!("|> Less\n{}{}",             fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Greater         => format!("|> Greater\n{}{}",          fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::LessEqual       => format!("|> LessEqual\n{}{}",        fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::GreaterEqual    => format!("|> GreaterEqual\n{}{}",     fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Equal           => format!("|> Equal\n{}{}",            fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::NotEqual        => format!("|> NotEqual\n{}{}",         fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::And             => format!("|> And\n{}{}",              fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Or              => format!("|> Or\n{}{}",               fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Not             => format!("|> Not\n{}",                fmt_optional(&node.left, &next_prefix)),
+                Syntax::Condition       => format!("|> Condition\n{}{}",        fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+
+                Syntax::Factorial       => format!("|> Factorial\n{}",          fmt_optional(&node.left, &next_prefix)),
+                Syntax::Conjugate       => format!("|> Conjugate\n{}",          fmt_optional(&node.left, &next_prefix)),
+                Syntax::Degree          => format!("|> Degree\n{}",             fmt_optional(&node.left, &next_prefix)),
+                Syntax::Percent         => format!("|> Percent\n{}",            fmt_optional(&node.left, &next_prefix)),
+
+                Syntax::Sum(name)       => format!("|> Sum {}\n{}{}",           name, fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Product(name)   => format!("|> Product {}\n{}{}",       name, fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
+                Syntax::Iterate(name)   => format!("|> Iterate {}\n{}{}",       name, fmt_optional(&node.left, &next_prefix), fmt_optional(&node.right, &next_prefix)),
             };
             format!("{}{}", prefix, result)
         }
+        fn fmt_optional(node: &Option<Box<Node>>, prefix: &str) -> String {
+            match node {
+                Some(node) => fmt_pretty(node, prefix),
+                None => String::new(),
+            }
+        }
         write!(f, "{}", fmt_pretty(self, ""))
     }
 }
 
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Expression {
     pub input: String,
+    // Last statement of the input, with all definitions inlined.
     pub tree: Option<Node>,
-    pub error: Option<SyntaxError>,
+    // Definitions in the order they appear, other definitions are inlined into their bodies.
+    pub definitions: Vec<Definition>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 impl Expression {
+    // Parts that could not be parsed are replaced with Syntax::Error.
+    // The tree is only missing if the input consists of definitions alone.
     pub fn parse(input: &str, keywords: &Vec<Keyword>) -> Self {
-        let mut error = None;
-        let mut tree = None;
-
-        let tokens = Token::parse(&input, &keywords);
-        match Self::parse_expression(&tokens, 0) {
-            Ok((root, index)) => {
-                if index == tokens.len() {
-                    tree = Some(root);
-                } else {
-                    error = Some(SyntaxError::UnexpectedEndOfInput(index));
+        Self::parse_with(input, keywords, true)
+    }
+    // Same as parse, but the definitions and the tree are kept as they were written, calls to definitions aren't inlined.
+    pub fn parse_written(input: &str, keywords: &[Keyword]) -> Self {
+        Self::parse_with(input, keywords, false)
+    }
+    fn parse_with(input: &str, keywords: &[Keyword], inline: bool) -> Self {
+        let mut diagnostics = Vec::new();
+
+        // Names of definitions are only keywords after they were found in the input,
+        // so the input is lexed again with them.
+        let (mut tokens, mut warnings) = Token::parse_diagnostics(input, keywords);
+        let (defined, parameters) = Definition::scan(&tokens, input);
+        let bindings = Token::bindings(&tokens, input);
+        let known = Self::known(keywords, &defined, &parameters, &bindings);
+        if !defined.is_empty() || !bindings.is_empty() {
+            for (index, (keyword, span)) in defined.iter().enumerate() {
+                let earlier = defined[..index].iter().any(|(other, _)| other.name() == keyword.name());
+                if !earlier && keywords.iter().any(|other| other.name() == keyword.name()) {
+                    let message = format!("Definition of '{}' hides an existing keyword", keyword.name());
+                    diagnostics.push(Diagnostic::warning(*span, &message));
                 }
             }
-            Err(syntax_error) => {
-                error = Some(syntax_error);
-            } 
+            let (retokens, rewarnings) = Token::parse_diagnostics(input, &known);
+            tokens = retokens;
+            warnings = rewarnings;
+        }
+        diagnostics.append(&mut warnings);
+
+        // Report every invalid token, not only the ones the parser runs into.
+        for lexeme in &tokens {
+            if let Token::Invalid(error) = &lexeme.token {
+                diagnostics.push(error.diagnostic(lexeme.span, input));
+            }
         }
 
+        let (tree, mut definitions, errors) = Parser::new(&tokens, &known).parse();
+        for error in errors {
+            diagnostics.push(error.diagnostic());
+        }
+
+        // Parameters that aren't keywords on their own are unknown outside of their function.
+        let parameters: Vec<String> = parameters.into_iter()
+            .filter(|parameter| !keywords.iter().any(|keyword| keyword.name() == parameter))
+            .filter(|parameter| !defined.iter().any(|(keyword, _)| keyword.name() == parameter))
+            .collect();
+        // The same goes for names bound by loops.
+        let bindings: Vec<String> = bindings.into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| !keywords.iter().any(|keyword| keyword.name() == name))
+            .filter(|name| !defined.iter().any(|(keyword, _)| keyword.name() == name))
+            .filter(|name| !parameters.contains(name))
+            .collect();
+
+        // Every definition in a cycle reports it, definitions that only use one don't.
+        let mut resolved = Vec::new();
+        for definition in &definitions {
+            let mut definition = definition.clone();
+            match definition.resolve(&definitions) {
+                Ok(body) if inline => definition.body = body,
+                Ok(_) => (),
+                Err(name) if name == definition.name() => {
+                    let message = format!("Definition of '{}' refers to itself", name);
+                    diagnostics.push(Diagnostic::error(definition.span, &message)
+                        .with_hint("a definition can only use definitions that don't depend on it"));
+                }
+                Err(_) => (),
+            };
+            let own = definition.parameters.clone();
+            let outside = parameters.iter().filter(|parameter| !own.contains(parameter)).cloned().collect();
+            if let Some(name) = definition.body.uses(&outside) {
+                diagnostics.push(Self::unbound(definition.span, &name));
+            }
+            if let Some(name) = definition.body.uses(&bindings) {
+                diagnostics.push(Self::unbound_loop(definition.span, &name));
+            }
+            resolved.push(definition);
+        }
+        definitions = resolved;
+
+        // Cycles were already reported at their definitions.
+        let tree = tree.map(|(tree, span)| {
+            if let Some(name) = tree.uses(&parameters) {
+                diagnostics.push(Self::unbound(span, &name));
+            }
+            if let Some(name) = tree.uses(&bindings) {
+                diagnostics.push(Self::unbound_loop(span, &name));
+            }
+            match inline {
+                true => tree.inline(&definitions).unwrap_or(Node::error().with_span(span)),
+                false => tree,
+            }
+        });
+        // Only numbers can be evaluated and drawn, calls that aren't inlined have no type yet.
+        if let (Some(tree), true) = (&tree, inline) {
+            tree.expect(Type::Complex, &mut diagnostics);
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+
         Self {
             input: format!("{}", input),
-            tree: tree,
-            error: error,
+            tree,
+            definitions,
+            diagnostics,
         }
     }
-    pub fn pretty_error(&self) -> String {
-        match &self.error {
-            Some(error) => error.pretty(&self.input),
-            None => match self.tree {
-                Some(_) => format!(""),
-                None => format!("Uninitialized expression."),
+    // Keywords the input is lexed with, definitions hide the given keywords with the same name,
+    // parameters and names bound by loops are added as variables.
+    pub fn known(keywords: &[Keyword], defined: &[(Keyword, Span)], parameters: &[String], bindings: &[(String, Span)]) -> Vec<Keyword> {
+        let mut known = keywords.to_vec();
+        known.retain(|other| !defined.iter().any(|(keyword, _)| keyword.name() == other.name()));
+        known.extend(defined.iter().map(|(keyword, _)| keyword.clone()));
+        for parameter in parameters.iter().chain(bindings.iter().map(|(name, _)| name)) {
+            if !known.iter().any(|other| other.name() == parameter) {
+                known.push(Keyword::Variable(parameter.to_string()));
             }
         }
+        known
+    }
+    fn unbound(span: Span, name: &str) -> Diagnostic {
+        Diagnostic::error(span, &format!("Parameter '{}' is used outside of its function", name))
+            .with_hint("parameters can only be used in the body of their definition")
+    }
+    fn unbound_loop(span: Span, name: &str) -> Diagnostic {
+        Diagnostic::error(span, &format!("Variable '{}' is used outside of its loop", name))
+            .with_hint("names bound by sum, prod and iter can only be used in the last argument")
+    }
+    pub fn pretty_error(&self) -> String {
+        if self.diagnostics.is_empty() {
+            return match (&self.tree, self.definitions.is_empty()) {
+                (Some(_), _) => String::new(),
+                (None, false) => "Nothing to evaluate, the input only contains definitions.".to_string(),
+                (None, true) => "Uninitialized expression.".to_string(),
+            };
+        }
+        self.diagnostics.iter()
+            .map(|diagnostic| diagnostic.pretty(&self.input))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.is_error())
     }
     pub fn is_valid(&self) -> bool {
-        self.tree.is_some() && !self.error.is_some()
+        self.tree.is_some() && self.errors().next().is_none()
     }
+    
 
-    fn parse_expression(tokens: &Vec<Token>, index: usize) -> Result<(Node, usize), SyntaxError> {
-        let (node, next_index) = Self::parse_factor(tokens, index)?;
-        let token = tokens.get(next_index);
-        match token {
-            Some(Token::Addition) => {
-                let (right, i) = Self::parse_expression(tokens, next_index + 1)?;
-                Ok((Node::from(node, right, Syntax::Addition), i))
-            }
-            Some(Token::Subtraction) => {
-                let (right, i) = Self::parse_expression(tokens, next_index + 1)?;
-                Ok((Node::from(node, right, Syntax::Subtraction), i))
+
+
+    // Folds constants and simplifies sums and products, see Node::simplify.
+    pub fn optimize(&mut self) {
+        self.tree = self.tree.as_ref().map(|root| root.simplify());
+    }
+}
+
+
+
+
+impl Node {
+    // Parses tokens that form a single expression, like the content of parentheses in a longer input.
+    // None if they don't, or if they have errors.
+    pub fn parse_tokens(tokens: &Vec<Lexeme>, keywords: &Vec<Keyword>) -> Option<Self> {
+        let (tree, definitions, errors) = Parser::new(tokens, keywords).parse();
+        match (tree, definitions.is_empty() && errors.is_empty()) {
+            (Some((tree, _)), true) => Some(tree),
+            _ => None,
+        }
+    }
+}
+
+
+
+// Recursive descent parser, that records errors and keeps going instead of stopping at the first one.
+// Missing operands become Syntax::Error nodes and stray tokens are skipped
+// up to the next operator or closing delimiter.
+struct Parser<'a> {
+    tokens: &'a Vec<Lexeme>,
+    // Used to check that inverse functions exist.
+    keywords: &'a Vec<Keyword>,
+    // Closing delimiters that are currently expected, innermost last.
+    closing: Vec<Token>,
+    // Set while parsing the argument of a function without parentheses.
+    applied: bool,
+    errors: Vec<SyntaxError>,
+}
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a Vec<Lexeme>, keywords: &'a Vec<Keyword>) -> Self {
+        Self {
+            tokens,
+            keywords,
+            closing: Vec::new(),
+            applied: false,
+            errors: Vec::new(),
+        }
+    }
+    // Statements are separated by semicolons, every statement but the last should be a definition.
+    fn parse(mut self) -> (Option<(Node, Span)>, Vec<Definition>, Vec<SyntaxError>) {
+        let mut tree: Option<(Node, Span)> = None;
+        let mut definitions: Vec<Definition> = Vec::new();
+
+        // At the top level every token that doesn't fit is skipped up to the next statement.
+        self.closing.push(Token::Semicolon);
+        let mut index = 0;
+        loop {
+            let start = self.span(index);
+            match self.parse_header(index) {
+                Some((keyword, parameters, i)) => {
+                    let (body, next_index) = self.parse_expression(i);
+                    if definitions.iter().any(|definition| definition.name() == keyword.name()) {
+                        self.error(SyntaxError::DuplicateDefinition(start));
+                    }
+                    let span = start.join(self.span(next_index - 1));
+                    definitions.push(Definition::new(keyword, parameters, body, span));
+                    index = next_index;
+                }
+                None => match self.token(index) {
+                    // Empty statement.
+                    None | Some(Token::Semicolon) => (),
+                    Some(_) => {
+                        let (node, next_index) = self.parse_expression(index);
+                        if let Some((_, span)) = tree {
+                            self.error(SyntaxError::UnusedExpression(span));
+                        }
+                        tree = Some((node, start.join(self.span(next_index - 1))));
+                        index = next_index;
+                    }
+                }
             }
-            _ => {
-                Ok((node, next_index))
+            match self.token(index) {
+                Some(Token::Semicolon) => index += 1,
+                _ => break,
             }
         }
+        self.closing.pop();
+
+        // Input without any statement is an incomplete expression.
+        if tree.is_none() && definitions.is_empty() {
+            self.error(SyntaxError::UnexpectedEndOfInput(self.span(index)));
+            tree = Some((Node::error().with_span(self.span(index)), self.span(index)));
+        }
+        (tree, definitions, self.errors)
     }
-    fn parse_factor(tokens: &Vec<Token>, index: usize) -> Result<(Node, usize), SyntaxError> {
-        let (node, next_index) = Self::parse_exponent(tokens, index)?;
-        let token = tokens.get(next_index);
-        match token {
-            Some(Token::Multiplication) => {
-                let (right, i) = Self::parse_factor(tokens, next_index + 1)?;
-                Ok((Node::from(node, right, Syntax::Multiplication), i))
+    // Matches "name =" and "name(a, b) =" at the start of a statement.
+    fn parse_header(&mut self, index: usize) -> Option<(Keyword, Vec<String>, usize)> {
+        match (self.token(index)?, self.token(index + 1)?) {
+            (Token::Variable(name), Token::Assignment) => {
+                Some((Keyword::Variable(name.to_string()), Vec::new(), index + 2))
             }
-            Some(Token::Division) => {
-                let (right, i) = Self::parse_factor(tokens, next_index + 1)?;
-                Ok((Node::from(node, right, Syntax::Division), i))
-            },
-            _ => {
-                Ok((node, next_index))
+            (Token::Function(name, _), Token::ParenthesisOpen) => {
+                let mut parameters: Vec<String> = Vec::new();
+                let mut duplicates = Vec::new();
+                let mut next_index = index + 2;
+                while let Some(Token::Variable(parameter)) = self.token(next_index) {
+                    if parameters.contains(parameter) {
+                        duplicates.push(self.span(next_index));
+                    }
+                    parameters.push(parameter.to_string());
+                    next_index += 1;
+                    match self.token(next_index) {
+                        Some(Token::Comma) => next_index += 1,
+                        _ => break,
+                    }
+                }
+                match (self.token(next_index)?, self.token(next_index + 1)?) {
+                    (Token::ParenthesisClosed, Token::Assignment) => (),
+                    _ => return None,
+                };
+                for span in duplicates {
+                    self.error(SyntaxError::DuplicateDefinition(span));
+                }
+                Some((Keyword::Function(name.to_string(), parameters.len()), parameters, next_index + 2))
+            }
+            _ => None,
+        }
+    }
+
+    fn token(&self, index: usize) -> Option<&'a Token> {
+        self.tokens.get(index).map(|lexeme| &lexeme.token)
+    }
+    // Span of the token at index, or an empty span at the end of the input.
+    fn span(&self, index: usize) -> Span {
+        match self.tokens.get(index) {
+            Some(lexeme) => lexeme.span,
+            None => match self.tokens.last() {
+                Some(lexeme) => Span::at(lexeme.span.end),
+                None => Span::at(0),
             },
         }
     }
-    // TODO: rename the "i"s to next_index.
-    fn parse_exponent(tokens: &Vec<Token>, index: usize) -> Result<(Node, usize), SyntaxError> {
-        let (node, next_index) = Self::parse_preterm(tokens, index)?;
-        let token = tokens.get(next_index);
-        match token {
-            Some(Token::Exponent) => {
-                let (right, next_index) = Self::parse_exponent(tokens, next_index + 1)?;
-                Ok((Node::from(node, right, Syntax::Exponent), next_index))
+    // Span from the token at start up to the token before end.
+    fn range(&self, start: usize, end: usize) -> Span {
+        match end > start {
+            true => self.span(start).join(self.span(end - 1)),
+            false => Span::at(self.span(start).start),
+        }
+    }
+    fn error(&mut self, error: SyntaxError) {
+        // Only report one error per location.
+        match self.errors.last() {
+            Some(last) if last.span() == error.span() => (),
+            _ => self.errors.push(error),
+        }
+    }
+    // Skips tokens until an operator or an expected closing delimiter.
+    fn recover(&mut self, index: usize) -> usize {
+        self.error(SyntaxError::UnexpectedToken(self.span(index)));
+        let mut next_index = index + 1;
+        while let Some(token) = self.token(next_index) {
+            if Operator::binary(token).is_some() || self.closing.contains(token) {
+                break;
             }
-            _ => {
-                Ok((node, next_index))
+            next_index += 1;
+        }
+        next_index
+    }
+
+
+    fn parse_expression(&mut self, index: usize) -> (Node, usize) {
+        self.parse_binary(index, 0)
+    }
+    // Precedence climbing, operators binding looser than min_precedence are left for the caller.
+    fn parse_binary(&mut self, index: usize, min_precedence: u8) -> (Node, usize) {
+        let (mut node, mut next_index) = self.parse_prefix(index);
+        while let Some(token) = self.token(next_index) {
+            if let Some(operator) = self.postfix(next_index) {
+                if operator.precedence < min_precedence {
+                    break;
+                }
+                node = Node::new(Some(node), None, operator.syntax).with_span(self.range(index, next_index + 1));
+                next_index += 1;
+                continue;
+            }
+            let operator = match Operator::binary(token) {
+                Some(operator) => Some((operator, next_index + 1)),
+                // The argument of a function without parentheses ends at the next function.
+                None if self.applied && matches!(token, Token::Function(..)) => None,
+                // Allows implicit multiplication between terms.
+                None if Self::starts_term(token) => Some((Operator::implicit(), next_index)),
+                None => None,
+            };
+            match operator {
+                Some((operator, right_index)) => {
+                    if operator.precedence < min_precedence {
+                        break;
+                    }
+                    let (right, i) = match operator.syntax {
+                        Syntax::Condition => self.parse_branches(right_index, operator.next_precedence()),
+                        _ => self.parse_binary(right_index, operator.next_precedence()),
+                    };
+                    node = Node::from(node, right, operator.syntax).with_span(self.range(index, i));
+                    next_index = i;
+                }
+                None => {
+                    // Only the outermost level of an expression skips stray tokens.
+                    if min_precedence > 0 || self.closing.contains(token) {
+                        break;
+                    }
+                    next_index = self.recover(next_index);
+                }
             }
         }
+        (node, next_index)
     }
-    fn parse_preterm(tokens: &Vec<Token>, index: usize) -> Result<(Node, usize), SyntaxError> {
-        let token = tokens.get(index).ok_or(SyntaxError::UnexpectedEndOfInput(index))?;
-        match token {
-            Token::Subtraction => {
-                // Allows - symbol in front of expression.
-                let (right, i) = Self::parse_preterm(tokens, index + 1)?;
-                Ok((Node::from(Node::from_syntax(Syntax::Real(0.0)), right, Syntax::Subtraction), i))
-            },
-            Token::Addition => {
-                // Allows + symbol in front of expression.
-                let (right, i) = Self::parse_preterm(tokens, index + 1)?;
-                Ok((Node::from(Node::from_syntax(Syntax::Real(0.0)), right, Syntax::Addition), i))
-            },
-            _ => Self::parse_term(tokens, index),
+    // "*" is a multiplication if anything it could multiply follows it, so "z*w" is a product.
+    // A sign after it only starts an operand if the "*" isn't attached to the left side alone,
+    // so "z* + 1" is "conj(z) + 1", but "z * -1" and "z*-1" are still products.
+    fn postfix(&self, index: usize) -> Option<Operator> {
+        let token = self.token(index)?;
+        if *token == Token::Multiplication {
+            let attached = index > 0 && self.span(index - 1).end == self.span(index).start;
+            let spaced = self.span(index).end != self.span(index + 1).start;
+            let operand = match self.token(index + 1) {
+                Some(next) if Self::starts_term(next) => true,
+                Some(next) if Operator::prefix(next).is_some() => !(attached && spaced),
+                _ => false,
+            };
+            if operand {
+                return None;
+            }
+        }
+        Operator::postfix(token)
+    }
+    fn parse_prefix(&mut self, index: usize) -> (Node, usize) {
+        let operator = match self.token(index) {
+            Some(token) => Operator::prefix(token),
+            None => None,
+        };
+        match operator {
+            Some(operator) => {
+                // Allows + and - symbols in front of expression.
+                let (right, i) = self.parse_binary(index + 1, operator.next_precedence());
+                let node = match operator.syntax {
+                    Syntax::Function(_) | Syntax::Not => Node::new(Some(right), None, operator.syntax),
+                    _ => {
+                        let zero = Node::from_syntax(Syntax::Real(0.0, Literal::Decimal)).with_span(self.span(index));
+                        Node::from(zero, right, operator.syntax)
+                    }
+                };
+                (node.with_span(self.range(index, i)), i)
+            }
+            None => self.parse_operand(index),
         }
     }
-    fn parse_term(tokens: &Vec<Token>, index: usize) -> Result<(Node, usize), SyntaxError> {
-        let token = tokens.get(index).ok_or(SyntaxError::UnexpectedEndOfInput(index))?;
+    // Like parse_term, but a term is required here.
+    fn parse_operand(&mut self, index: usize) -> (Node, usize) {
+        match self.parse_term(index) {
+            Some(result) => result,
+            None => {
+                let span = self.span(index);
+                match self.token(index) {
+                    Some(_) => self.error(SyntaxError::MissingOperand(span)),
+                    None => self.error(SyntaxError::UnexpectedEndOfInput(span)),
+                }
+                (Node::error().with_span(self.range(index, index)), index)
+            }
+        }
+    }
+    // Returns None if no term starts at index.
+    fn parse_term(&mut self, index: usize) -> Option<(Node, usize)> {
+        let (node, next_index) = self.parse_term_syntax(index)?;
+        Some((node.with_span(self.range(index, next_index)), next_index))
+    }
+    fn parse_term_syntax(&mut self, index: usize) -> Option<(Node, usize)> {
+        let span = self.span(index);
+        let token = self.token(index)?;
         match token {
             Token::Variable(name) => {
-                let node= Node::from_syntax(Syntax::Variable(format!("{}", name)));
-                Self::parse_implicit(tokens, index + 1, node)
+                Some((Node::from_syntax(Syntax::Variable(name.to_string())), index + 1))
             }
-            Token::Function(name) => {
-                // TODO: handle case where no opening paren is present.
-                // TODO: that should be done for every delimiter!
-                Self::parse_delimiter(tokens, index + 2, Syntax::Function(format!("{}", name)), Token::ParenthesisClosed)             
+            Token::Function(name, arity) => {
+                Some(self.parse_function(index, name, *arity))
             },
-            Token::Real(value) => {
-                let node = Node::from_syntax(Syntax::Real(*value));
-                Self::parse_implicit(tokens, index + 1, node)
+            Token::Real(value, literal) => {
+                Some((Node::from_syntax(Syntax::Real(*value, *literal)), index + 1))
             },
-            Token::Imaginary(value) => {
-                let node = Node::from_syntax(Syntax::Imaginary(*value));
-                Self::parse_implicit(tokens, index + 1, node)
+            Token::Imaginary(value, literal) => {
+                Some((Node::from_syntax(Syntax::Imaginary(*value, *literal)), index + 1))
             },
+            // Prefix operators that start a term, so "2√z" is "2 * √z".
+            Token::SquareRoot => Some(self.parse_prefix(index)),
             Token::ParenthesisOpen => {
-                Self::parse_delimiter(tokens, index + 1, Syntax::Parenthesis, Token::ParenthesisClosed)             
+                Some(self.parse_delimiter(index + 1, span, Syntax::Parenthesis, Token::ParenthesisClosed))
             },
-            /*Token::ParenthesisClosed => {
-                // TODO: fix
-                Err(SyntaxError::MissingClosingDelimiter(index))
-            }*/
             Token::AbsoluteOpen => {
-                Self::parse_delimiter(tokens, index + 1, Syntax::Absolute, Token::AbsoluteClosed)             
+                Some(self.parse_delimiter(index + 1, span, Syntax::Absolute, Token::AbsoluteClosed))
             }
-    
-            Token::Invalid(invalid) => Err(SyntaxError::TokenError(invalid.clone())),
-            _ => Err(SyntaxError::UnexpectedToken(index)),
-        }
-    }
-    fn parse_delimiter(tokens: &Vec<Token>, index: usize, syntax: Syntax, closing_delimiter: Token) -> Result<(Node, usize), SyntaxError> {
-        match Self::parse_expression(tokens, index) {
-            Ok((node, next_index)) => {
-                match tokens.get(next_index) {
-                    Some(token) => {
-                        if *token == closing_delimiter {
-                           let node = Node::new(Some(node), None, syntax);
-                           Self::parse_implicit(tokens, next_index + 1, node)
-                        } else {
-                            Err(SyntaxError::MissingClosingDelimiter(index))
-                        }
-                    }
-                    _ => Err(SyntaxError::MissingClosingDelimiter(index))
-                }
+            Token::BracketOpen => {
+                Some(self.parse_delimiter(index + 1, span, Syntax::Bracket, Token::BracketClosed))
+            }
+            Token::BraceOpen => {
+                Some(self.parse_delimiter(index + 1, span, Syntax::Brace, Token::BraceClosed))
             }
-            Err(SyntaxError::UnexpectedToken(_)) => {
-                Err(SyntaxError::EmptyDelimiter(index))
+            Token::FloorOpen => {
+                Some(self.parse_delimiter(index + 1, span, Syntax::Floor, Token::FloorClosed))
             }
-            Err(SyntaxError::UnexpectedEndOfInput(_))=> {
-                Err(SyntaxError::MissingClosingDelimiter(index))
+            Token::CeilOpen => {
+                Some(self.parse_delimiter(index + 1, span, Syntax::Ceil, Token::CeilClosed))
             }
-            Err(error) => Err(error),
+            Token::NormOpen => {
+                Some(self.parse_delimiter(index + 1, span, Syntax::Norm, Token::NormClosed))
+            }
+            // "if(c, a, b)" is the same as "c ? a : b".
+            Token::If => match self.token(index + 1) {
+                Some(Token::ParenthesisOpen) => {
+                    let (mut node, next_index) = self.parse_call(index + 1, span, "if", 3);
+                    node.syntax = Syntax::Condition;
+                    Some((node, next_index))
+                }
+                _ => {
+                    self.error(SyntaxError::MissingParenthesis(span));
+                    Some((Node::error(), index + 1))
+                }
+            }
+            Token::Sum | Token::Product | Token::Iterate => Some(self.parse_loop(index)),
+
+            // Already reported by the lexer.
+            Token::Invalid(_) => Some((Node::error(), index + 1)),
+            _ => None,
         }
     }
-    fn parse_implicit(tokens: &Vec<Token>, index: usize, node: Node) -> Result<(Node, usize), SyntaxError> {
-        match Self::parse_term(tokens, index) {
-            Ok((right, next_index)) => {
-                // Allows implicit multiplication between terms.
-                Ok((Node::from(node, right, Syntax::Multiplication), next_index))
+    // Tokens parse_term accepts.
+    fn starts_term(token: &Token) -> bool {
+        matches!(token,
+            Token::Variable(_) | Token::Function(..) | Token::Real(..) | Token::Imaginary(..) |
+            Token::ParenthesisOpen | Token::AbsoluteOpen | Token::SquareRoot | Token::Invalid(_) |
+            Token::BracketOpen | Token::BraceOpen | Token::FloorOpen | Token::CeilOpen | Token::NormOpen |
+            Token::If | Token::Sum | Token::Product | Token::Iterate
+        )
+    }
+    // Branches of "c ? a : b", chained like the arguments of a function.
+    fn parse_branches(&mut self, index: usize, precedence: u8) -> (Node, usize) {
+        self.closing.push(Token::Colon);
+        let (consequent, mut next_index) = self.parse_expression(index);
+        self.closing.pop();
+
+        let alternative = match self.token(next_index) {
+            Some(Token::Colon) => {
+                let (alternative, i) = self.parse_binary(next_index + 1, precedence);
+                next_index = i;
+                alternative
             }
-            Err(SyntaxError::UnexpectedEndOfInput(_)) | Err(SyntaxError::UnexpectedToken(_))=> {
-                Ok((node, index))
+            _ => {
+                self.error(SyntaxError::MissingAlternative(self.span(next_index)));
+                Node::error().with_span(self.range(next_index, next_index))
             }
-            Err(error) => Err(error),
-        }
+        };
+        let span = alternative.span;
+        let alternative = Node::new(Some(alternative), None, Syntax::Argument).with_span(span);
+        (Node::new(Some(consequent), Some(alternative), Syntax::Argument).with_span(self.range(index, next_index)), next_index)
     }
-    
+    // Functions are either called with parentheses "sin(z)", or applied to the term after them "sin 2z".
+    // Without parentheses the argument is the implicit product that follows, up to the next function,
+    // so "sin 2z cos z" is "sin(2z) * cos(z)", "sin z^2" is "sin(z^2)" and "sin z * 2" is "sin(z) * 2".
+    // A power right after the name applies to the result, "sin^2 z" is "sin(z)^2",
+    // except for "^-1", which is the inverse function, "sin^-1 z" is "asin(z)".
+    fn parse_function(&mut self, index: usize, name: &str, arity: usize) -> (Node, usize) {
+        let span = self.span(index);
+        let mut name = name.to_string();
+        let mut next_index = index + 1;
 
+        let mut power = None;
+        if self.token(next_index) == Some(&Token::Exponent) {
+            let (exponent, i) = self.parse_binary(next_index + 1, Operator::EXPONENT);
+            next_index = i;
+            if exponent.constant() == Some(-1.0) {
+                let inverse = Keyword::inverse(&name)
+                    .filter(|inverse| self.keywords.contains(&Keyword::Function(inverse.to_string(), 1)));
+                match inverse {
+                    Some(inverse) => name = inverse.to_string(),
+                    None => self.error(SyntaxError::MissingInverse(span.join(self.span(next_index - 1)))),
+                }
+            } else {
+                power = Some(exponent);
+            }
+        }
 
+        let (node, next_index) = match self.token(next_index) {
+            Some(Token::ParenthesisOpen) => self.parse_call(next_index, span, &name, arity),
+            _ => self.parse_application(next_index, span, &name, arity),
+        };
+        let node = node.with_span(self.range(index, next_index));
+        match power {
+            Some(exponent) => (Node::from(node, exponent, Syntax::Exponent), next_index),
+            None => (node, next_index),
+        }
+    }
+    fn parse_application(&mut self, index: usize, name_span: Span, name: &str, arity: usize) -> (Node, usize) {
+        let starts_term = match self.token(index) {
+            Some(token) => Self::starts_term(token),
+            None => false,
+        };
+        if arity != 1 || !starts_term {
+            self.error(SyntaxError::MissingParenthesis(name_span));
+        }
+        if !starts_term {
+            let argument = Node::error().with_span(self.range(index, index));
+            return (Node::new(Some(argument), None, Syntax::Function(name.to_string())), index);
+        }
 
+        let applied = self.applied;
+        self.applied = true;
+        let (argument, next_index) = self.parse_binary(index, Operator::IMPLICIT);
+        self.applied = applied;
+        (Node::new(Some(argument), None, Syntax::Function(name.to_string())), next_index)
+    }
+    // The open span points at the opening delimiter, so errors can refer back to it.
+    fn parse_delimiter(&mut self, index: usize, open: Span, syntax: Syntax, closing_delimiter: Token) -> (Node, usize) {
+        let applied = self.applied;
+        self.applied = false;
+        self.closing.push(closing_delimiter.clone());
+        let (node, next_index) = match self.token(index) {
+            Some(token) if *token == closing_delimiter => {
+                self.error(SyntaxError::EmptyDelimiter(open.join(self.span(index))));
+                (Node::error().with_span(self.range(index, index)), index)
+            }
+            _ => self.parse_expression(index),
+        };
+        self.closing.pop();
+        self.applied = applied;
 
-    // TODO fix optimize since keywords are strings now.
-    pub fn optimize(&mut self) {
-        self.tree = self.tree.as_ref().and_then(|root| { Some(Self::optimize_child(&root)) });
+        let node = Node::new(Some(node), None, syntax);
+        match self.token(next_index) {
+            Some(token) if *token == closing_delimiter => {
+                (node, next_index + 1)
+            }
+            _ => {
+                self.error(SyntaxError::MissingClosingDelimiter(open));
+                (node, next_index)
+            }
+        }
+    }
+    // Call with its arguments in parentheses, index points at the opening parenthesis.
+    fn parse_call(&mut self, index: usize, name_span: Span, name: &str, arity: usize) -> (Node, usize) {
+        let (arguments, next_index, end) = self.parse_arguments(index + 1, self.span(index));
+        if arguments.len() != arity {
+            let span = name_span.join(end);
+            self.error(SyntaxError::ArgumentCount(span, arity, arguments.len()));
+        }
+        (Node::chain(arguments, Syntax::Function(name.to_string())), next_index)
     }
-    fn optimize_child(root: &Node) -> Node {
-        let mut new = root.clone();
+    // Arguments separated by commas up to and including the closing parenthesis,
+    // also returns the span of the closing parenthesis.
+    fn parse_arguments(&mut self, index: usize, open: Span) -> (Vec<Node>, usize, Span) {
+        let mut arguments = Vec::new();
+        let mut next_index = index;
 
-        new.left = match &root.left {
-            Some(left_node) => Some(Box::new(Self::optimize_child(&*left_node))),
-            None => None,
-        };
-        new.right = match &root.right {
-            Some(right_node) => Some(Box::new(Self::optimize_child(&*right_node))),
-            None => None,
-        };
-    
-    
-        match &root.syntax {
-            Syntax::Addition => match new.child_syntax() {
-                // x + y
-                (Syntax::Real(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Real(lhs + rhs);
-                }
-                // xi + yi
-                (Syntax::Imaginary(lhs), Syntax::Imaginary(rhs)) => {
-                    new.syntax = Syntax::Imaginary(lhs + rhs);
-                }
-    
-    
-                // a + a = 2a
-                (Syntax::Variable(id_lhs), Syntax::Variable(id_rhs)) => {
-                    if id_lhs == id_rhs {
-                        new.syntax = Syntax::Multiplication;
-                        new.left = Some(Box::new(Node::from_syntax(Syntax::Real(2.0))));
-                    }
-                }
-                // a + xa = (x + 1)a
-                (Syntax::Variable(id_lhs), Syntax::Multiplication) => {
-                    if let Some(op_rhs) = &mut new.right {
-                        match op_rhs.child_syntax() {
-                            // ax
-                            (Syntax::Variable(id_rhs), Syntax::Real(value)) => {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Multiplication;
-                                    **op_rhs = Node::from_syntax(Syntax::Real(value + 1.0));
-                                }
-                            }
-                            // xa
-                            (Syntax::Real(value), Syntax::Variable(id_rhs)) => {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Multiplication;
-                                    **op_rhs = Node::from_syntax(Syntax::Real(value + 1.0));
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                // xa + a = (x + 1)a
-                (Syntax::Multiplication, Syntax::Variable(id_rhs)) => {
-                    if let Some(op_lhs) = &mut new.left {
-                        match op_lhs.child_syntax() {
-                            // ax
-                            (Syntax::Variable(id_lhs), Syntax::Real(value)) => {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Multiplication;
-                                    **op_lhs = Node::from_syntax(Syntax::Real(value + 1.0));
-                                }
-                            }
-                            // xa
-                            (Syntax::Real(value), Syntax::Variable(id_lhs)) => {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Multiplication;
-                                    **op_lhs = Node::from_syntax(Syntax::Real(value + 1.0));
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
+        let applied = self.applied;
+        self.applied = false;
+        self.closing.push(Token::ParenthesisClosed);
+        self.closing.push(Token::Comma);
+        if self.token(next_index) != Some(&Token::ParenthesisClosed) {
+            loop {
+                let (argument, i) = self.parse_expression(next_index);
+                arguments.push(argument);
+                next_index = i;
+                match self.token(next_index) {
+                    Some(Token::Comma) => next_index += 1,
+                    _ => break,
                 }
-                _ => (),
             }
-            Syntax::Subtraction => match new.child_syntax() {
-                // x - y
-                (Syntax::Real(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Real(lhs - rhs);
-                }
-                // xi - yi
-                (Syntax::Imaginary(lhs), Syntax::Imaginary(rhs)) => {
-                    new.syntax = Syntax::Imaginary(lhs - rhs);
-                }
-                _ => (),
+        }
+        self.closing.pop();
+        self.closing.pop();
+        self.applied = applied;
+
+        let end = match self.token(next_index) {
+            Some(Token::ParenthesisClosed) => {
+                next_index += 1;
+                self.span(next_index - 1)
             }
-            Syntax::Multiplication => match new.child_syntax() {
-                // x * y = xy
-                (Syntax::Real(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Real(lhs * rhs);
-                }
-                // xi * yi = xy
-                (Syntax::Imaginary(lhs), Syntax::Imaginary(rhs)) => {
-                    let value = (Complex::new(0.0, lhs) * Complex::new(0.0, rhs)).re;
-                    new.syntax = Syntax::Real(value);
-                }
-                // xi * y = xiy
-                (Syntax::Imaginary(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Imaginary(lhs / rhs);
-                }
-                // x * yi = xyi
-                (Syntax::Real(lhs), Syntax::Imaginary(rhs)) => {
-                    new.syntax = Syntax::Imaginary(lhs / rhs);
-                }
-    
-    
-                // a * a = a^2
-                (Syntax::Variable(id_lhs), Syntax::Variable(id_rhs)) => {
-                    if id_lhs == id_rhs {
-                        if let Some(node) = &mut new.right {
-                            new.syntax = Syntax::Exponent;
-                            node.syntax = Syntax::Real(2.0);
-                        }
-                    }
-                }
-                // a * a^n = a^(n + 1)
-                (Syntax::Variable(id_lhs), Syntax::Exponent) => {
-                    if let Some(rhs) = &mut new.right {
-                        match rhs.child_syntax() {
-                            (Syntax::Variable(id_rhs), Syntax::Real(value)) => {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Exponent;
-                                    **rhs = Node::from_syntax(Syntax::Real(value + 1.0));
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                // a^n * a = a^(n + 1)
-                (Syntax::Exponent, Syntax::Variable(id_rhs)) => {
-                    if let Some(op_lhs) = &mut new.left {
-                        match op_lhs.child_syntax() {
-                            (Syntax::Variable(id_lhs), Syntax::Real(value)) => {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Exponent;
-                                    new.right = Some(Box::new(Node::from_syntax(Syntax::Real(value + 1.0))));
-                                    new.left = Some(Box::new(Node::from_syntax(Syntax::Variable(id_lhs))));
-                                }
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                // a^n * a^k = a^(n + k)
-                (Syntax::Exponent, Syntax::Exponent) => {
-                    if let (Some(op_lhs), Some(op_rhs)) = (&mut new.left, &mut new.right) {
-                        if let (Syntax::Variable(id_lhs), Syntax::Real(value_lhs)) = op_lhs.child_syntax() {
-                            if let (Syntax::Variable(id_rhs), Syntax::Real(value_rhs)) = op_rhs.child_syntax() {
-                                if id_lhs == id_rhs {
-                                    new.syntax = Syntax::Exponent;
-                                    new.right = Some(Box::new(Node::from_syntax(Syntax::Real(value_lhs + value_rhs))));
-                                    new.left = Some(Box::new(Node::from_syntax(Syntax::Variable(id_lhs))));
-                                }
-                            }
-                        }
-                    }
-                }
-    
-                // a * xa^k
-                _ => (),
+            _ => {
+                self.error(SyntaxError::MissingClosingDelimiter(open));
+                self.span(next_index)
             }
-            Syntax::Division => match new.child_syntax() {
-                (Syntax::Real(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Real(lhs / rhs);
-                }
-                (Syntax::Imaginary(lhs), Syntax::Imaginary(rhs)) => {
-                    let value = (Complex::new(0.0, lhs) / Complex::new(0.0, rhs)).re;
-                    new.syntax = Syntax::Real(value);
-                }
-                (Syntax::Imaginary(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Imaginary(lhs * rhs);
-                }
-                (Syntax::Real(lhs), Syntax::Imaginary(rhs)) => {
-                    new.syntax = Syntax::Imaginary(lhs * rhs);
+        };
+        (arguments, next_index, end)
+    }
+    // "sum(k, a, b, expr)" and "prod(k, a, b, expr)" add or multiply expr for every integer k from a to b.
+    // "iter(n, w = z0, expr)" starts with w = z0 and replaces w with expr n times, "iter(n, z0, expr)" does the same with z.
+    fn parse_loop(&mut self, index: usize) -> (Node, usize) {
+        let span = self.span(index);
+        let open = index + 1;
+        if self.token(open) != Some(&Token::ParenthesisOpen) {
+            self.error(SyntaxError::MissingParenthesis(span));
+            return (Node::error(), index + 1);
+        }
+
+        let mut arguments = Vec::new();
+        let (start, separator, arity) = match self.token(index) {
+            Some(Token::Iterate) => {
+                let applied = self.applied;
+                self.applied = false;
+                self.closing.push(Token::ParenthesisClosed);
+                self.closing.push(Token::Comma);
+                let (count, i) = self.parse_expression(open + 1);
+                self.closing.pop();
+                self.closing.pop();
+                self.applied = applied;
+                arguments.push(count);
+                match self.token(i) {
+                    Some(Token::Comma) => (i + 1, Token::Assignment, 3),
+                    _ => (i, Token::Assignment, 3),
                 }
-                _ => (),
             }
-            Syntax::Exponent => match new.child_syntax() {
-                (Syntax::Real(lhs), Syntax::Real(rhs)) => {
-                    new.syntax = Syntax::Real(lhs.powf(rhs));
+            _ => (open + 1, Token::Comma, 4),
+        };
+        let binding = match (self.token(start), self.token(start + 1)) {
+            (Some(Token::Variable(name)), Some(token)) if *token == separator => Some((name.to_string(), start + 2)),
+            _ if self.token(index) == Some(&Token::Iterate) => Some((ITERATE_BINDING.to_string(), start)),
+            _ => None,
+        };
+
+        match binding {
+            Some((name, first)) => {
+                let (mut rest, next_index, end) = self.parse_arguments(first, self.span(open));
+                arguments.append(&mut rest);
+                // The name of sum and prod is an argument of its own, the one of iter is part of the start value.
+                let found = arguments.len() + (arity - 3);
+                if found != arity {
+                    self.error(SyntaxError::ArgumentCount(span.join(end), arity, found));
                 }
-                _ => (),
-                //(Syntax::Imaginary(lhs), Syntax::Imaginary(rhs)) => {
-                //    let value = (Complex::new(0.0, lhs) / Complex::new(0.0, rhs)).re;
-                //    new.syntax = Syntax::Real(value);
-                //}
-                //(Syntax::Imaginary(lhs), Syntax::Real(rhs)) => {
-                //    new.syntax = Syntax::Imaginary(lhs * rhs);
-                //}
-                //(Syntax::Real(lhs), Syntax::Imaginary(rhs)) => {
-                //    new.syntax = Syntax::Imaginary(lhs * rhs);
-                //}
-            }
-            _ => (),
+                let syntax = match self.token(index) {
+                    Some(Token::Sum) => Syntax::Sum(name),
+                    Some(Token::Product) => Syntax::Product(name),
+                    _ => Syntax::Iterate(name),
+                };
+                (Node::chain(arguments, syntax), next_index)
+            }
+            None => {
+                self.error(SyntaxError::MissingBinding(self.span(start)));
+                let (_, next_index, _) = self.parse_arguments(start, self.span(open));
+                (Node::error(), next_index)
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ Expression, Literal, Node, Syntax };
+    use super::super::interpreter::FUNCTIONS;
+    use super::super::keyword::Keyword;
+
+    fn keywords() -> Vec<Keyword> {
+        let mut keywords = Keyword::vars(&vec!["z"]);
+        keywords.append(&mut Keyword::funcs(&FUNCTIONS.to_vec()));
+        keywords
+    }
+    fn parse(input: &str) -> Node {
+        let expression = Expression::parse(input, &keywords());
+        assert!(expression.is_valid(), "'{}': {}", input, expression.pretty_error());
+        expression.tree.unwrap()
+    }
+    // Every input has the same tree as the one with explicit parentheses next to it.
+    fn check_grouping(cases: &[(&str, &str)]) {
+        for &(input, grouped) in cases {
+            let tree = parse(input);
+            assert!(tree.same(&parse(grouped)), "'{}' is parsed as '{}', expected '{}'", input, tree.to_source(), grouped);
+        }
+    }
+
+    #[test]
+    fn operators_associate() {
+        check_grouping(&[
+            ("1-2-3", "(1-2)-3"),
+            ("8/4/2", "(8/4)/2"),
+            ("1-2+3", "(1-2)+3"),
+            ("2^3^2", "2^(3^2)"),
+            ("-2^2", "-(2^2)"),
+            ("2z^2", "2*(z^2)"),
+        ]);
+    }
+
+    #[test]
+    fn number_literals() {
+        let cases = [
+            ("1e-6", Syntax::Real(1e-6, Literal::Scientific)),
+            ("2.5E3", Syntax::Real(2500.0, Literal::Scientific)),
+            ("3e2i", Syntax::Imaginary(300.0, Literal::Scientific)),
+            ("0x1f", Syntax::Real(31.0, Literal::Hexadecimal)),
+            ("0b101", Syntax::Real(5.0, Literal::Binary)),
+            ("1_000.5", Syntax::Real(1000.5, Literal::Decimal)),
+        ];
+        for (input, syntax) in cases.iter() {
+            assert_eq!(parse(input).syntax, *syntax, "'{}'", input);
+        }
+    }
+
+    #[test]
+    fn unicode_symbols() {
+        check_grouping(&[
+            ("z²", "z^2"),
+            ("2z³", "2*(z^3)"),
+            ("√z", "sqrt(z)"),
+            ("2×3÷z", "(2*3)/z"),
+            ("2·z", "2*z"),
+            ("3−z", "3-z"),
+            ("2π", "2*π"),
+        ]);
+    }
+
+    #[test]
+    fn functions_without_parentheses() {
+        check_grouping(&[
+            ("sin z", "sin(z)"),
+            ("exp 2z", "exp(2*z)"),
+            ("sin^2(z)", "(sin(z))^2"),
+            ("sin^-1 z", "asin(z)"),
+            ("cos^-1(z)", "acos(z)"),
+        ]);
+        let messages = |input: &str| -> Vec<String> {
+            Expression::parse(input, &keywords()).diagnostics.iter().map(|diagnostic| diagnostic.message.to_string()).collect()
+        };
+        assert_eq!(messages("sqrt^-1 z"), vec!["Function has no known inverse"]);
+    }
+
+    #[test]
+    fn nested_delimiters() {
+        let cases = [
+            ("||z|-1|", "||z| - 1|"),
+            ("|z|-|1|", "|z| - |1|"),
+            ("[z+1]{2}", "[z + 1] * {2}"),
+            ("⌊z⌋+⌈z⌉", "⌊z⌋ + ⌈z⌉"),
+            ("‖z‖", "‖z‖"),
+        ];
+        for &(input, source) in &cases {
+            assert_eq!(parse(input).to_source(), source, "'{}'", input);
+        }
+    }
+
+    #[test]
+    fn postfix_operators() {
+        check_grouping(&[
+            ("2^3!", "2^(3!)"),
+            ("-z!", "-(z!)"),
+            ("2z'", "2*(z')"),
+            ("z'^2", "(z')^2"),
+            ("30°+z", "(30°)+z"),
+            ("50%z", "(50%)*z"),
+        ]);
+    }
+
+    // iter binds z unless its start value is assigned to a name, inner loops hide the names of outer ones.
+    #[test]
+    fn loops() {
+        check_grouping(&[
+            ("iter(3, 1, z^2)", "iter(3, z = 1, z^2)"),
+            ("sum(k, 1, 3, k) + z", "(sum(k, 1, 3, k)) + z"),
+            ("prod(k, 1, 3, z - k)", "prod(k, 1, 3, (z - k))"),
+        ]);
+        let bound = |input: &str| -> Vec<String> {
+            let mut names = Vec::new();
+            let mut nodes = vec![parse(input)];
+            while let Some(node) = nodes.pop() {
+                names.extend(node.binding().map(|name| name.to_string()));
+                nodes.extend(node.left.map(|left| *left));
+                nodes.extend(node.right.map(|right| *right));
+            }
+            names
+        };
+        assert_eq!(bound("iter(2, 1, z + 1)"), vec!["z"]);
+        assert_eq!(bound("iter(2, w = z, w^2)"), vec!["w"]);
+        assert_eq!(bound("sum(k, 1, 2, sum(k, 1, 3, k))"), vec!["k", "k"]);
+
+        let messages = |input: &str| -> Vec<String> {
+            Expression::parse(input, &keywords()).diagnostics.iter().map(|diagnostic| diagnostic.message.to_string()).collect()
         };
-        new
+        assert_eq!(messages("sum(1, 3, z)"), vec!["Expected the name the loop binds"]);
+        assert_eq!(messages("sum(k, 1, 3, k) + k"), vec!["Variable 'k' is used outside of its loop"]);
     }
 }
@@ -0,0 +1,282 @@
+use super::parser::{ Expression, Literal, Node, Operator, Syntax, Token };
+
+
+
+// Precedence of terms that never need parentheses, like numbers, calls and delimiters.
+pub const ATOM: u8 = u8::MAX;
+
+
+
+impl Node {
+    // Infix source with as few parentheses as the operator table allows,
+    // parsing it again gives the same tree, except for parentheses.
+    pub fn to_source(&self) -> String {
+        match &self.syntax {
+            Syntax::Ignore => String::new(),
+            // Keeps the expression invalid when it is parsed again.
+            Syntax::Error => "_".to_string(),
+
+            Syntax::Real(value, literal) => Self::number(*value, literal, ""),
+            Syntax::Imaginary(value, literal) => Self::number(*value, literal, "i"),
+            Syntax::Variable(name) => name.to_string(),
+            Syntax::Function(name) => format!("{}({})", name, self.source_arguments()),
+
+            Syntax::Parenthesis | Syntax::Argument => self.source_left(0),
+            Syntax::Absolute => format!("|{}|", self.source_left(0)),
+            Syntax::Bracket => format!("[{}]", self.source_left(0)),
+            Syntax::Brace => format!("{{{}}}", self.source_left(0)),
+            Syntax::Floor => format!("⌊{}⌋", self.source_left(0)),
+            Syntax::Ceil => format!("⌈{}⌉", self.source_left(0)),
+            Syntax::Norm => format!("‖{}‖", self.source_left(0)),
+
+            // The consequent is closed by the colon, so it never needs parentheses.
+            Syntax::Condition => {
+                let arguments = self.arguments();
+                let source = |index: usize, precedence: u8| match arguments.get(index) {
+                    Some(argument) => argument.source_child(precedence),
+                    None => "_".to_string(),
+                };
+                format!("{} ? {} : {}", source(0, Operator::CONDITIONAL + 1), source(1, 0), source(2, Operator::CONDITIONAL))
+            }
+
+            Syntax::Sum(name) | Syntax::Product(name) => {
+                let function = match &self.syntax {
+                    Syntax::Sum(_) => "sum",
+                    _ => "prod",
+                };
+                format!("{}({}, {})", function, name, self.source_arguments())
+            }
+            Syntax::Iterate(name) => {
+                let arguments: Vec<String> = self.arguments().iter().map(|argument| argument.to_source()).collect();
+                match &arguments[..] {
+                    [count, start, rest @ ..] => format!("iter({}, {} = {}, {})", count, name, start, rest.join(", ")),
+                    _ => format!("iter({})", arguments.join(", ")),
+                }
+            }
+
+            _ => match (Self::operator(&self.syntax), self.prefix()) {
+                // "0 - x" is parsed the same as "-x".
+                (Some((_, symbol)), true) => {
+                    format!("{}{}", symbol, self.source_after_sign(Operator::PREFIX))
+                }
+                (Some((operator, symbol)), false) => {
+                    let (left, right) = operator.operand_precedence();
+                    match (&self.syntax, symbol) {
+                        (Syntax::Exponent, _) => format!("{}{}{}", self.source_left(left), symbol, self.source_right(right)),
+                        (Syntax::Subtraction, _) => format!("{} {} {}", self.source_left(left), symbol, self.source_after_sign(right)),
+                        (Syntax::Not, _) => format!("{}{}", symbol, self.source_left(operator.next_precedence())),
+                        (_, _) if Self::postfix(&self.syntax) => format!("{}{}", self.source_left(Operator::POSTFIX), symbol),
+                        _ => format!("{} {} {}", self.source_left(left), symbol, self.source_right(right)),
+                    }
+                }
+                (None, _) => "_".to_string(),
+            },
+        }
+    }
+    fn source_left(&self, precedence: u8) -> String {
+        match &self.left {
+            Some(left) => left.source_child(precedence),
+            None => "_".to_string(),
+        }
+    }
+    fn source_right(&self, precedence: u8) -> String {
+        match &self.right {
+            Some(right) => right.source_child(precedence),
+            None => "_".to_string(),
+        }
+    }
+    // A sign right after another one is wrapped, "z - (-3)" instead of "z - -3".
+    fn source_after_sign(&self, precedence: u8) -> String {
+        match &self.right {
+            Some(right) if right.precedence() == Operator::PREFIX => format!("({})", right.to_source()),
+            _ => self.source_right(precedence),
+        }
+    }
+    // Parentheses are added if the child binds looser than the place it's printed at.
+    fn source_child(&self, precedence: u8) -> String {
+        match self.precedence() < precedence {
+            true => format!("({})", self.to_source()),
+            false => self.to_source(),
+        }
+    }
+    fn source_arguments(&self) -> String {
+        self.arguments().iter()
+            .map(|argument| argument.to_source())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+    // Negative numbers only come from folding constants, they are printed and wrapped like a sign.
+    fn number(value: f64, literal: &Literal, suffix: &str) -> String {
+        match value {
+            _ if value.is_nan() => "(0 / 0)".to_string(),
+            _ if value == f64::INFINITY => format!("∞{}", suffix),
+            _ if value < 0.0 => format!("-{}", Self::number(-value, literal, suffix)),
+            _ => format!("{}{}", literal.format(value), suffix),
+        }
+    }
+
+    // Precedence of the node as it's printed.
+    pub fn precedence(&self) -> u8 {
+        match &self.syntax {
+            Syntax::Parenthesis | Syntax::Argument => match &self.left {
+                Some(left) => left.precedence(),
+                None => ATOM,
+            },
+            Syntax::Addition | Syntax::Subtraction if self.prefix() => Operator::PREFIX,
+            Syntax::Real(value, _) | Syntax::Imaginary(value, _) if *value < 0.0 => Operator::PREFIX,
+            syntax => match Self::operator(syntax) {
+                Some((operator, _)) => operator.precedence,
+                None => ATOM,
+            },
+        }
+    }
+    // Signs in front of a term are parsed as "0 + x" and "0 - x".
+    pub fn prefix(&self) -> bool {
+        let zero = match &self.left {
+            Some(left) => left.syntax == Syntax::Real(0.0, Literal::Decimal),
+            None => false,
+        };
+        zero && matches!(self.syntax, Syntax::Addition | Syntax::Subtraction)
+    }
+    pub fn postfix(syntax: &Syntax) -> bool {
+        matches!(syntax, Syntax::Factorial | Syntax::Conjugate | Syntax::Degree | Syntax::Percent)
+    }
+    // Entry of the operator table and the symbol that is printed for it.
+    pub fn operator(syntax: &Syntax) -> Option<(Operator, &'static str)> {
+        let (token, symbol) = match syntax {
+            Syntax::Addition        => (Token::Addition, "+"),
+            Syntax::Subtraction     => (Token::Subtraction, "-"),
+            Syntax::Multiplication  => (Token::Multiplication, "*"),
+            Syntax::Division        => (Token::Division, "/"),
+            Syntax::Exponent        => (Token::Exponent, "^"),
+            Syntax::Less            => (Token::Less, "<"),
+            Syntax::Greater         => (Token::Greater, ">"),
+            Syntax::LessEqual       => (Token::LessEqual, "<="),
+            Syntax::GreaterEqual    => (Token::GreaterEqual, ">="),
+            Syntax::Equal           => (Token::Equal, "=="),
+            Syntax::NotEqual        => (Token::NotEqual, "!="),
+            Syntax::And             => (Token::And, "and"),
+            Syntax::Or              => (Token::Or, "or"),
+            Syntax::Condition       => (Token::Question, "?"),
+            Syntax::Not             => return Operator::prefix(&Token::Not).map(|operator| (operator, "not ")),
+            Syntax::Factorial       => (Token::Factorial, "!"),
+            Syntax::Conjugate       => (Token::Conjugate, "'"),
+            Syntax::Degree          => (Token::Degree, "°"),
+            Syntax::Percent         => (Token::Percent, "%"),
+            _ => return None,
+        };
+        Operator::binary(&token).or_else(|| Operator::postfix(&token)).map(|operator| (operator, symbol))
+    }
+
+
+    // Compares two trees, ignoring parentheses and spans. NaN is the same as itself, like after loading a folded 0 / 0.
+    pub fn same(&self, other: &Node) -> bool {
+        let (lhs, rhs) = (self.ungrouped(), other.ungrouped());
+        let child = |lhs: &Option<Box<Node>>, rhs: &Option<Box<Node>>| match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => lhs.same(rhs),
+            (None, None) => true,
+            _ => false,
+        };
+        let syntax = match (&lhs.syntax, &rhs.syntax) {
+            (Syntax::Real(lhs, lhs_literal), Syntax::Real(rhs, rhs_literal))
+            | (Syntax::Imaginary(lhs, lhs_literal), Syntax::Imaginary(rhs, rhs_literal)) => {
+                lhs_literal == rhs_literal && (lhs == rhs || (lhs.is_nan() && rhs.is_nan()))
+            }
+            (lhs, rhs) => lhs == rhs,
+        };
+        syntax && child(&lhs.left, &rhs.left) && child(&lhs.right, &rhs.right)
+    }
+    fn ungrouped(&self) -> &Node {
+        match (&self.syntax, &self.left) {
+            (Syntax::Parenthesis, Some(left)) => left.ungrouped(),
+            _ => self,
+        }
+    }
+}
+
+impl Expression {
+    pub fn to_source(&self) -> String {
+        match &self.tree {
+            Some(tree) => tree.to_source(),
+            None => String::new(),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::complex::Complex;
+    use super::super::interpreter::{ Intepreter, FUNCTIONS };
+    use super::super::keyword::Keyword;
+    use super::super::parser::{ Expression, Literal, Node, Syntax };
+    use super::super::testing::{ random_tree, Xorshift };
+
+    // Printed random trees parse back to the same tree.
+    #[test]
+    fn round_trip() {
+        let mut keywords = Keyword::vars(&vec!["z", "x"]);
+        keywords.append(&mut Keyword::funcs(&FUNCTIONS.to_vec()));
+
+        let mut random = Xorshift(0x2545f491);
+        for _ in 0..1000 {
+            let tree = random_tree(&mut random, 4);
+            let source = tree.to_source();
+            match Expression::parse(&source, &keywords).tree {
+                Some(parsed) => assert!(parsed.same(&tree), "'{}' is parsed as\n{}", source, parsed),
+                None => panic!("'{}' has no tree", source),
+            }
+        }
+    }
+
+    // Folded constants are negative numbers, they only get parentheses where a sign would,
+    // and are parsed back to the same value.
+    #[test]
+    fn negative_numbers() {
+        let mut interpreter = Intepreter::new();
+        interpreter.set_variable("z", Complex::new(0.5, 2.0));
+        let keywords = interpreter.keywords();
+        let z = || Node::from_syntax(Syntax::Variable("z".to_string()));
+        let number = |value: f64| Node::from_syntax(Syntax::Real(value, Literal::Decimal));
+        let cases = [
+            (Node::from(z(), number(-3.0), Syntax::Subtraction), "z - (-3)"),
+            (Node::from(number(0.0), number(-3.0), Syntax::Subtraction), "-(-3)"),
+            (Node::from(z(), number(-2.0), Syntax::Exponent), "z^(-2)"),
+            (Node::from(number(-2.0), z(), Syntax::Exponent), "(-2)^z"),
+            (Node::from(number(-4.0), z(), Syntax::Multiplication), "-4 * z"),
+            (Node::from(z(), number(-4.0), Syntax::Addition), "z + -4"),
+            (Node::from(number(-4.0), z(), Syntax::Subtraction), "-4 - z"),
+        ];
+        for (tree, expected) in &cases {
+            let source = tree.to_source();
+            assert_eq!(source, *expected);
+            let parsed = Expression::parse(&source, &keywords);
+            assert_eq!(interpreter.evaluate(&parsed), interpreter.evaluate_node(tree), "'{}'", source);
+        }
+    }
+
+    // JSON loads and folding can give infinite and NaN parts, they are printed as something the parser reads.
+    #[test]
+    fn infinity_and_nan() {
+        let interpreter = Intepreter::new();
+        let keywords = interpreter.keywords();
+        let cases = [
+            (Syntax::Real(f64::INFINITY, Literal::Symbol('∞')), "∞"),
+            (Syntax::Imaginary(f64::INFINITY, Literal::Symbol('∞')), "∞i"),
+            (Syntax::Real(f64::NEG_INFINITY, Literal::Decimal), "-∞"),
+            (Syntax::Imaginary(f64::NEG_INFINITY, Literal::Decimal), "-∞i"),
+            (Syntax::Real(f64::NAN, Literal::Decimal), "(0 / 0)"),
+            (Syntax::Imaginary(f64::NAN, Literal::Decimal), "(0 / 0)"),
+        ];
+        for (syntax, expected) in &cases {
+            let tree = Node::from_syntax(syntax.clone());
+            let source = tree.to_source();
+            assert_eq!(source, *expected);
+            let parsed = Expression::parse(&source, &keywords);
+            assert!(parsed.is_valid(), "'{}': {}", source, parsed.pretty_error());
+            let (value, original) = (interpreter.evaluate(&parsed), interpreter.evaluate_node(&tree));
+            assert!(value == original || (value.is_nan() && original.is_nan()), "'{}' is {}, expected {}", source, value, original);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{ Serialize, Deserialize };
+use super::parser::Expression;
+
+
+
+// Version of the JSON format, increased whenever a change to the parsed types breaks saved expressions.
+pub const SAVE_VERSION: u32 = 1;
+
+
+
+#[derive(Serialize)]
+struct Saved<'a> {
+    version: u32,
+    expression: &'a Expression,
+}
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+#[derive(Deserialize)]
+struct Loaded {
+    expression: Expression,
+}
+
+impl Expression {
+    // Tree, definitions and diagnostics together with the input, so spans stay valid after loading.
+    pub fn to_json(&self) -> String {
+        let saved = Saved {
+            version: SAVE_VERSION,
+            expression: self,
+        };
+        serde_json::to_string(&saved).unwrap_or_default()
+    }
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        // The version is read first, so an old format gives a clear error instead of a missing field.
+        let version = serde_json::from_str::<Version>(json)
+            .map_err(|error| format!("Not a saved expression: {}", error))?
+            .version;
+        if version != SAVE_VERSION {
+            return Err(format!("Saved with format version {}, expected version {}", version, SAVE_VERSION));
+        }
+        serde_json::from_str::<Loaded>(json)
+            .map(|loaded| loaded.expression)
+            .map_err(|error| format!("Invalid saved expression: {}", error))
+    }
+}
+
+
+
+// JSON has no infinity or NaN, folded constants and ∞ are written as strings instead.
+pub mod number {
+    use serde::{ Serialize, Serializer, Deserialize, Deserializer };
+    use serde::de::Error;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Finite(f64),
+        Named(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        let number = match *value {
+            value if value.is_finite() => Number::Finite(value),
+            value if value.is_nan() => Number::Named("nan".to_string()),
+            value if value > 0.0 => Number::Named("inf".to_string()),
+            _ => Number::Named("-inf".to_string()),
+        };
+        number.serialize(serializer)
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        match Number::deserialize(deserializer)? {
+            Number::Finite(value) => Ok(value),
+            Number::Named(name) => match name.as_str() {
+                "nan" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(D::Error::custom(format!("unknown number '{}'", name))),
+            },
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::SAVE_VERSION;
+    use super::super::keyword::Keyword;
+    use super::super::parser::Expression;
+
+    #[test]
+    fn round_trip() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        // Folding gives an infinite imaginary part and NaN, neither of which JSON has.
+        let mut expression = Expression::parse("c = ∞i; f(w) = w / c + 0 / 0; f(z) + c - ∞ + sum(k, 1, 3, z^k)", &keywords);
+        assert!(expression.is_valid(), "{}", expression.pretty_error());
+        expression.optimize();
+        let json = expression.to_json();
+        assert!(json.contains("\"inf\"") && json.contains("\"nan\""), "{}", json);
+
+        let loaded = Expression::from_json(&json).unwrap();
+        assert_eq!(loaded.input, expression.input);
+        assert!(loaded.tree.unwrap().same(&expression.tree.unwrap()));
+        assert_eq!(loaded.definitions.len(), expression.definitions.len());
+        for (loaded, definition) in loaded.definitions.iter().zip(&expression.definitions) {
+            assert_eq!((&loaded.keyword, &loaded.parameters, loaded.span), (&definition.keyword, &definition.parameters, definition.span));
+            assert!(loaded.body.same(&definition.body), "{}", definition.name());
+        }
+        assert_eq!(loaded.diagnostics, expression.diagnostics);
+
+        // Invalid inputs keep their diagnostics.
+        let invalid = Expression::parse("z + ", &keywords);
+        assert_eq!(Expression::from_json(&invalid.to_json()).unwrap().diagnostics, invalid.diagnostics);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        let json = Expression::parse("z^2", &keywords).to_json();
+        let version = format!("\"version\":{}", SAVE_VERSION);
+        assert!(json.starts_with(&format!("{{{}", version)), "{}", json);
+
+        let newer = json.replacen(&version, &format!("\"version\":{}", SAVE_VERSION + 1), 1);
+        let expected = format!("Saved with format version {}, expected version {}", SAVE_VERSION + 1, SAVE_VERSION);
+        assert_eq!(Expression::from_json(&newer).unwrap_err(), expected);
+        assert!(Expression::from_json("{\"tree\": null}").unwrap_err().starts_with("Not a saved expression: "));
+    }
+}
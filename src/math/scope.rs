@@ -0,0 +1,73 @@
+use super::definition::Definition;
+use super::diagnostic::Span;
+use super::parser::{ Lexeme, Node, Syntax, Token };
+
+
+
+// Upper bound for the number of iterations of a single loop in the interpreter,
+// the shader uses the loop_limit uniform instead.
+pub const LOOP_LIMIT: usize = 100_000;
+// Name iter binds when the start value isn't assigned to one, "iter(n, z0, expr)" applies expr to z.
+pub const ITERATE_BINDING: &str = "z";
+
+
+
+impl Token {
+    // Finds the names loops bind before parsing, so they can be lexed as variables.
+    // "sum(k, ..." and "prod(k, ..." bind k, "iter(n, w = ..." binds w and any other iter binds z.
+    pub fn bindings(tokens: &[Lexeme], input: &str) -> Vec<(String, Span)> {
+        let mut bindings = Vec::new();
+        let token = |index: usize| tokens.get(index).map(|lexeme| &lexeme.token);
+
+        for index in 0..tokens.len() {
+            let (start, separator) = match (token(index), token(index + 1)) {
+                (Some(Token::Sum), Some(Token::ParenthesisOpen)) => (index + 2, Token::Comma),
+                (Some(Token::Product), Some(Token::ParenthesisOpen)) => (index + 2, Token::Comma),
+                (Some(Token::Iterate), Some(Token::ParenthesisOpen)) => {
+                    bindings.push((ITERATE_BINDING.to_string(), tokens[index].span));
+                    continue;
+                }
+                // The count of iter can be any expression, so only the binding itself is matched.
+                (Some(Token::Comma), _) => (index + 1, Token::Assignment),
+                _ => continue,
+            };
+            if let Some((name, after)) = Definition::identifier(tokens, input, start) {
+                if token(after) == Some(&separator) {
+                    bindings.push((name, tokens[start].span.join(tokens[after - 1].span)));
+                }
+            }
+        }
+        bindings
+    }
+}
+
+
+
+impl Node {
+    // Name a loop binds for its body, the body is always the last argument.
+    pub fn binding(&self) -> Option<&str> {
+        match &self.syntax {
+            Syntax::Sum(name) | Syntax::Product(name) | Syntax::Iterate(name) => Some(name),
+            _ => None,
+        }
+    }
+    // Bounds and body of a loop.
+    pub fn scoped(&self) -> Option<(&str, Vec<&Node>, &Node)> {
+        let name = self.binding()?;
+        let mut arguments = self.arguments();
+        let body = arguments.pop()?;
+        Some((name, arguments, body))
+    }
+    // Same node with its arguments replaced, the nodes chaining them keep their spans.
+    pub fn with_arguments(&self, arguments: Vec<Node>) -> Self {
+        let mut arguments = arguments.into_iter();
+        let mut node = self.clone();
+        node.left = arguments.next().map(Box::new);
+        let mut next = &mut node.right;
+        while let Some(argument) = next {
+            argument.left = arguments.next().map(Box::new);
+            next = &mut argument.right;
+        }
+        node
+    }
+}
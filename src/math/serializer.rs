@@ -1,4 +1,4 @@
-use super::{ Node, Expression, Syntax };
+use super::{ Dag, Diagnostic, Node, Expression, Span, Syntax, MAX_ITERATIONS, MAX_ROOTS };
 
 pub trait KeywordType 
     where Self: Sized + Clone
@@ -17,6 +17,9 @@ impl KeywordType for Empty {
 
 
 
+// Size of the shader's node buffer, the shader gets it as MAX_NODES, see with_limits.
+pub const MAX_NODES: usize = 50;
+
 #[derive(Debug, Clone, Copy, Default)]
 #[repr(packed)]
 pub struct NodeData {
@@ -37,64 +40,109 @@ pub struct NodeData {
 }
 
 impl Node {
-    // Array is sorted in pre-order
+    // Equal subtrees are written once, in the order of Dag::new. Parents come before their children,
+    // without shared subtrees the array is sorted in pre-order.
     pub fn serialize<V: KeywordType, F: KeywordType>(&self) -> Vec<NodeData> {
         let mut result = Vec::new();
-        self.serialize_next::<V, F>(&mut result);
+        self.serialize_into::<V, F>(&mut result);
         result
     }
-    fn serialize_next<V: KeywordType, F: KeywordType>(&self, result: &mut Vec<NodeData>) {
+    // Appends the nodes after the ones already in the result, indices count from the start of the result.
+    pub fn serialize_into<V: KeywordType, F: KeywordType>(&self, result: &mut Vec<NodeData>) {
+        let dag = Dag::new(self);
+        let offset = result.len();
+        let shifted = |index: usize| match index {
+            0 => 0,
+            index => (index + offset) as u16,
+        };
+        let mut index = 0;
+        while index < dag.nodes.len() {
+            let node = dag.nodes[index];
+            // Loops aren't shared, their subtree is written in pre-order right after them.
+            if node.binding().is_some() {
+                node.serialize_next::<V, F>(result, &mut Vec::new());
+                index = result.len() - offset;
+                continue;
+            }
+            let (left, right) = dag.children[index];
+            result.push(NodeData{
+                left_index: shifted(left),
+                right_index: shifted(right),
+                keyword: node.syntax.serialize_keyword::<V, F>() as u16,
+                syntax: node.syntax.serialize_syntax() as u16,
+                value: node.syntax.serialize_value(),
+            });
+            index += 1;
+        }
+    }
+    // Children the shader reads, numbers have none and delimiters only have the left one.
+    pub fn serialized_children(&self) -> (Option<&Node>, Option<&Node>) {
+        match self.syntax {
+            Syntax::Imaginary(..) | Syntax::Real(..) => (None, None),
+            Syntax::Parenthesis | Syntax::Absolute => (self.left.as_deref(), None),
+            Syntax::Bracket | Syntax::Brace | Syntax::Floor | Syntax::Ceil | Syntax::Norm => (self.left.as_deref(), None),
+            _ => (self.left.as_deref(), self.right.as_deref()),
+        }
+    }
+    // Loops are serialized like functions, the body is the last node of their subtree.
+    // The keyword of a loop is the index after its subtree, so the shader can jump back to evaluate the body again.
+    // Variables bound by a loop are serialized as Bound with the index of the loop as their keyword.
+    fn serialize_next<V: KeywordType, F: KeywordType>(&self, result: &mut Vec<NodeData>, scope: &mut Vec<(String, usize)>) {
         let index = result.len();
+        let bound = match &self.syntax {
+            Syntax::Variable(name) => scope.iter().rev().find(|(other, _)| other == name).map(|&(_, index)| index),
+            _ => None,
+        };
         result.push(NodeData{
             right_index: 0,
             left_index: 0,
-            keyword: self.syntax.serialize_keyword::<V, F>() as u16,
-            syntax: self.syntax.serialize_syntax() as u16,
+            keyword: match bound {
+                Some(index) => index as u16,
+                None => self.syntax.serialize_keyword::<V, F>() as u16,
+            },
+            syntax: match bound {
+                Some(_) => Syntax::BOUND,
+                None => self.syntax.serialize_syntax() as u16,
+            },
             value: self.syntax.serialize_value(),
         });
-
-        let mut do_left = true;
-        let mut do_right = true;
-        match self.syntax {
-            Syntax::Imaginary(_) |Syntax::Real(_) => {
-                do_left = false;
-                do_right = false;
-            }
-
-            Syntax::Parenthesis | Syntax::Absolute | Syntax::Function(_) => {
-                do_right = false;
-            }
-
-            _ => (),
+        if let Some(name) = self.binding() {
+            scope.push((name.to_string(), index));
         }
-        
-        
+
+        let (left_node, right_node) = self.serialized_children();
         let mut left = 0;
-        if do_left {
-            if let Some(node) = &self.left {
-                left = result.len();
-                node.serialize_next::<V, F>(result);
-            }
+        if let Some(node) = left_node {
+            left = result.len();
+            node.serialize_next::<V, F>(result, scope);
         }
         let mut right = 0;
-        if do_right {
-            if let Some(node) = &self.right {
-                right = result.len();
-                node.serialize_next::<V, F>(result);
-            }
+        if let Some(node) = right_node {
+            right = result.len();
+            node.serialize_next::<V, F>(result, scope);
         }
 
+        if self.binding().is_some() {
+            scope.pop();
+        }
+        let end = result.len();
         if let Some(parent) = result.get_mut(index) {
             parent.left_index = left as u16;
             parent.right_index = right as u16;
+            if self.binding().is_some() {
+                parent.keyword = end as u16;
+            }
         }
     }
 }
 impl Syntax {
+    // Variable bound by a loop.
+    pub const BOUND: u16 = 34;
+
     pub fn serialize_syntax(&self) -> u32 {
         match self {
-            Self::Real(_)       => 0,
-            Self::Imaginary(_)  => 1,
+            Self::Real(..)      => 0,
+            Self::Imaginary(..) => 1,
             Self::Variable(_)   => 2,
             Self::Function(_)   => 3,
 
@@ -105,14 +153,40 @@ impl Syntax {
             Self::Division          => 8,
             Self::Exponent          => 9,
             Self::Absolute          => 10,
+            Self::Argument          => 11,
+            Self::Bracket           => 12,
+            Self::Brace             => 13,
+            Self::Floor             => 14,
+            Self::Ceil              => 15,
+            Self::Norm              => 16,
+
+            Self::Less              => 17,
+            Self::Greater           => 18,
+            Self::LessEqual         => 19,
+            Self::GreaterEqual      => 20,
+            Self::Equal             => 21,
+            Self::NotEqual          => 22,
+            Self::And               => 23,
+            Self::Or                => 24,
+            Self::Not               => 25,
+            Self::Condition         => 26,
+
+            Self::Factorial         => 27,
+            Self::Conjugate         => 28,
+            Self::Degree            => 29,
+            Self::Percent           => 30,
+
+            Self::Sum(_)            => 31,
+            Self::Product(_)        => 32,
+            Self::Iterate(_)        => 33,
 
             _ => 0,
         }
     }
     pub fn serialize_value(&self) -> (f32, f32) {
         match self {
-            Self::Real(value) => (*value as f32, 0.0),
-            Self::Imaginary(value) => (0.0, *value as f32),
+            Self::Real(value, _) => (*value as f32, 0.0),
+            Self::Imaginary(value, _) => (0.0, *value as f32),
             _ => (0.0, 0.0),
         }
     }
@@ -132,4 +206,77 @@ impl Expression {
             None => vec![],
         }
     }
-}
\ No newline at end of file
+    // Nodes past MAX_NODES would be written outside of the shader's arrays, they aren't uploaded.
+    pub fn check_node_count(&self, count: usize) -> Result<(), Diagnostic> {
+        if count <= MAX_NODES {
+            return Ok(());
+        }
+        let message = format!("Expression needs {} nodes, but the shader only has room for {}", count, MAX_NODES);
+        Err(Diagnostic::error(Span::new(0, self.input.len()), &message).with_hint("shorten the expression"))
+    }
+}
+
+// Defines the sizes of the shader's arrays after its #version line, so they can't disagree with the buffers,
+// and the iterations of Newton's method, so the shader stops where Newton::iterate does.
+pub fn with_limits(source: &str) -> String {
+    let (version, rest) = source.split_at(source.find('\n').map_or(source.len(), |end| end + 1));
+    format!(
+        "{}#define MAX_NODES {}\n#define MAX_ROOTS {}\n#define MAX_ITERATIONS {}\n{}",
+        version, MAX_NODES, MAX_ROOTS, MAX_ITERATIONS, rest,
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{ with_limits, Empty, MAX_ITERATIONS, MAX_NODES };
+    use super::super::parser::Syntax;
+    use super::super::keyword::Keyword;
+    use super::super::parser::Expression;
+
+    #[test]
+    fn node_count_limit() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        let short = Expression::parse("z^2 + 1", &keywords);
+        assert!(short.check_node_count(short.serialize::<Empty, Empty>().len()).is_ok());
+
+        // Every term is different, so nothing is shared.
+        let terms: Vec<String> = (1..=MAX_NODES).map(|power| format!("z^{}", power)).collect();
+        let long = Expression::parse(&terms.join(" + "), &keywords);
+        let error = long.check_node_count(long.serialize::<Empty, Empty>().len()).unwrap_err();
+        assert!(error.is_error() && error.span.end == long.input.len());
+    }
+
+    #[test]
+    fn shader_limits() {
+        let source = with_limits(include_str!("../shader/frag.glsl"));
+        assert!(source.starts_with(&format!("#version 300 es\n#define MAX_NODES {}\n", MAX_NODES)));
+        assert!(source.contains("const int max_nodes = MAX_NODES;"));
+        assert!(source.contains("const int max_roots = MAX_ROOTS;"));
+        assert!(source.contains(&format!("#define MAX_ITERATIONS {}\n", MAX_ITERATIONS)));
+        assert!(source.contains("const int max_iterations = MAX_ITERATIONS;"));
+    }
+
+    // Names bound by a loop are Bound with the index of the innermost loop binding them,
+    // the keyword of the loop is the index after its subtree.
+    #[test]
+    fn bound_names() {
+        let keywords = Keyword::vars(&vec!["z"]);
+        let cases: [(&str, &[(u16, u16)]); 3] = [
+            ("sum(k, 1, 3, z^k)", &[(31, 8), (0, 0), (11, 0), (0, 0), (11, 0), (9, 0), (2, 0), (Syntax::BOUND, 0)]),
+            ("sum(k, 1, 2, sum(k, 1, 3, k))", &[
+                (31, 11), (0, 0), (11, 0), (0, 0), (11, 0),
+                (31, 11), (0, 0), (11, 0), (0, 0), (11, 0), (Syntax::BOUND, 5),
+            ]),
+            ("iter(2, 1, z + z)", &[(33, 8), (0, 0), (11, 0), (0, 0), (11, 0), (5, 0), (Syntax::BOUND, 0), (Syntax::BOUND, 0)]),
+        ];
+        for &(input, expected) in &cases {
+            let expression = Expression::parse(input, &keywords);
+            assert!(expression.is_valid(), "'{}': {}", input, expression.pretty_error());
+            let nodes: Vec<(u16, u16)> = expression.serialize::<Empty, Empty>().iter()
+                .map(|node| (node.syntax, node.keyword))
+                .collect();
+            assert_eq!(nodes, expected.to_vec(), "'{}'", input);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use super::complex::Complex;
+use super::interpreter::Intepreter;
+use super::parser::{ Literal, Node, Syntax };
+
+
+
+// Integer exponents above this aren't multiplied out, the coefficient would overflow anyway.
+const EXPONENT_LIMIT: f64 = 64.0;
+
+// Coefficient times powers of factors, like 3 z^2 x^-1.
+// Only integer exponents are collected, a^n a^m = a^(n + m) doesn't hold on branch cuts otherwise.
+#[derive(Debug, Clone)]
+struct Term {
+    coefficient: Complex,
+    // Number the coefficient was parsed from, so "π z" doesn't turn into "3.141592653589793 z".
+    number: Option<Node>,
+    // Source of the base, the base and its exponent, sorted by source.
+    factors: Vec<(String, Node, i32)>,
+}
+impl Term {
+    fn constant(coefficient: Complex, number: Option<Node>) -> Self {
+        Self {
+            coefficient,
+            number,
+            factors: Vec::new(),
+        }
+    }
+    fn factor(base: Node) -> Self {
+        Self {
+            coefficient: Complex::real(1.0),
+            number: None,
+            factors: vec![(base.to_source(), base, 1)],
+        }
+    }
+    fn multiply(mut self, other: Self) -> Self {
+        let one = Complex::real(1.0);
+        self.number = match (self.coefficient == one, other.coefficient == one) {
+            (true, _) => other.number,
+            (_, true) => self.number,
+            _ => None,
+        };
+        self.coefficient = self.coefficient * other.coefficient;
+        for (key, base, exponent) in other.factors {
+            match self.factors.iter_mut().find(|(other, _, _)| *other == key) {
+                Some(factor) => factor.2 += exponent,
+                None => self.factors.push((key, base, exponent)),
+            }
+        }
+        self.factors.retain(|&(_, _, exponent)| exponent != 0);
+        self.factors.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        self
+    }
+    // None for a zero coefficient, the base is kept as a factor then.
+    fn power(self, exponent: i32) -> Option<Self> {
+        if exponent < 0 && self.coefficient.is_zero() {
+            return None;
+        }
+        // Repeated multiplication, pow goes through log and exp and 1 / 2i would get a tiny real part.
+        let mut coefficient = Complex::real(1.0);
+        for _ in 0..exponent.abs() {
+            coefficient = coefficient * self.coefficient;
+        }
+        if exponent < 0 {
+            coefficient = Complex::real(1.0) / coefficient;
+        }
+        Some(Self {
+            number: if exponent == 1 { self.number } else { None },
+            coefficient,
+            factors: self.factors.into_iter()
+                .map(|(key, base, power)| (key, base, power * exponent))
+                .filter(|&(_, _, power)| power != 0)
+                .collect(),
+        })
+    }
+    // Sum of the exponents, terms of higher degree are written first.
+    fn degree(&self) -> i32 {
+        self.factors.iter().map(|&(_, _, exponent)| exponent).sum()
+    }
+    fn key(&self) -> String {
+        self.factors.iter()
+            .map(|(key, _, exponent)| format!("{}^{}", key, exponent))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    // Node of the term without its sign, negative coefficients are subtracted by the sum.
+    fn to_node(&self, magnitude: Complex) -> Node {
+        let power = |base: &Node, exponent: i32| match exponent {
+            1 => base.clone(),
+            _ => Node::from(base.clone(), number(exponent as f64), Syntax::Exponent),
+        };
+        let product = |nodes: Vec<Node>| nodes.into_iter().fold(None, |product, node| match product {
+            Some(product) => Some(Node::from(product, node, Syntax::Multiplication)),
+            None => Some(node),
+        });
+        let numerator: Vec<Node> = self.factors.iter()
+            .filter(|&&(_, _, exponent)| exponent > 0)
+            .map(|(_, base, exponent)| power(base, *exponent))
+            .collect();
+        let denominator: Vec<Node> = self.factors.iter()
+            .filter(|&&(_, _, exponent)| exponent < 0)
+            .map(|(_, base, exponent)| power(base, -exponent))
+            .collect();
+
+        let coefficient = match &self.number {
+            Some(node) if node.value() == Some(magnitude) => node.clone(),
+            _ => Node::from_complex(magnitude, Default::default()),
+        };
+        let mut nodes = numerator;
+        if magnitude != Complex::real(1.0) || nodes.is_empty() {
+            nodes.insert(0, coefficient);
+        }
+        let numerator = product(nodes).unwrap_or_else(|| number(1.0));
+        match product(denominator) {
+            Some(denominator) => Node::from(numerator, denominator, Syntax::Division),
+            None => numerator,
+        }
+    }
+}
+
+fn number(value: f64) -> Node {
+    Node::from_syntax(Syntax::Real(value, Literal::Decimal))
+}
+// Coefficients like -2 and -3i are written as subtractions.
+fn negative(value: Complex) -> bool {
+    (value.im == 0.0 && value.re < 0.0) || (value.re == 0.0 && value.im < 0.0)
+}
+
+
+
+impl Node {
+    // Rewrites sums and products into a canonical sum of products: chains are flattened, like terms and
+    // integer powers of the same base are collected, identities are dropped and constants are folded.
+    // Terms are sorted by degree, factors by their source, so equal expressions simplify to the same tree.
+    pub fn simplify(&self) -> Node {
+        let mut new = self.clone();
+        for child in new.left.iter_mut().chain(new.right.iter_mut()) {
+            **child = child.simplify();
+        }
+        if let Some(folded) = new.fold() {
+            return folded;
+        }
+
+        match &new.syntax {
+            Syntax::Addition | Syntax::Subtraction | Syntax::Multiplication | Syntax::Division | Syntax::Exponent => {
+                Self::from_terms(new.terms()).with_span(self.span)
+            }
+            // a'' = a
+            Syntax::Conjugate => match new.left.as_ref().map(|left| (&left.syntax, &left.left)) {
+                Some((Syntax::Conjugate, Some(inner))) => (**inner).clone(),
+                _ => new,
+            },
+            _ => new,
+        }
+    }
+
+    // Value of a real or an imaginary number.
+    fn value(&self) -> Option<Complex> {
+        match &self.syntax {
+            Syntax::Real(value, _) => Some(Complex::real(*value)),
+            Syntax::Imaginary(value, _) => Some(Complex::imaginary(*value)),
+            _ => None,
+        }
+    }
+
+    // Terms of the sum, like terms are collected, terms that are zero are dropped.
+    fn terms(&self) -> Vec<Term> {
+        let (left, right) = (self.left.as_deref(), self.right.as_deref());
+        let mut terms = match (&self.syntax, left, right) {
+            (Syntax::Parenthesis, Some(inner), _) => return inner.terms(),
+            (Syntax::Addition, Some(left), Some(right)) => {
+                let mut terms = left.terms();
+                terms.extend(right.terms());
+                terms
+            }
+            (Syntax::Subtraction, Some(left), Some(right)) => {
+                let mut terms = left.terms();
+                terms.extend(right.terms().into_iter().map(|mut term| {
+                    term.coefficient = -term.coefficient;
+                    term
+                }));
+                terms
+            }
+            _ => vec![self.term()],
+        };
+
+        let mut collected: Vec<Term> = Vec::new();
+        for term in terms.drain(..) {
+            let key = term.key();
+            match collected.iter_mut().find(|other| other.key() == key) {
+                Some(other) => {
+                    other.coefficient = other.coefficient + term.coefficient;
+                    other.number = None;
+                }
+                None => collected.push(term),
+            }
+        }
+        collected.retain(|term| !term.coefficient.is_zero());
+        collected.sort_by(|lhs, rhs| rhs.degree().cmp(&lhs.degree()).then_with(|| lhs.key().cmp(&rhs.key())));
+        collected
+    }
+    // Node as a single term, sums with more than one term are a factor of their own.
+    fn term(&self) -> Term {
+        let (left, right) = (self.left.as_deref(), self.right.as_deref());
+        match (&self.syntax, left, right) {
+            (Syntax::Real(value, _), _, _) => Term::constant(Complex::real(*value), Some(self.clone())),
+            (Syntax::Imaginary(value, _), _, _) => Term::constant(Complex::imaginary(*value), Some(self.clone())),
+            (Syntax::Multiplication, Some(left), Some(right)) => left.term().multiply(right.term()),
+            (Syntax::Division, Some(left), Some(right)) => match right.term().power(-1) {
+                Some(inverse) => left.term().multiply(inverse),
+                None => Term::factor(self.clone()),
+            },
+            (Syntax::Exponent, Some(base), Some(exponent)) => {
+                let integer = match exponent.value() {
+                    Some(value) if value.im == 0.0 && value.re.fract() == 0.0 && value.re.abs() <= EXPONENT_LIMIT => {
+                        Some(value.re as i32)
+                    }
+                    _ => None,
+                };
+                match integer.and_then(|integer| base.term().power(integer)) {
+                    Some(term) => term,
+                    None => Term::factor(self.clone()),
+                }
+            }
+            (Syntax::Addition, _, _) | (Syntax::Subtraction, _, _) | (Syntax::Parenthesis, _, _) => {
+                let mut terms = self.terms();
+                match terms.len() {
+                    0 => Term::constant(Complex::zero(), None),
+                    1 => terms.remove(0),
+                    _ => Term::factor(Self::from_terms(terms)),
+                }
+            }
+            _ => Term::factor(self.clone()),
+        }
+    }
+
+    // Minimal tree of the terms, negative terms are subtracted and complex constants are split into their parts.
+    fn from_terms(terms: Vec<Term>) -> Node {
+        let mut parts: Vec<(bool, Node)> = Vec::new();
+        for term in terms {
+            let value = term.coefficient;
+            match term.factors.is_empty() && value.re != 0.0 && value.im != 0.0 {
+                true => {
+                    parts.push((value.re < 0.0, number(value.re.abs())));
+                    let imaginary = Node::from_syntax(Syntax::Imaginary(value.im.abs(), Literal::Decimal));
+                    parts.push((value.im < 0.0, imaginary));
+                }
+                false => {
+                    let sign = negative(value);
+                    let magnitude = if sign { -value } else { value };
+                    parts.push((sign, term.to_node(magnitude)));
+                }
+            }
+        }
+
+        let mut parts = parts.into_iter();
+        let mut sum = match parts.next() {
+            Some((false, node)) => node,
+            Some((true, node)) => Node::from(number(0.0), node, Syntax::Subtraction),
+            None => return number(0.0),
+        };
+        for (sign, node) in parts {
+            let syntax = if sign { Syntax::Subtraction } else { Syntax::Addition };
+            sum = Node::from(sum, node, syntax);
+        }
+        sum
+    }
+}
+
+
+
+// Simplifying assumes every part of the tree is finite, "0 * (1 / 0)" is 0. Huge parts come from
+// dividing by the rounding error of something that is zero, like "1 / (z^1 - z)", or lose every digit in sin and cos.
+// Loop bodies aren't checked, they depend on the bound variable.
+pub fn bounded(node: &Node, interpreter: &Intepreter) -> bool {
+    let value = interpreter.evaluate_node(node);
+    if !value.is_finite() || value.abs() > 1e6 {
+        return false;
+    }
+    let children = match node.scoped() {
+        Some((_, bounds, _)) => bounds,
+        None => [&node.left, &node.right].iter().filter_map(|child| child.as_deref()).collect(),
+    };
+    children.iter().all(|child| bounded(child, interpreter))
+}
+pub fn close_within(lhs: Complex, rhs: Complex, tolerance: f64) -> bool {
+    (lhs - rhs).abs() <= tolerance
+}
+
+// Evaluates the tree before and after optimizing it at a few points, every variable gets a different value at each.
+// Points where a part of the original isn't bounded are skipped, the rewrites don't have to hold there.
+// Returns the first point where the values differ.
+pub fn check_optimized(original: &Node, optimized: &Node, points: usize) -> Result<(), String> {
+    let mut names = Vec::new();
+    original.variables(&mut names);
+    for point in 0..points {
+        let mut interpreter = Intepreter::new();
+        for (index, name) in names.iter().enumerate() {
+            // Points on a spiral around 0, so they don't line up for any two variables.
+            let angle = 2.4 * (point * names.len() + index) as f64;
+            let radius = 0.5 + 0.75 * point as f64;
+            interpreter.set_variable(name, Complex::new(radius * angle.cos(), radius * angle.sin()));
+        }
+        if !bounded(original, &interpreter) {
+            continue;
+        }
+        let (expected, found) = (interpreter.evaluate_node(original), interpreter.evaluate_node(optimized));
+        // Functions round differently after a rewrite, like exp(a) exp(b) and exp(a + b).
+        if !close_within(expected, found, 1e-6 * expected.abs().max(found.abs()).max(1.0)) {
+            let values: Vec<String> = names.iter()
+                .map(|name| format!("{} = {}", name, interpreter.evaluate_node(&Node::from_syntax(Syntax::Variable(name.to_string())))))
+                .collect();
+            return Err(format!(
+                "'{}' is {}, but optimized to '{}' it is {}, at {}",
+                original.to_source(), expected, optimized.to_source(), found, values.join(", "),
+            ));
+        }
+    }
+    Ok(())
+}
+impl Node {
+    // Names of the variables in the tree, each once.
+    fn variables(&self, names: &mut Vec<String>) {
+        if let Syntax::Variable(name) = &self.syntax {
+            if !names.contains(name) {
+                names.push(name.to_string());
+            }
+        }
+        for child in self.left.iter().chain(self.right.iter()) {
+            child.variables(names);
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::check_optimized;
+    use super::super::keyword::Keyword;
+    use super::super::parser::Expression;
+    use super::super::testing::{ bounded, close, random_arithmetic, Xorshift };
+
+    // Evaluates random trees before and after Expression::optimize at random points.
+    // The trees only use operations without branch cuts or jumps, where the rewrites hold exactly,
+    // "log(-(a / b))" and "log(a / -b)" differ in the sign of a zero imaginary part.
+    #[test]
+    fn optimize_keeps_values() {
+        let mut random = Xorshift(0x2545f491);
+        for _ in 0..1000 {
+            let tree = random_arithmetic(&mut random, 4);
+            let mut expression = Expression {
+                input: tree.to_source(),
+                tree: Some(tree),
+                definitions: Vec::new(),
+                diagnostics: Vec::new(),
+            };
+            let original = expression.clone();
+            expression.optimize();
+
+            let interpreter = random.interpreter();
+            if !original.tree.iter().any(|tree| bounded(tree, &interpreter)) {
+                continue;
+            }
+            let (expected, found) = (interpreter.evaluate(&original), interpreter.evaluate(&expression));
+            assert!(
+                close(expected, found),
+                "'{}' is {}, but optimized to '{}' it is {}", original.input, expected, expression.to_source(), found,
+            );
+        }
+    }
+
+    #[test]
+    fn optimized_check() {
+        let keywords = Keyword::vars(&vec!["z", "x"]);
+        let tree = |input: &str| Expression::parse(input, &keywords).tree.unwrap();
+        let mut random = Xorshift(0x9e3779b9);
+        for _ in 0..200 {
+            let original = random_arithmetic(&mut random, 4);
+            assert_eq!(check_optimized(&original, &original.simplify(), 8), Ok(()));
+        }
+        assert_eq!(check_optimized(&tree("z + z + x"), &tree("2z + x"), 8), Ok(()));
+        assert!(check_optimized(&tree("z + z + x"), &tree("2z - x"), 8).is_err());
+        // Only points where the original is bounded are compared, 0 * (1 / 0) isn't.
+        assert_eq!(check_optimized(&tree("0 / (z - z)"), &tree("0"), 8), Ok(()));
+    }
+}
@@ -0,0 +1,163 @@
+use super::complex::Complex;
+use super::interpreter::{ Intepreter, FUNCTIONS };
+use super::parser::{ Literal, Node, Syntax };
+pub use super::simplify::{ bounded, close_within };
+
+
+
+// Small deterministic generator, so failures can be reproduced from the seed.
+pub struct Xorshift(pub u64);
+impl Xorshift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+    // Point in the square from -4 - 4i to 4 + 4i.
+    pub fn point(&mut self) -> Complex {
+        let part = |random: &mut Self| (random.below(2001) as f64 - 1000.0) / 250.0;
+        let re = part(self);
+        Complex::new(re, part(self))
+    }
+    // Interpreter with random values for z and x.
+    pub fn interpreter(&mut self) -> Intepreter {
+        let mut interpreter = Intepreter::new();
+        for name in ["z", "x"].iter() {
+            interpreter.set_variable(name, self.point());
+        }
+        interpreter
+    }
+}
+
+
+
+// Any syntax the printer knows, with conditions, loops and every function.
+pub fn random_tree(random: &mut Xorshift, depth: usize) -> Node {
+    let next = |random: &mut Xorshift| random_tree(random, depth.saturating_sub(1));
+    let leaf = depth == 0 || random.below(4) == 0;
+
+    if leaf {
+        return match random.below(5) {
+            0 => Node::from_syntax(Syntax::Real(random.below(100) as f64, Literal::Decimal)),
+            1 => Node::from_syntax(Syntax::Real(random.below(64) as f64 / 8.0, Literal::Decimal)),
+            2 => Node::from_syntax(Syntax::Imaginary(random.below(10) as f64, Literal::Decimal)),
+            3 => Node::from_syntax(Syntax::Real(std::f64::consts::PI, Literal::Symbol('π'))),
+            _ => Node::from_syntax(Syntax::Variable(["z", "x"][random.below(2)].to_string())),
+        };
+    }
+
+    const BINARY: [Syntax; 13] = [
+        Syntax::Addition, Syntax::Subtraction, Syntax::Multiplication, Syntax::Division, Syntax::Exponent,
+        Syntax::Less, Syntax::Greater, Syntax::LessEqual, Syntax::GreaterEqual, Syntax::Equal, Syntax::NotEqual,
+        Syntax::And, Syntax::Or,
+    ];
+    const UNARY: [Syntax; 11] = [
+        Syntax::Not, Syntax::Factorial, Syntax::Conjugate, Syntax::Degree, Syntax::Percent,
+        Syntax::Absolute, Syntax::Bracket, Syntax::Brace, Syntax::Floor, Syntax::Ceil, Syntax::Norm,
+    ];
+    match random.below(6) {
+        0 | 1 => {
+            let syntax = BINARY[random.below(BINARY.len())].clone();
+            Node::from(next(random), next(random), syntax)
+        }
+        2 => {
+            let syntax = UNARY[random.below(UNARY.len())].clone();
+            Node::new(Some(next(random)), None, syntax)
+        }
+        3 => {
+            let syntax = [Syntax::Addition, Syntax::Subtraction][random.below(2)].clone();
+            Node::from(Node::from_syntax(Syntax::Real(0.0, Literal::Decimal)), next(random), syntax)
+        }
+        4 => {
+            let (name, arity) = FUNCTIONS[random.below(FUNCTIONS.len())];
+            let arguments = (0..arity).map(|_| next(random)).collect();
+            Node::chain(arguments, Syntax::Function(name.to_string()))
+        }
+        _ => {
+            let syntax = match random.below(4) {
+                0 => Syntax::Sum("k".to_string()),
+                1 => Syntax::Product("k".to_string()),
+                2 => Syntax::Iterate("w".to_string()),
+                _ => Syntax::Condition,
+            };
+            let arguments = (0..3).map(|_| next(random)).collect();
+            Node::chain(arguments, syntax)
+        }
+    }
+}
+
+// Sums, products, integer powers, smooth functions and loops with constant bounds.
+pub fn random_arithmetic(random: &mut Xorshift, depth: usize) -> Node {
+    let next = |random: &mut Xorshift| random_arithmetic(random, depth.saturating_sub(1));
+    let number = |value: usize| Node::from_syntax(Syntax::Real(value as f64, Literal::Decimal));
+    let leaf = depth == 0 || random.below(4) == 0;
+
+    if leaf {
+        return match random.below(6) {
+            0 => number(random.below(10)),
+            1 => Node::from_syntax(Syntax::Real(random.below(64) as f64 / 8.0, Literal::Decimal)),
+            2 => Node::from_syntax(Syntax::Imaginary(random.below(10) as f64, Literal::Decimal)),
+            3 => Node::from_syntax(Syntax::Real(std::f64::consts::PI, Literal::Symbol('π'))),
+            _ => Node::from_syntax(Syntax::Variable(["z", "x"][random.below(2)].to_string())),
+        };
+    }
+
+    const BINARY: [Syntax; 4] = [Syntax::Addition, Syntax::Subtraction, Syntax::Multiplication, Syntax::Division];
+    match random.below(8) {
+        0..=3 => {
+            let syntax = BINARY[random.below(BINARY.len())].clone();
+            Node::from(next(random), next(random), syntax)
+        }
+        4 => {
+            let exponent = match random.below(3) {
+                0 => Node::from(number(0), number(random.below(4)), Syntax::Subtraction),
+                _ => number(random.below(5)),
+            };
+            Node::from(next(random), exponent, Syntax::Exponent)
+        }
+        5 => Node::from(number(0), next(random), Syntax::Subtraction),
+        6 => {
+            let name = ["exp", "sin", "cos"][random.below(3)];
+            Node::chain(vec![next(random)], Syntax::Function(name.to_string()))
+        }
+        _ => {
+            let syntax = [Syntax::Sum("k".to_string()), Syntax::Product("k".to_string())][random.below(2)].clone();
+            let arguments = vec![number(1), number(1 + random.below(3)), next(random)];
+            Node::chain(arguments, syntax)
+        }
+    }
+}
+// Random trees that reuse a few subtrees in several places.
+pub fn random_repeated(random: &mut Xorshift) -> Node {
+    let parts: Vec<Node> = (0..3).map(|_| random_arithmetic(random, 2)).collect();
+    let mut tree = parts[random.below(parts.len())].clone();
+    for _ in 0..2 + random.below(4) {
+        let part = parts[random.below(parts.len())].clone();
+        let syntax = match random.below(5) {
+            0 => Syntax::Addition,
+            1 => Syntax::Subtraction,
+            2 => Syntax::Multiplication,
+            3 => Syntax::Division,
+            _ => {
+                tree = Node::new(Some(tree), None, Syntax::Function("sin".to_string()));
+                Syntax::Addition
+            }
+        };
+        tree = match random.below(2) {
+            0 => Node::from(tree, part, syntax),
+            _ => Node::from(part, tree, syntax),
+        };
+    }
+    tree
+}
+
+
+
+// Equal up to rounding relative to the larger modulus.
+pub fn close(lhs: Complex, rhs: Complex) -> bool {
+    close_within(lhs, rhs, 1e-9 * lhs.abs().max(rhs.abs()).max(1.0))
+}
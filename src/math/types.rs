@@ -0,0 +1,107 @@
+use std::fmt::Display;
+use super::diagnostic::Diagnostic;
+use super::parser::{ Node, Syntax };
+
+
+
+// Result of a node, conditions are only allowed where a condition is expected.
+// Both are stored as complex numbers when evaluated, true is 1 and false is 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Complex,
+    Boolean,
+}
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Complex => write!(f, "number"),
+            Self::Boolean => write!(f, "condition"),
+        }
+    }
+}
+
+
+
+impl Node {
+    // Reports every node with the wrong type, returns None if the type is unknown,
+    // like for Syntax::Error, so errors don't cascade.
+    pub fn check(&self, diagnostics: &mut Vec<Diagnostic>) -> Option<Type> {
+        let arguments = self.arguments();
+        match &self.syntax {
+            Syntax::Ignore | Syntax::Error => None,
+            Syntax::Real(..) | Syntax::Imaginary(..) | Syntax::Variable(_) => Some(Type::Complex),
+
+            Syntax::Function(_) | Syntax::Sum(_) | Syntax::Product(_) | Syntax::Iterate(_) => {
+                for argument in arguments {
+                    argument.expect(Type::Complex, diagnostics);
+                }
+                Some(Type::Complex)
+            }
+            Syntax::Argument => self.left.as_ref().and_then(|left| left.check(diagnostics)),
+
+            // Grouping keeps the type, so "(|z| < 1) and b" works.
+            Syntax::Parenthesis | Syntax::Bracket | Syntax::Brace => {
+                self.left.as_ref().and_then(|left| left.check(diagnostics))
+            }
+            Syntax::Absolute | Syntax::Floor | Syntax::Ceil | Syntax::Norm => {
+                self.expect_children(Type::Complex, diagnostics);
+                Some(Type::Complex)
+            }
+            Syntax::Factorial | Syntax::Conjugate | Syntax::Degree | Syntax::Percent => {
+                self.expect_children(Type::Complex, diagnostics);
+                Some(Type::Complex)
+            }
+            Syntax::Addition | Syntax::Subtraction | Syntax::Multiplication | Syntax::Division | Syntax::Exponent => {
+                self.expect_children(Type::Complex, diagnostics);
+                Some(Type::Complex)
+            }
+
+            Syntax::Less | Syntax::Greater | Syntax::LessEqual | Syntax::GreaterEqual | Syntax::Equal | Syntax::NotEqual => {
+                self.expect_children(Type::Complex, diagnostics);
+                Some(Type::Boolean)
+            }
+            Syntax::And | Syntax::Or | Syntax::Not => {
+                self.expect_children(Type::Boolean, diagnostics);
+                Some(Type::Boolean)
+            }
+
+            // Both branches need the same type, the result has that type.
+            Syntax::Condition => {
+                if let Some(condition) = arguments.first() {
+                    condition.expect(Type::Boolean, diagnostics);
+                }
+                let consequent = arguments.get(1).and_then(|node| node.check(diagnostics));
+                let alternative = arguments.get(2);
+                match (consequent, alternative) {
+                    (Some(expected), Some(alternative)) => {
+                        alternative.expect(expected, diagnostics);
+                        Some(expected)
+                    }
+                    (None, Some(alternative)) => alternative.check(diagnostics),
+                    (result, None) => result,
+                }
+            }
+        }
+    }
+    pub fn expect(&self, expected: Type, diagnostics: &mut Vec<Diagnostic>) {
+        match self.check(diagnostics) {
+            Some(found) if found != expected => {
+                let message = format!("Expected a {}, found a {}", expected, found);
+                let hint = match expected {
+                    Type::Complex => "use the condition to choose between numbers, like if(|z| < 1, z, 1/z)",
+                    Type::Boolean => "compare the number to get a condition, like |z| < 1",
+                };
+                diagnostics.push(Diagnostic::error(self.span, &message).with_hint(hint));
+            }
+            _ => (),
+        }
+    }
+    fn expect_children(&self, expected: Type, diagnostics: &mut Vec<Diagnostic>) {
+        if let Some(left) = &self.left {
+            left.expect(expected, diagnostics);
+        }
+        if let Some(right) = &self.right {
+            right.expect(expected, diagnostics);
+        }
+    }
+}
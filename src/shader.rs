@@ -149,6 +149,12 @@ impl Shader {
         self.context.uniform1i(location.as_ref(), value);
     }
 
+    // Sets an array of vec2 from its first element, the values are x and y after each other.
+    pub fn uniform2fv(&mut self, name: &str, values: &[f32]) {
+        let location = self.uniform_location(name);
+        self.context.uniform2fv_with_f32_array(location.as_ref(), values);
+    }
+
     pub fn bind_uniform_block(&mut self, name: &str,  buffer: &mut UniformBufferObject) {
         let location = self.context.get_uniform_block_index(&self.program, name);
         if location != WebGl2::INVALID_INDEX {
@@ -311,7 +317,6 @@ impl UniformBufferObject {
         self.context.bind_buffer(WebGl2::UNIFORM_BUFFER, None);
     }
     // TODO: give it an allocate function that uses buffer_data_i32
-    // TODO: use sub_data or map to set data.
     // TODO: use get_buffer_sub_daat to verify. 
     pub fn set_data<T: Clone>(&self, data: &Vec<T>) {
         self.bind();
@@ -329,6 +334,19 @@ impl UniformBufferObject {
             );
         };
     }
+    // Overwrites the element at the index, the buffer has to be allocated with set_data first.
+    pub fn set_element<T: Clone>(&self, index: usize, element: &T) {
+        self.bind();
+        unsafe {
+            let size = std::mem::size_of::<T>();
+            let raw = std::slice::from_raw_parts(element as *const T as *const u8, size);
+            self.context.buffer_sub_data_with_i32_and_u8_array(
+                WebGl2::UNIFORM_BUFFER,
+                (index * size) as i32,
+                raw,
+            );
+        };
+    }
 }
 impl Drop for UniformBufferObject {
     fn drop(&mut self) {
@@ -120,7 +120,9 @@ float log_hypot(float x, float y) {
     if (a < 3000.0 && b < 3000.0) {
         return log(a * a + b * b) * 0.5;
     }
-    return log(cos(a / atan2(b, a)));
+    float m = max(a, b);
+    float n = min(a, b) / m;
+    return log(m) + 0.5 * log(1.0 + n * n);
 }
 
 
@@ -211,6 +213,53 @@ vec2 ctan(vec2 value) {
     float det = cos(re) + cosh(im);
     return vec2(sin(re) / det, sinh(im) / det);
 }
+vec2 casin(vec2 value) {
+    // -i * log(iz + sqrt(1 - z^2))
+    vec2 a = clog(cmul(vec2(0.0, 1.0), value) + csqrt(vec2(1.0, 0.0) - cmul(value, value)));
+    return vec2(a.y, -a.x);
+}
+vec2 cacos(vec2 value) {
+    // -i * log(z + i * sqrt(1 - z^2))
+    vec2 a = clog(value + cmul(vec2(0.0, 1.0), csqrt(vec2(1.0, 0.0) - cmul(value, value))));
+    return vec2(a.y, -a.x);
+}
+vec2 catan(vec2 value) {
+    // i / 2 * log((i + z) / (i - z))
+    vec2 a = clog(cdiv(vec2(0.0, 1.0) + value, vec2(0.0, 1.0) - value));
+    return vec2(-a.y, a.x) * 0.5;
+}
+vec2 cgamma_lanczos(vec2 value) {
+    const float coefficients[9] = float[9](
+        0.99999999999980993,
+        676.5203681218851,
+        -1259.1392167224028,
+        771.32342877765313,
+        -176.61502916214059,
+        12.507343278686905,
+        -0.13857109526572012,
+        9.9843695780195716e-6,
+        1.5056327351493116e-7
+    );
+    vec2 z = value - vec2(1.0, 0.0);
+    vec2 x = vec2(coefficients[0], 0.0);
+    for (int i = 1; i < 9; i++) {
+        x += cdiv(vec2(coefficients[i], 0.0), z + vec2(float(i), 0.0));
+    }
+    vec2 t = z + vec2(7.5, 0.0);
+    return sqrt(2.0 * pi) * cmul(cmul(cpow(t, z + vec2(0.5, 0.0)), cexp(-t)), x);
+}
+vec2 cgamma(vec2 value) {
+    // Reflection formula for the left half plane, pi / (sin(pi z) * gamma(1 - z))
+    if (value.x < 0.5) {
+        return cdiv(vec2(pi, 0.0), cmul(csin(pi * value), cgamma_lanczos(vec2(1.0, 0.0) - value)));
+    }
+    return cgamma_lanczos(value);
+}
+vec2 catan2(vec2 y, vec2 x) {
+    // -i * log((x + iy) / sqrt(x^2 + y^2))
+    vec2 a = clog(cdiv(x + cmul(vec2(0.0, 1.0), y), csqrt(cmul(x, x) + cmul(y, y))));
+    return vec2(a.y, -a.x);
+}
 
 
 
@@ -236,6 +285,30 @@ const int NodeSyntaxMultiplication = 7;
 const int NodeSyntaxDivision       = 8;
 const int NodeSyntaxExponent       = 9;
 const int NodeSyntaxAbsolute       = 10;
+const int NodeSyntaxArgument       = 11;
+const int NodeSyntaxBracket        = 12;
+const int NodeSyntaxBrace          = 13;
+const int NodeSyntaxFloor          = 14;
+const int NodeSyntaxCeil           = 15;
+const int NodeSyntaxNorm           = 16;
+const int NodeSyntaxLess           = 17;
+const int NodeSyntaxGreater        = 18;
+const int NodeSyntaxLessEqual      = 19;
+const int NodeSyntaxGreaterEqual   = 20;
+const int NodeSyntaxEqual          = 21;
+const int NodeSyntaxNotEqual       = 22;
+const int NodeSyntaxAnd            = 23;
+const int NodeSyntaxOr             = 24;
+const int NodeSyntaxNot            = 25;
+const int NodeSyntaxCondition      = 26;
+const int NodeSyntaxFactorial      = 27;
+const int NodeSyntaxConjugate      = 28;
+const int NodeSyntaxDegree         = 29;
+const int NodeSyntaxPercent        = 30;
+const int NodeSyntaxSum            = 31;
+const int NodeSyntaxProduct        = 32;
+const int NodeSyntaxIterate        = 33;
+const int NodeSyntaxBound          = 34;
 
 // Vars
 const int NodeVariableTime  = 0;
@@ -273,10 +346,18 @@ const int NodeFunctionRound = 8;
 const int NodeFunctionCeil  = 9;
 const int NodeFunctionFloor = 10;
 const int NodeFunctionRand  = 11;
+const int NodeFunctionPow   = 12;
+const int NodeFunctionLogb  = 13;
+const int NodeFunctionLerp  = 14;
+const int NodeFunctionAtan2 = 15;
+const int NodeFunctionAsin  = 16;
+const int NodeFunctionAcos  = 17;
+const int NodeFunctionAtan  = 18;
 
 
 // 16 bytes total size per node
-const int max_nodes = 50;
+// MAX_NODES, MAX_ROOTS and MAX_ITERATIONS are defined by math::with_limits when the shader is attached.
+const int max_nodes = MAX_NODES;
 struct NodeData {
     // 16 bit hi: right_index
     // 16 bit lo: left_index
@@ -290,9 +371,13 @@ struct NodeData {
 };
 
 uniform int nodes_count;
+// Upper bound for the number of iterations of a single loop.
+uniform int loop_limit;
 layout(std140) uniform NodesBlock {
     NodeData nodes[max_nodes];
 };
+// Value of the variable z, the point of the pixel or the current point of an iteration.
+vec2 tree_z;
 
 
 vec2 evaluate_tree_variable(int variable) {
@@ -302,13 +387,13 @@ vec2 evaluate_tree_variable(int variable) {
             break;
 
         case NodeVariableZ:
-            return (f_uv - 0.5) / zoom + view_offset;
+            return tree_z;
             break;
         case NodeVariableZr:
-            return vec2((f_uv.x - 0.5) / zoom.x + view_offset.x, 0.0);
+            return vec2(tree_z.x, 0.0);
             break;
         case NodeVariableZi:
-            return vec2(0.0, (f_uv.y - 0.5) / zoom.y + view_offset.y);
+            return vec2(0.0, tree_z.y);
             break;
 
         case NodeVariableUv:
@@ -352,7 +437,8 @@ vec2 evaluate_tree_variable(int variable) {
             break;
     }
 }
-vec2 evaluate_tree_function(int function, vec2 value) {
+// Unused arguments are zero.
+vec2 evaluate_tree_function(int function, vec2 value, vec2 second, vec2 third) {
     switch (function) {
         case NodeFunctionSin:
             return csin(value);
@@ -393,20 +479,52 @@ vec2 evaluate_tree_function(int function, vec2 value) {
         case NodeFunctionRand:
             return crand(value);
             break;
+
+        case NodeFunctionPow:
+            return cpow(value, second);
+            break;
+        case NodeFunctionLogb:
+            return cdiv(clog(value), clog(second));
+            break;
+        case NodeFunctionLerp:
+            return value + cmul(second - value, third);
+            break;
+        case NodeFunctionAtan2:
+            return catan2(value, second);
+            break;
+        case NodeFunctionAsin:
+            return casin(value);
+            break;
+        case NodeFunctionAcos:
+            return cacos(value);
+            break;
+        case NodeFunctionAtan:
+            return catan(value);
+            break;
     }
 }
 
 NodeData nodes_buffer[max_nodes];
-vec2 evaluate_tree() {
+// Value of the name a loop binds and the number of finished iterations, -1 if it isn't running.
+vec2 loop_value[max_nodes];
+int loop_count[max_nodes];
+void load_tree() {
     for (int index = 0; index < nodes_count; index++) {
         nodes_buffer[index] = nodes[index];
+        loop_count[index] = -1;
     }
+}
+// Evaluates the tree with its root at first, which ends right before end.
+// The buffer can hold several trees, indices are counted from the start of the buffer.
+// Children always come after their parents, a subtree with several parents is stored and evaluated once.
+vec2 evaluate_tree(int first, int end, vec2 z) {
+    tree_z = z;
 
     // For some reason index has to be an int.
     // If it's an uint, then this loop will never exit.
     // Also uint(0xffff0000) does not equal what you think
     // so instead of (x & 0xffff0000) >> 16 do (x >> 16) & 0x0000ffff
-    for(int index = nodes_count - 1; index >= 0; index--) {
+    for(int index = end - 1; index >= first; index--) {
         int left = lo16(int(nodes_buffer[index].indices));
         int right = hi16(int(nodes_buffer[index].indices));
 
@@ -420,8 +538,76 @@ vec2 evaluate_tree() {
             case NodeSyntaxVariable: 
                 nodes_buffer[index].value = evaluate_tree_variable(hi16(id));
                 break; 
-            case NodeSyntaxFunction:
-                nodes_buffer[index].value = evaluate_tree_function(hi16(id), nodes_buffer[left].value);
+            case NodeSyntaxBound:
+                nodes_buffer[index].value = loop_value[hi16(id)];
+                break;
+
+            // The body is the last node of the subtree of a loop, so it's evaluated right before the loop.
+            // The first time it's evaluated before the loop started, so that value is ignored.
+            // Every iteration jumps back to the end of the subtree, which evaluates the body again.
+            case NodeSyntaxSum:
+            case NodeSyntaxProduct:
+            case NodeSyntaxIterate: {
+                int syntax = lo16(id);
+                int body = hi16(int(nodes_buffer[right].indices));
+                vec2 first = nodes_buffer[left].value;
+                vec2 second = nodes_buffer[right].value;
+                int total = syntax == NodeSyntaxIterate
+                    ? int(round(first.x))
+                    : int(round(second.x) - round(first.x)) + 1;
+                total = min(total, loop_limit);
+
+                int count = loop_count[index];
+                if (count < 0) {
+                    if (syntax == NodeSyntaxSum) {
+                        nodes_buffer[index].value = vec2(0.0, 0.0);
+                        loop_value[index] = vec2(round(first.x), 0.0);
+                    } else if (syntax == NodeSyntaxProduct) {
+                        nodes_buffer[index].value = vec2(1.0, 0.0);
+                        loop_value[index] = vec2(round(first.x), 0.0);
+                    } else {
+                        nodes_buffer[index].value = second;
+                        loop_value[index] = second;
+                    }
+                } else {
+                    vec2 value = nodes_buffer[body].value;
+                    if (syntax == NodeSyntaxSum) {
+                        nodes_buffer[index].value += value;
+                        loop_value[index].x += 1.0;
+                    } else if (syntax == NodeSyntaxProduct) {
+                        nodes_buffer[index].value = cmul(nodes_buffer[index].value, value);
+                        loop_value[index].x += 1.0;
+                    } else {
+                        nodes_buffer[index].value = value;
+                        loop_value[index] = value;
+                    }
+                }
+                loop_count[index] = count + 1;
+
+                if (loop_count[index] < total) {
+                    index = hi16(id);
+                } else {
+                    loop_count[index] = -1;
+                }
+                break;
+            }
+            case NodeSyntaxFunction: {
+                // Arguments after the first are chained through the right child.
+                // Index 0 is the first root, so it never refers to an argument.
+                vec2 second = vec2(0.0);
+                vec2 third = vec2(0.0);
+                if (right > 0) {
+                    second = nodes_buffer[right].value;
+                    int next = hi16(int(nodes_buffer[right].indices));
+                    if (next > 0) {
+                        third = nodes_buffer[next].value;
+                    }
+                }
+                nodes_buffer[index].value = evaluate_tree_function(hi16(id), nodes_buffer[left].value, second, third);
+                break;
+            }
+            case NodeSyntaxArgument:
+                nodes_buffer[index].value = nodes_buffer[left].value;
                 break;
 
             case NodeSyntaxParenthesis:
@@ -430,6 +616,19 @@ vec2 evaluate_tree() {
             case NodeSyntaxAbsolute:
                 nodes_buffer[index].value = vec2(cabs(nodes_buffer[left].value), 0.0);
                 break;
+            case NodeSyntaxBracket:
+            case NodeSyntaxBrace:
+                nodes_buffer[index].value = nodes_buffer[left].value;
+                break;
+            case NodeSyntaxFloor:
+                nodes_buffer[index].value = floor(nodes_buffer[left].value);
+                break;
+            case NodeSyntaxCeil:
+                nodes_buffer[index].value = ceil(nodes_buffer[left].value);
+                break;
+            case NodeSyntaxNorm:
+                nodes_buffer[index].value = vec2(cabs(nodes_buffer[left].value), 0.0);
+                break;
 
             case NodeSyntaxAddition:
                 nodes_buffer[index].value = nodes_buffer[left].value + nodes_buffer[right].value;
@@ -446,9 +645,60 @@ vec2 evaluate_tree() {
             case NodeSyntaxExponent:
                 nodes_buffer[index].value = cpow(nodes_buffer[left].value, nodes_buffer[right].value);
                 break;
+
+            // Conditions are stored as 1.0 or 0.0 in x, comparisons use the real part.
+            case NodeSyntaxLess:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x < nodes_buffer[right].value.x), 0.0);
+                break;
+            case NodeSyntaxGreater:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x > nodes_buffer[right].value.x), 0.0);
+                break;
+            case NodeSyntaxLessEqual:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x <= nodes_buffer[right].value.x), 0.0);
+                break;
+            case NodeSyntaxGreaterEqual:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x >= nodes_buffer[right].value.x), 0.0);
+                break;
+            case NodeSyntaxEqual:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value == nodes_buffer[right].value), 0.0);
+                break;
+            case NodeSyntaxNotEqual:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value != nodes_buffer[right].value), 0.0);
+                break;
+            case NodeSyntaxAnd:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x != 0.0 && nodes_buffer[right].value.x != 0.0), 0.0);
+                break;
+            case NodeSyntaxOr:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x != 0.0 || nodes_buffer[right].value.x != 0.0), 0.0);
+                break;
+            case NodeSyntaxNot:
+                nodes_buffer[index].value = vec2(float(nodes_buffer[left].value.x == 0.0), 0.0);
+                break;
+            case NodeSyntaxFactorial:
+                nodes_buffer[index].value = cgamma(nodes_buffer[left].value + vec2(1.0, 0.0));
+                break;
+            case NodeSyntaxConjugate:
+                nodes_buffer[index].value = vec2(nodes_buffer[left].value.x, -nodes_buffer[left].value.y);
+                break;
+            case NodeSyntaxDegree:
+                nodes_buffer[index].value = nodes_buffer[left].value * (pi / 180.0);
+                break;
+            case NodeSyntaxPercent:
+                nodes_buffer[index].value = nodes_buffer[left].value / 100.0;
+                break;
+            case NodeSyntaxCondition: {
+                // Both branches are already evaluated, the right child chains them like arguments.
+                int alternative = hi16(int(nodes_buffer[right].indices));
+                if (nodes_buffer[left].value.x != 0.0) {
+                    nodes_buffer[index].value = nodes_buffer[right].value;
+                } else {
+                    nodes_buffer[index].value = nodes_buffer[alternative].value;
+                }
+                break;
+            }
         }
     }
-    return nodes_buffer[0].value;
+    return nodes_buffer[first].value;
 }
 
 
@@ -545,8 +795,83 @@ vec3 render_grid(vec2 point, vec3 input_color) {
 
 
 
+// -----------------------------------------------------------------
+// Newton fractal
+//
+
+const int RenderModeMap     = 0;
+const int RenderModeNewton  = 1;
+uniform int render_mode;
+const int max_iterations = MAX_ITERATIONS;
+
+// The buffer holds p followed by p', which starts at newton_derivative.
+// Roots are found on the CPU, see math::Newton.
+const int max_roots = MAX_ROOTS;
+uniform int newton_derivative;
+uniform float newton_relaxation;
+uniform float newton_tolerance;
+uniform vec2 newton_roots[max_roots];
+uniform int newton_roots_count;
+
+vec3 hsv_to_rgb(vec3 hsv) {
+    vec3 rgb = clamp(abs(mod(hsv.x * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+    return hsv.z * mix(vec3(1.0), rgb, hsv.y);
+}
+// Same iteration as Newton::iterate, the hue is the root and slower points are darker.
+// Points that don't converge are black, the ones that converge to something else are grey.
+vec3 render_newton(vec2 z) {
+    int iterations = -1;
+    for (int iteration = 0; iteration < max_iterations; iteration++) {
+        vec2 delta = newton_relaxation * cdiv(
+            evaluate_tree(0, newton_derivative, z),
+            evaluate_tree(newton_derivative, nodes_count, z)
+        );
+        if (any(isnan(delta)) || any(isinf(delta))) {
+            break;
+        }
+        z -= delta;
+        if (length(delta) < newton_tolerance) {
+            iterations = iteration + 1;
+            break;
+        }
+    }
+    if (iterations < 0) {
+        return vec3(0.0);
+    }
+
+    int root = -1;
+    float nearest = sqrt(newton_tolerance);
+    for (int index = 0; index < newton_roots_count; index++) {
+        float gap = length(z - newton_roots[index]);
+        if (gap < nearest) {
+            nearest = gap;
+            root = index;
+        }
+    }
+    float shade = 1.0 - 0.8 * sqrt(float(iterations) / float(max_iterations));
+    if (root < 0) {
+        return vec3(0.5 * shade);
+    }
+    return hsv_to_rgb(vec3(float(root) / float(newton_roots_count), 0.75, shade));
+}
+
+
+
+
+
+
+
+
 void main() {
-    vec2 sample_point = evaluate_tree();
+    load_tree();
+    vec2 pixel = (f_uv - 0.5) / zoom + view_offset;
+    if (render_mode == RenderModeNewton) {
+        vec3 newton = render_newton(pixel);
+        color = vec4(render_grid(pixel, newton), 1.0);
+        return;
+    }
+
+    vec2 sample_point = evaluate_tree(0, nodes_count, pixel);
     
     if (sample_point.x < -0.5 || sample_point.y < -0.5 ||
         sample_point.x > 0.5 || sample_point.y > 0.5
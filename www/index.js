@@ -4,4 +4,12 @@ import("../pkg").then(application => {
     app.init();
 
     window.eval_rs = application.eval_rs;
+    window.source_rs = application.source_rs;
+    window.latex_rs = application.latex_rs;
+    window.mathml_rs = application.mathml_rs;
+    window.derivative_rs = application.derivative_rs;
+    window.highlight_rs = application.highlight_rs;
+    window.complete_rs = application.complete_rs;
+    window.newton_rs = application.newton_rs;
+    window.newton_point_rs = application.newton_point_rs;
 }).catch(e => console.error("error executing wasm: ", e));